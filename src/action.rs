@@ -1,9 +1,150 @@
+use std::any::Any;
+use std::error;
+use std::fmt;
 use super::EdBuffer;
-use super::program::{Program, Source};
+use super::program::{Program, Source, Instruction};
+use super::path::ProgramPath;
+use super::tags::{Tags, TagType};
+use super::schedule::Schedule;
+use super::error::Error;
 
+#[derive(Debug)]
+pub enum ActionError {
+    /// The buffer is not in the state the action expected, usually because
+    /// it was changed behind the history's back.
+    Mismatch(String),
+    /// As `Mismatch`, found by the schedule, with the error it gave.
+    ScheduleMismatch(Box<Error>),
+    /// The action refers to a program or instruction that does not exist.
+    NotFound(String),
+    /// The edit was refused.
+    Rejected(String),
+    /// The schedule or the tags refused the edit, with the error they gave.
+    ScheduleRejected(Box<Error>),
+    /// Replaying the history no longer produces the buffer's schedule.
+    HistoryDiverged
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ActionError::Mismatch(ref x) => try!(write!(fmt, "The buffer does not match the history: {}", x)),
+            ActionError::ScheduleMismatch(ref f) => try!(write!(fmt, "The buffer does not match the history: {}", f)),
+            ActionError::NotFound(ref x) => try!(write!(fmt, "{}", x)),
+            ActionError::Rejected(ref x) => try!(write!(fmt, "{}", x)),
+            ActionError::ScheduleRejected(ref f) => try!(write!(fmt, "{}", f)),
+            ActionError::HistoryDiverged => try!(write!(fmt, "The undo history no longer matches the buffer"))
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ActionError {
+    fn description(&self) -> &str {
+        match *self {
+            ActionError::Mismatch(_) | ActionError::ScheduleMismatch(_) => "buffer does not match the history",
+            ActionError::NotFound(ref x) => x,
+            ActionError::Rejected(ref x) => x,
+            ActionError::ScheduleRejected(ref f) => f.description(),
+            ActionError::HistoryDiverged => "history diverged"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ActionError::ScheduleMismatch(ref f) | ActionError::ScheduleRejected(ref f) => Some(&**f),
+            _ => None
+        }
+    }
+}
+
+impl ActionError {
+    fn mismatch(err: Error) -> ActionError {
+        ActionError::ScheduleMismatch(Box::new(err))
+    }
+
+    pub fn rejected<E: Into<Error>>(err: E) -> ActionError {
+        ActionError::ScheduleRejected(Box::new(err.into()))
+    }
+}
+
+pub type ActionResult = Result<(), ActionError>;
+
+/// Fails with a mismatch unless `cond` holds.
+fn expect(cond: bool, what: &str) -> ActionResult {
+    if cond {
+        Ok(())
+    } else {
+        Err(ActionError::Mismatch(what.to_string()))
+    }
+}
+
+/// An undoable edit. Both directions check the buffer before changing it, and
+/// leave it untouched when they fail.
 pub trait Action {
-    fn apply(&self, buffer: &mut super::gui::EdBuffer) -> Result<(), String>;
-    fn reverse(&self, buffer: &mut super::gui::EdBuffer) -> Result<(), String>;
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult;
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult;
+
+    /// A short description of the edit for the history list.
+    fn label(&self) -> String;
+
+    /// Actions that can merge expose themselves here so `merge` can downcast
+    /// the action that follows them.
+    fn as_any(&self) -> Option<&Any> {
+        None
+    }
+
+    /// A single action doing both this and `next`, which is applied right
+    /// after this one, if the two can be undone as one step.
+    fn merge(&self, _next: &Action) -> Option<Box<Action>> {
+        None
+    }
+}
+
+/// Several actions applied as one. If any of them fails, the ones already
+/// done are reversed before the error is returned.
+pub struct CompoundAction {
+    label: String,
+    actions: Vec<Box<Action>>
+}
+
+impl CompoundAction {
+    pub fn new(label: &str, actions: Vec<Box<Action>>) -> Box<Action> {
+        Box::new(CompoundAction {
+            label: label.to_string(),
+            actions: actions
+        })
+    }
+}
+
+impl Action for CompoundAction {
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        for (idx, action) in self.actions.iter().enumerate() {
+            if let Err(f) = action.apply(buffer) {
+                for done in self.actions[..idx].iter().rev() {
+                    let _ = done.reverse(buffer);
+                }
+                return Err(f)
+            }
+        }
+        Ok(())
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        for (idx, action) in self.actions.iter().enumerate().rev() {
+            if let Err(f) = action.reverse(buffer) {
+                for done in self.actions[idx + 1 ..].iter() {
+                    let _ = done.apply(buffer);
+                }
+                return Err(f)
+            }
+        }
+        Ok(())
+    }
+
+    fn label(&self) -> String {
+        self.label.clone()
+    }
 }
 
 //Schedule methods
@@ -14,11 +155,32 @@ pub struct SetName {
 }
 
 impl Action for SetName {
-    fn apply(&self, buffer: &mut super::gui::EdBuffer) -> Result<(), String> {
+    fn label(&self) -> String {
+        format!("Rename schedule to \"{}\"", self.new)
+    }
+
+    fn as_any(&self) -> Option<&Any> {
+        Some(self)
+    }
+
+    /// Consecutive renames merge, so typing a name undoes in one step.
+    fn merge(&self, next: &Action) -> Option<Box<Action>> {
+        match next.as_any().and_then(|x| x.downcast_ref::<SetName>()) {
+            Some(rename) if rename.old == self.new => Some(Box::new(SetName {
+                old: self.old.clone(),
+                new: rename.new.clone()
+            })),
+            _ => None
+        }
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(buffer.get_name() == self.old, "the schedule name has changed"));
         buffer.set_name(&self.new);
         Ok(())
     }
-    fn reverse(&self, buffer: &mut super::gui::EdBuffer) -> Result<(), String> {
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(buffer.get_name() == self.new, "the schedule name has changed"));
         buffer.set_name(&self.old);
         Ok(())
     }
@@ -34,6 +196,71 @@ impl SetName {
     }
 }
 
+/// Replaces the whole schedule, as when edits are recovered from a journal.
+pub struct SetSchedule {
+    old: Schedule,
+    new: Schedule,
+    label: String
+}
+
+impl SetSchedule {
+    pub fn new(buffer: &EdBuffer, sched: &Schedule, label: &str) -> Box<Action> {
+        Box::new(SetSchedule {
+            old: buffer.get_schedule().clone(),
+            new: sched.clone(),
+            label: label.to_string()
+        })
+    }
+}
+
+impl Action for SetSchedule {
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(*buffer.get_schedule() == self.old, "the schedule has changed"));
+        buffer.set_schedule(&self.new);
+        Ok(())
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(*buffer.get_schedule() == self.new, "the schedule has changed"));
+        buffer.set_schedule(&self.old);
+        Ok(())
+    }
+}
+
+/// Replaces the tags every program of the schedule inherits.
+pub struct SetDefaults {
+    old: Tags,
+    new: Tags
+}
+
+impl SetDefaults {
+    pub fn new(buffer: &EdBuffer, tags: &Tags) -> Box<Action> {
+        Box::new(SetDefaults { old: buffer.defaults().clone(), new: tags.clone() })
+    }
+}
+
+impl Action for SetDefaults {
+    fn label(&self) -> String {
+        "Change default tags".to_string()
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(*buffer.defaults() == self.old, "the default tags have changed"));
+        buffer.set_defaults(&self.new);
+        Ok(())
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(*buffer.defaults() == self.new, "the default tags have changed"));
+        buffer.set_defaults(&self.old);
+        Ok(())
+    }
+}
+
 pub struct AddProgram {
     program: Program,
 }
@@ -45,17 +272,18 @@ impl AddProgram {
 }
 
 impl Action for AddProgram {
-    fn apply(&self, buffer: &mut EdBuffer) -> Result<(), String> {
+    fn label(&self) -> String {
+        "Add program".to_string()
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
         buffer.add_program(&self.program);
         Ok(())
     }
 
-    fn reverse(&self, buffer: &mut EdBuffer) -> Result<(), String> {
-        if let Some(x) =  buffer.pop_program() {
-            if x != self.program {
-                panic!("Modification made after undo without clearing redo buffer!")
-            }
-        }
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(buffer.last_program() == Some(&self.program), "the last program is not the one added"));
+        buffer.pop_program();
         Ok(())
     }
 }
@@ -65,25 +293,26 @@ pub struct PopProgram {
 }
 
 impl PopProgram {
-    pub fn new(buffer: &EdBuffer) -> Result<Box<Action>, String> {
+    pub fn new(buffer: &EdBuffer) -> Result<Box<Action>, ActionError> {
         match buffer.last_program() {
             Some(p) => Ok(Box::new(PopProgram { program: p.clone() })),
-            None =>  Err("Can not pop an empty program".to_string())
+            None =>  Err(ActionError::NotFound("Can not pop an empty program".to_string()))
         }
     }
 }
 
 impl Action for PopProgram {
-    fn apply(&self, buffer: &mut EdBuffer) -> Result<(), String> {
-        if let Some(x) = buffer.pop_program() {
-            if x != self.program {
-                panic!("Modification made after undo without clearing redo buffer!")
-            }
-        }
+    fn label(&self) -> String {
+        "Remove last program".to_string()
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(buffer.last_program() == Some(&self.program), "the last program is not the one to pop"));
+        buffer.pop_program();
         Ok(())
     }
 
-    fn reverse(&self, buffer: &mut EdBuffer) -> Result<(), String> {
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
         buffer.add_program(&self.program);
         Ok(())
     }
@@ -104,15 +333,18 @@ impl InsertProgram {
 }
 
 impl Action for InsertProgram {
-    fn apply(&self, buffer: &mut EdBuffer) -> Result<(), String> {
-        buffer.insert_program(self.index, &self.program)
+    fn label(&self) -> String {
+        format!("Insert program at {}", self.index)
     }
 
-    fn reverse(&self, buffer: &mut EdBuffer) -> Result<(), String> {
-        if let Err(f) = buffer.delete_program(self.index) {
-            panic!(format!("Modifications made after undo. Error: {}", f))
-        }
-        Ok(())
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        buffer.insert_program(self.index, &self.program).map_err(ActionError::rejected)
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(buffer.get_program_at(self.index) == Some(&self.program),
+                    "the inserted program is no longer in place"));
+        buffer.delete_program(self.index).map_err(ActionError::rejected)
     }
 }
 
@@ -122,56 +354,568 @@ pub struct DeleteProgram {
 }
 
 impl DeleteProgram {
-    pub fn new(buffer: &EdBuffer, idx: usize) -> Result<Box<Action>, String> {
+    pub fn new(buffer: &EdBuffer, idx: usize) -> Result<Box<Action>, ActionError> {
         match buffer.get_program_at(idx) {
             Some(p) => Ok(Box::new(DeleteProgram { program: p.clone(), index: idx })),
-            None => Err(format!("Can not delete program at {} because it does not exist", idx))
+            None => Err(ActionError::NotFound(format!("Can not delete program at {} because it does not exist", idx)))
         }
     }
 }
 
 impl Action for DeleteProgram {
-    fn apply(&self, buffer: &mut EdBuffer) -> Result<(), String> {
-        buffer.delete_program(self.index)
+    fn label(&self) -> String {
+        format!("Delete program {}", self.index)
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(expect(buffer.get_program_at(self.index) == Some(&self.program),
+                    "the program to delete is no longer in place"));
+        buffer.delete_program(self.index).map_err(ActionError::rejected)
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        buffer.insert_program(self.index, &self.program).map_err(ActionError::rejected)
     }
+}
 
-    fn reverse(&self, buffer: &mut EdBuffer) -> Result<(), String> {
-        if let Err(f) = buffer.insert_program(self.index, &self.program) {
-            panic!(format!("Modifications made after undo. Error: {}", f))
+pub struct MoveProgram {
+    from: usize,
+    to: usize
+}
+
+impl MoveProgram {
+    pub fn new(buffer: &EdBuffer, from: usize, to: usize) -> Result<Box<Action>, ActionError> {
+        let len = buffer.get_schedule().programs_len();
+        if from >= len || to >= len {
+            Err(ActionError::NotFound(format!("Can not move program {} to {} in a schedule of {} programs",
+                                              from, to, len)))
+        } else {
+            Ok(Box::new(MoveProgram { from: from, to: to }))
         }
-        Ok(())
+    }
+}
+
+impl Action for MoveProgram {
+    fn label(&self) -> String {
+        format!("Move program {} to {}", self.from, self.to)
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        buffer.move_program(self.from, self.to).map_err(ActionError::mismatch)
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        buffer.move_program(self.to, self.from).map_err(ActionError::mismatch)
     }
 }
 
 //Program methods
+
+fn program_at<'a>(buffer: &'a EdBuffer, path: &ProgramPath) -> Result<&'a Program, ActionError> {
+    match buffer.get_program_at_path(path) {
+        Some(prog) => Ok(prog),
+        None => Err(ActionError::NotFound(format!("There is no program at {}", path)))
+    }
+}
+
+fn program_at_mut<'a>(buffer: &'a mut EdBuffer, path: &ProgramPath) -> Result<&'a mut Program, ActionError> {
+    match buffer.get_program_at_path_mut(path) {
+        Some(prog) => Ok(prog),
+        None => Err(ActionError::Mismatch(format!("There is no program at {}", path)))
+    }
+}
+
 pub struct SetSource {
     old: Source,
     new: Source,
-    index: usize
+    path: ProgramPath
 }
 
 impl SetSource {
-    pub fn new(buffer: &EdBuffer, novo: &Source) -> Result<Box<Action>, String> {
-        if let Some(ref prog) = buffer.get_program() {
-            Ok(Box::new(SetSource { 
-                old: prog.get_location().clone(), 
+    pub fn new(buffer: &EdBuffer, path: &ProgramPath, novo: &Source) -> Result<Box<Action>, ActionError> {
+        let prog = try!(program_at(buffer, path));
+        Ok(Box::new(SetSource {
+            old: prog.get_location().clone(),
+            new: novo.clone(),
+            path: path.clone()
+        }))
+    }
+}
+
+impl Action for SetSource {
+    fn label(&self) -> String {
+        "Change source".to_string()
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(*prog.get_location() == self.old, "the source has changed"));
+        prog.set_location(&self.new);
+        Ok(())
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(*prog.get_location() == self.new, "the source has changed"));
+        prog.set_location(&self.old);
+        Ok(())
+    }
+}
+
+pub struct PushInstruction {
+    instr: Instruction,
+    path: ProgramPath
+}
+
+impl PushInstruction {
+    pub fn new(buffer: &EdBuffer, path: &ProgramPath, instr: &Instruction) -> Result<Box<Action>, ActionError> {
+        try!(program_at(buffer, path));
+        Ok(Box::new(PushInstruction { instr: instr.clone(), path: path.clone() }))
+    }
+}
+
+impl Action for PushInstruction {
+    fn label(&self) -> String {
+        "Add instruction".to_string()
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(program_at_mut(buffer, &self.path)).push_instruction(&self.instr);
+        Ok(())
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(prog.get_instrs().last() == Some(&self.instr), "the last instruction is not the one pushed"));
+        prog.pop_instruction();
+        Ok(())
+    }
+}
+
+pub struct PopInstruction {
+    instr: Instruction,
+    cursor: Option<usize>,
+    path: ProgramPath
+}
+
+impl PopInstruction {
+    pub fn new(buffer: &EdBuffer, path: &ProgramPath) -> Result<Box<Action>, ActionError> {
+        let prog = try!(program_at(buffer, path));
+        match prog.get_instrs().last() {
+            Some(instr) => Ok(Box::new(PopInstruction {
+                instr: instr.clone(),
+                cursor: prog.get_current_instr_idx(),
+                path: path.clone()
+            })),
+            None => Err(ActionError::NotFound("Can not pop from a program without instructions".to_string()))
+        }
+    }
+}
+
+impl Action for PopInstruction {
+    fn label(&self) -> String {
+        "Remove last instruction".to_string()
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(prog.get_instrs().last() == Some(&self.instr), "the last instruction is not the one to pop"));
+        prog.pop_instruction();
+        Ok(())
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        prog.push_instruction(&self.instr);
+        if let Some(idx) = self.cursor {
+            try!(prog.set_current_instr_idx(idx).map_err(ActionError::rejected));
+        }
+        Ok(())
+    }
+}
+
+pub struct InsertInstruction {
+    instr: Instruction,
+    index: usize,
+    path: ProgramPath
+}
+
+impl InsertInstruction {
+    pub fn new(buffer: &EdBuffer, path: &ProgramPath, idx: usize, instr: &Instruction)
+               -> Result<Box<Action>, ActionError> {
+        if idx > try!(program_at(buffer, path)).get_instrs().len() {
+            return Err(ActionError::NotFound(format!("Can not insert an instruction at {}", idx)))
+        }
+        Ok(Box::new(InsertInstruction { instr: instr.clone(), index: idx, path: path.clone() }))
+    }
+}
+
+impl Action for InsertInstruction {
+    fn label(&self) -> String {
+        format!("Insert instruction at {}", self.index)
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(program_at_mut(buffer, &self.path)).insert_instruction(self.index, &self.instr)
+            .map_err(ActionError::mismatch)
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(prog.get_instr_at(self.index) == Some(&self.instr),
+                    "the inserted instruction is no longer in place"));
+        try!(prog.remove_instruction(self.index).map_err(ActionError::mismatch));
+        Ok(())
+    }
+}
+
+pub struct RemoveInstruction {
+    instr: Instruction,
+    index: usize,
+    cursor: Option<usize>,
+    path: ProgramPath
+}
+
+impl RemoveInstruction {
+    pub fn new(buffer: &EdBuffer, path: &ProgramPath, idx: usize) -> Result<Box<Action>, ActionError> {
+        let prog = try!(program_at(buffer, path));
+        match prog.get_instr_at(idx) {
+            Some(instr) => Ok(Box::new(RemoveInstruction {
+                instr: instr.clone(),
+                index: idx,
+                cursor: prog.get_current_instr_idx(),
+                path: path.clone()
+            })),
+            None => Err(ActionError::NotFound(format!("Can not remove instruction {} because it does not exist", idx)))
+        }
+    }
+}
+
+impl Action for RemoveInstruction {
+    fn label(&self) -> String {
+        format!("Remove instruction {}", self.index)
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(prog.get_instr_at(self.index) == Some(&self.instr),
+                    "the instruction to remove is no longer in place"));
+        try!(prog.remove_instruction(self.index).map_err(ActionError::mismatch));
+        Ok(())
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(prog.insert_instruction(self.index, &self.instr).map_err(ActionError::mismatch));
+        if let Some(idx) = self.cursor {
+            try!(prog.set_current_instr_idx(idx).map_err(ActionError::rejected));
+        }
+        Ok(())
+    }
+}
+
+/// Replaces an instruction. Editing a subprogram as a whole goes through here;
+/// edits inside one are addressed with a longer path instead.
+pub struct SetInstruction {
+    old: Instruction,
+    new: Instruction,
+    index: usize,
+    path: ProgramPath
+}
+
+impl SetInstruction {
+    pub fn new(buffer: &EdBuffer, path: &ProgramPath, idx: usize, novo: &Instruction)
+               -> Result<Box<Action>, ActionError> {
+        match try!(program_at(buffer, path)).get_instr_at(idx) {
+            Some(instr) => Ok(Box::new(SetInstruction {
+                old: instr.clone(),
                 new: novo.clone(),
-                index: buffer.get_schedule().get_current_program_idx().unwrap()
-            }))
+                index: idx,
+                path: path.clone()
+            })),
+            None => Err(ActionError::NotFound(format!("Can not modify instruction {} because it does not exist", idx)))
+        }
+    }
+}
+
+impl Action for SetInstruction {
+    fn label(&self) -> String {
+        format!("Change instruction {}", self.index)
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(prog.get_instr_at(self.index) == Some(&self.old), "the instruction has changed"));
+        try!(prog.set_instruction(self.index, &self.new).map_err(ActionError::mismatch));
+        Ok(())
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(prog.get_instr_at(self.index) == Some(&self.new), "the instruction has changed"));
+        try!(prog.set_instruction(self.index, &self.old).map_err(ActionError::mismatch));
+        Ok(())
+    }
+}
+
+pub struct MoveInstruction {
+    from: usize,
+    to: usize,
+    path: ProgramPath
+}
+
+impl MoveInstruction {
+    pub fn new(buffer: &EdBuffer, path: &ProgramPath, from: usize, to: usize) -> Result<Box<Action>, ActionError> {
+        let len = try!(program_at(buffer, path)).get_instrs().len();
+        if from >= len || to >= len {
+            Err(ActionError::NotFound(format!("Can not move instruction {} to {} in a program of {} instructions",
+                                              from, to, len)))
         } else {
-            Err("No selected program".to_string())
+            Ok(Box::new(MoveInstruction { from: from, to: to, path: path.clone() }))
         }
     }
 }
 
-impl Action for SetSource {
-    fn apply(&self, buffer: &mut EdBuffer) -> Result<(), String> {
-        buffer.get_program_at_mut(self.index).unwrap().set_location(&self.new);
+impl Action for MoveInstruction {
+    fn label(&self) -> String {
+        format!("Move instruction {} to {}", self.from, self.to)
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(program_at_mut(buffer, &self.path)).move_instruction(self.from, self.to)
+            .map_err(ActionError::mismatch)
+    }
+
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        try!(program_at_mut(buffer, &self.path)).move_instruction(self.to, self.from)
+            .map_err(ActionError::mismatch)
+    }
+}
+
+//Tag methods
+
+/// Replaces the tags of a program. Every tag edit is recorded as one of these,
+/// so undoing it restores the tags exactly as they were.
+pub struct SetTags {
+    old: Tags,
+    new: Tags,
+    path: ProgramPath,
+    label: String
+}
+
+impl SetTags {
+    pub fn new(buffer: &EdBuffer, path: &ProgramPath, tags: &Tags) -> Result<Box<Action>, ActionError> {
+        SetTags::labeled(buffer, path, tags, "Change tags")
+    }
+
+    fn labeled(buffer: &EdBuffer, path: &ProgramPath, tags: &Tags, label: &str)
+               -> Result<Box<Action>, ActionError> {
+        let old = try!(program_at(buffer, path)).get_tags().clone();
+        Ok(Box::new(SetTags { old: old, new: tags.clone(), path: path.clone(), label: label.to_string() }))
+    }
+
+    /// Sets a single tag, as `Tags::modify_tag` does.
+    pub fn modify(buffer: &EdBuffer, path: &ProgramPath, tagtype: &TagType, tagdata: &str)
+                  -> Result<Box<Action>, ActionError> {
+        let mut tags = try!(program_at(buffer, path)).get_tags().clone();
+        if let Err(f) = tags.modify_tag(tagtype, tagdata) {
+            return Err(ActionError::rejected(f))
+        }
+        SetTags::new(buffer, path, &tags)
+    }
+
+    /// Sets a tag holding several values, as `Tags::modify_multi` does.
+    pub fn modify_multi(buffer: &EdBuffer, path: &ProgramPath, tagtype: TagType, tagdata: &Vec<String>)
+                        -> Result<Box<Action>, ActionError> {
+        let mut tags = try!(program_at(buffer, path)).get_tags().clone();
+        if let Err(f) = tags.modify_multi(tagdata, tagtype) {
+            return Err(ActionError::rejected(f))
+        }
+        SetTags::new(buffer, path, &tags)
+    }
+
+    /// Adds one value to a tag holding several, such as a genre.
+    pub fn add_value(buffer: &EdBuffer, path: &ProgramPath, tagtype: &TagType, value: &str)
+                     -> Result<Box<Action>, ActionError> {
+        let mut tags = try!(program_at(buffer, path)).get_tags().clone();
+        if let Err(f) = tags.add_value(tagtype, value) {
+            return Err(ActionError::rejected(f))
+        }
+        SetTags::labeled(buffer, path, &tags, &format!("Add {} {}", tagtype, value))
+    }
+
+    /// Removes one value from a tag holding several.
+    pub fn remove_value(buffer: &EdBuffer, path: &ProgramPath, tagtype: &TagType, value: &str)
+                        -> Result<Box<Action>, ActionError> {
+        let mut tags = try!(program_at(buffer, path)).get_tags().clone();
+        if let Err(f) = tags.remove_value(tagtype, value) {
+            return Err(ActionError::rejected(f))
+        }
+        SetTags::labeled(buffer, path, &tags, &format!("Remove {} {}", tagtype, value))
+    }
+
+    /// Unsets a single tag.
+    pub fn remove(buffer: &EdBuffer, path: &ProgramPath, tagtype: &TagType) -> Result<Box<Action>, ActionError> {
+        let mut tags = try!(program_at(buffer, path)).get_tags().clone();
+        tags.remove_tag(tagtype);
+        SetTags::new(buffer, path, &tags)
+    }
+
+    /// Removes every tag, as `Program::delete_all_tags` does.
+    pub fn clear(buffer: &EdBuffer, path: &ProgramPath) -> Result<Box<Action>, ActionError> {
+        SetTags::new(buffer, path, &Tags::new())
+    }
+}
+
+impl Action for SetTags {
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    fn apply(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(*prog.get_tags() == self.old, "the tags have changed"));
+        prog.set_tags(&self.new);
         Ok(())
     }
 
-    fn reverse(&self, buffer: &mut EdBuffer) -> Result<(), String> {
-        buffer.get_program_at_mut(self.index).unwrap().set_location(&self.old);
+    fn reverse(&self, buffer: &mut EdBuffer) -> ActionResult {
+        let prog = try!(program_at_mut(buffer, &self.path));
+        try!(expect(*prog.get_tags() == self.new, "the tags have changed"));
+        prog.set_tags(&self.old);
         Ok(())
     }
 }
+
+#[cfg(test)]
+fn nested_buffer() -> EdBuffer {
+    let sched = super::parse::parse("(schedule \"test\"
+ (program (local \"~/htpc/Videos/fsn.webm\") (tags :studio \"Studio Deen\")
+  (instr (play 00:00:01 00:10:00)
+         (program (network \"https://www.youtube.com/watch?v=foo\") (instr (play 00:01:00)))
+         (play 00:10:00)))
+ (program (local \"~/htpc/Music/Gorillaz/Punk.ogg\") (instr (play 00:00:00 00:00:00))))").unwrap();
+    EdBuffer::from_schedule(&sched)
+}
+
+#[test]
+fn apply_then_reverse_restores() {
+    let top = ProgramPath::new(0);
+    let sub = top.child(1);
+    let play = Instruction::Play(5, 5);
+    let other = Source::Pathname("other".to_string());
+    let buffer = nested_buffer();
+    let mut defaults = Tags::new();
+    defaults.series = Some("Fate/stay night".to_string());
+
+    let actions: Vec<Box<Action>> = vec!(
+        SetName::new(&buffer, "renamed"),
+        SetDefaults::new(&buffer, &defaults),
+        AddProgram::new(&Program::example()),
+        PopProgram::new(&buffer).unwrap(),
+        InsertProgram::new(&Program::example(), 1),
+        DeleteProgram::new(&buffer, 0).unwrap(),
+        MoveProgram::new(&buffer, 0, 1).unwrap(),
+        SetSource::new(&buffer, &sub, &other).unwrap(),
+        PushInstruction::new(&buffer, &sub, &play).unwrap(),
+        PopInstruction::new(&buffer, &top).unwrap(),
+        InsertInstruction::new(&buffer, &top, 1, &play).unwrap(),
+        RemoveInstruction::new(&buffer, &top, 2).unwrap(),
+        RemoveInstruction::new(&buffer, &sub, 0).unwrap(),
+        SetInstruction::new(&buffer, &top, 1, &play).unwrap(),
+        MoveInstruction::new(&buffer, &top, 0, 2).unwrap(),
+        SetTags::modify(&buffer, &sub, &TagType::Title, "Trailer").unwrap(),
+        SetTags::modify_multi(&buffer, &top, TagType::Cast, &vec!("Foo".to_string(), "Bar".to_string())).unwrap(),
+        SetTags::add_value(&buffer, &top, &TagType::Genre, "Anime").unwrap(),
+        SetTags::remove_value(&buffer, &top, &TagType::Studio, "Studio Deen").unwrap(),
+        SetTags::clear(&buffer, &top).unwrap());
+
+    for action in actions.iter() {
+        let mut buffer = nested_buffer();
+        let original = buffer.get_schedule().clone();
+        action.apply(&mut buffer).unwrap();
+        assert!(*buffer.get_schedule() != original);
+        action.reverse(&mut buffer).unwrap();
+        assert_eq!(*buffer.get_schedule(), original);
+    }
+
+    assert!(SetSource::new(&buffer, &top.child(0), &other).is_err());
+    match SetTags::modify(&buffer, &top, &TagType::Season, "first") {
+        Err(ActionError::ScheduleRejected(ref f)) => match **f {
+            Error::Parse(_) => { },
+            ref x => panic!("Expected a parse error, found {:?}", x)
+        },
+        _ => panic!("Expected the season to be rejected")
+    }
+    assert!(SetTags::add_value(&buffer, &top, &TagType::Title, "Fate").is_err());
+}
+
+#[test]
+fn mismatched_undo_is_an_error() {
+    let mut buffer = nested_buffer();
+    buffer.apply(AddProgram::new(&Program::example())).unwrap();
+    assert!(buffer.verify_history().is_ok());
+
+    // Change the buffer behind the history's back.
+    buffer.pop_program();
+    buffer.add_program(&Program::new(Source::Pathname("other".to_string()), Tags::new(), Vec::new()));
+    match buffer.verify_history() {
+        Err(ActionError::HistoryDiverged) => { },
+        x => panic!("Expected the history to diverge, found {:?}", x)
+    }
+
+    let before = buffer.get_schedule().clone();
+    match buffer.undo() {
+        Err(ActionError::Mismatch(_)) => { },
+        x => panic!("Expected a mismatch, found {:?}", x)
+    }
+    assert_eq!(*buffer.get_schedule(), before);
+    assert!(!buffer.is_undo_buffer_empty());
+
+    buffer.reset_history();
+    assert!(buffer.verify_history().is_ok());
+    assert!(buffer.undo().is_ok());
+    assert_eq!(*buffer.get_schedule(), before);
+}
+
+#[test]
+fn compound_rolls_back() {
+    let mut buffer = nested_buffer();
+    let original = buffer.get_schedule().clone();
+    let top = ProgramPath::new(0);
+
+    let retag = CompoundAction::new("Retag", vec!(
+        SetTags::modify(&buffer, &top, &TagType::Title, "Fate").unwrap(),
+        SetTags::modify(&buffer, &ProgramPath::new(1), &TagType::Title, "Punk").unwrap()));
+    buffer.apply(retag).unwrap();
+    assert_eq!(buffer.undo_labels(), vec!("Retag".to_string()));
+    buffer.undo().unwrap();
+    assert_eq!(*buffer.get_schedule(), original);
+
+    // The second delete no longer finds its program once the first has run.
+    let deletes = CompoundAction::new("Delete both", vec!(
+        DeleteProgram::new(&buffer, 0).unwrap(),
+        DeleteProgram::new(&buffer, 1).unwrap()));
+    assert!(deletes.apply(&mut buffer).is_err());
+    assert_eq!(*buffer.get_schedule(), original);
+}
+
+#[test]
+fn renames_coalesce() {
+    let mut buffer = nested_buffer();
+    for name in ["t", "te", "tes"].iter() {
+        let rename = SetName::new(&buffer, name);
+        buffer.apply(rename).unwrap();
+    }
+    buffer.apply(AddProgram::new(&Program::example())).unwrap();
+    let rename = SetName::new(&buffer, "other");
+    buffer.apply(rename).unwrap();
+
+    assert_eq!(buffer.undo_labels(), vec!("Rename schedule to \"tes\"".to_string(), "Add program".to_string(),
+                                          "Rename schedule to \"other\"".to_string()));
+    buffer.undo().unwrap();
+    buffer.undo().unwrap();
+    buffer.undo().unwrap();
+    assert_eq!(buffer.get_name(), "test");
+    assert_eq!(buffer.redo_labels().len(), 3);
+}
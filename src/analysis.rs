@@ -0,0 +1,260 @@
+//! Finds overlapping airings and dead air across channels.
+
+extern crate chrono;
+
+use std::fmt;
+use self::chrono::{Duration, NaiveDateTime};
+use super::channel::{Airing, Lineup, DATETIME_FORMAT};
+use super::schedule::Schedule;
+use super::program::Program;
+
+/// A schedule playing on a channel from a particular time.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Booking<'a> {
+    pub channel: u16,
+    pub start: NaiveDateTime,
+    pub schedule: &'a Schedule
+}
+
+impl<'a> Booking<'a> {
+    pub fn new(channel: u16, start: NaiveDateTime, sched: &'a Schedule) -> Booking<'a> {
+        Booking {
+            channel: channel,
+            start: start,
+            schedule: sched
+        }
+    }
+
+    /// When the schedule stops playing, using its computed running time.
+    pub fn end(&self) -> NaiveDateTime {
+        self.start + Duration::seconds(self.schedule.running_time() as i64)
+    }
+}
+
+/// Two bookings on the same channel that are on the air at the same time.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Conflict {
+    pub channel: u16,
+    pub first: String,
+    pub second: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "Channel {}: \"{}\" and \"{}\" overlap from {} to {}", self.channel,
+                    self.first, self.second, self.start.format(DATETIME_FORMAT),
+                    self.end.format(DATETIME_FORMAT)));
+        Ok(())
+    }
+}
+
+/// A stretch of time where nothing plays on a channel.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Gap {
+    pub channel: u16,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "Channel {}: dead air from {} to {}", self.channel,
+                    self.start.format(DATETIME_FORMAT), self.end.format(DATETIME_FORMAT)));
+        Ok(())
+    }
+}
+
+impl Gap {
+    pub fn seconds(&self) -> usize {
+        (self.end - self.start).num_seconds() as usize
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Report {
+    pub conflicts: Vec<Conflict>,
+    pub gaps: Vec<Gap>
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for conflict in self.conflicts.iter() {
+            try!(writeln!(fmt, "{}", conflict));
+        }
+        for gap in self.gaps.iter() {
+            try!(writeln!(fmt, "{}", gap));
+        }
+        Ok(())
+    }
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.len() == 0 && self.gaps.len() == 0
+    }
+}
+
+/// Checks `channels` between `from` and `to` for overlapping bookings and dead
+/// air. Bookings on channels not listed are ignored.
+pub fn analyze(channels: &[u16], bookings: &[Booking], from: NaiveDateTime, to: NaiveDateTime) -> Report {
+    let mut report = Report { conflicts: Vec::new(), gaps: Vec::new() };
+
+    for &channel in channels.iter() {
+        let mut booked = bookings.iter().filter(|x| x.channel == channel && x.end() > from && x.start < to)
+            .collect::<Vec<&Booking>>();
+        booked.sort_by(|x, y| x.start.cmp(&y.start));
+
+        let mut covered_until = from;
+        for (idx, booking) in booked.iter().enumerate() {
+            for earlier in booked[..idx].iter() {
+                if earlier.end() > booking.start {
+                    report.conflicts.push(Conflict {
+                        channel: channel,
+                        first: earlier.schedule.get_name(),
+                        second: booking.schedule.get_name(),
+                        start: booking.start,
+                        end: if earlier.end() < booking.end() { earlier.end() } else { booking.end() }
+                    });
+                }
+            }
+
+            if booking.start > covered_until {
+                report.gaps.push(Gap { channel: channel, start: covered_until, end: booking.start });
+            }
+            if booking.end() > covered_until {
+                covered_until = booking.end();
+            }
+        }
+
+        if covered_until < to {
+            report.gaps.push(Gap { channel: channel, start: covered_until, end: to });
+        }
+    }
+
+    report
+}
+
+/// Every airing in the lineup between `from` and `to`, with recurrences
+/// expanded.
+pub fn lineup_bookings<'a>(lineup: &'a Lineup, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Booking<'a>> {
+    let mut bookings = Vec::new();
+    for chan in lineup.channels().iter() {
+        for airing in chan.airings().iter() {
+            let length = Duration::seconds(airing.get_schedule().running_time() as i64);
+            for start in airing.occurrences((from - length).date(), to.date()).into_iter() {
+                bookings.push(Booking::new(chan.number(), start, airing.get_schedule()));
+            }
+        }
+    }
+    bookings
+}
+
+pub fn analyze_lineup(lineup: &Lineup, from: NaiveDateTime, to: NaiveDateTime) -> Report {
+    let channels = lineup.channels().iter().map(|x| x.number()).collect::<Vec<u16>>();
+    analyze(&channels, &lineup_bookings(lineup, from, to), from, to)
+}
+
+/// Builds a schedule of filler programs that fits in `gap`, taking programs
+/// from `pool` in turn. Programs too long for the remaining time are skipped,
+/// so the gap may not be filled completely.
+pub fn fill_gap(gap: &Gap, pool: &[Program]) -> Option<Schedule> {
+    let mut remaining = gap.seconds();
+    let mut progs = Vec::new();
+    let mut misses = 0;
+    let mut idx = 0;
+
+    while pool.len() > 0 && misses < pool.len() {
+        let prog = &pool[idx % pool.len()];
+        let length = prog.running_time();
+        if length > 0 && length <= remaining {
+            remaining -= length;
+            progs.push(prog.clone());
+            misses = 0;
+        } else {
+            misses += 1;
+        }
+        idx += 1;
+    }
+
+    if progs.len() == 0 {
+        None
+    } else {
+        Some(Schedule::new(&format!("Filler {}", gap.start.format(DATETIME_FORMAT)), progs))
+    }
+}
+
+/// Adds filler airings for every gap in `report` that the pool can fill.
+/// Returns the gaps that could not be filled at all.
+pub fn fill_lineup(lineup: &mut Lineup, report: &Report, pool: &[Program]) -> Vec<Gap> {
+    let mut unfilled = Vec::new();
+    for gap in report.gaps.iter() {
+        let channel = (0 .. lineup.channels_len())
+            .find(|&idx| lineup.get_channel_at(idx).unwrap().number() == gap.channel);
+
+        match (channel, fill_gap(gap, pool)) {
+            (Some(idx), Some(sched)) => {
+                lineup.get_channel_mut_at(idx).unwrap().add_airing(Airing::new(gap.start, sched));
+            },
+            _ => unfilled.push(gap.clone())
+        }
+    }
+    unfilled
+}
+
+#[test]
+fn conflicts_and_gaps() {
+    use super::parse;
+    use self::chrono::NaiveDate;
+
+    let six = NaiveDate::from_ymd(2015, 6, 1).and_hms(18, 0, 0);
+    // 20 minutes of episode with a 1 minute bumper nested inside it.
+    let evening = parse::parse("(schedule \"evening\" (program (local \"ep\")
+ (instr (play 00:00:00 00:10:00)
+        (program (local \"bumper\") (tags :runtime \"1m\") (instr (play 00:00:00 00:00:00)))
+        (play 00:10:00 00:10:00))))").unwrap();
+    let news = parse::parse("(schedule \"news\" (program (local \"news\") (tags :runtime \"30m\")
+ (instr (play 00:00:00 00:00:00))))").unwrap();
+
+    let bookings = vec!(Booking::new(5, six, &evening),
+                        Booking::new(5, six + Duration::minutes(20), &news),
+                        Booking::new(6, six + Duration::minutes(10), &news));
+
+    let report = analyze(&[5, 6], &bookings, six, six + Duration::hours(1));
+
+    assert_eq!(report.conflicts, vec!(Conflict {
+        channel: 5,
+        first: "evening".to_string(),
+        second: "news".to_string(),
+        start: six + Duration::minutes(20),
+        end: six + Duration::minutes(21)
+    }));
+    assert_eq!(report.gaps, vec!(
+        Gap { channel: 5, start: six + Duration::minutes(50), end: six + Duration::hours(1) },
+        Gap { channel: 6, start: six, end: six + Duration::minutes(10) },
+        Gap { channel: 6, start: six + Duration::minutes(40), end: six + Duration::hours(1) }));
+}
+
+#[test]
+fn gap_filling() {
+    use self::chrono::NaiveDate;
+    use super::channel::Channel;
+    use super::parse;
+
+    let six = NaiveDate::from_ymd(2015, 6, 1).and_hms(18, 0, 0);
+    let gap = Gap { channel: 5, start: six, end: six + Duration::minutes(10) };
+    let pool = parse::parse("(schedule \"filler\"
+ (program (local \"long\") (tags :runtime \"15m\") (instr (play 00:00:00 00:00:00)))
+ (program (local \"ad\") (tags :runtime \"4m\") (instr (play 00:00:00 00:00:00)))
+ (program (local \"bumper\") (tags :runtime \"1m\") (instr (play 00:00:00 00:00:00))))").unwrap().programs();
+
+    let filler = fill_gap(&gap, &pool).unwrap();
+    assert_eq!(filler.running_time(), 600);
+    assert!(fill_gap(&gap, &pool[..1]).is_none());
+
+    let mut lineup = Lineup::new("XBTV", vec!(Channel::new("Anime", 5, None)));
+    let report = Report { conflicts: Vec::new(), gaps: vec!(gap) };
+    assert_eq!(fill_lineup(&mut lineup, &report, &pool).len(), 0);
+    assert!(analyze_lineup(&lineup, six, six + Duration::minutes(10)).is_clean());
+}
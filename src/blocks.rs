@@ -2,6 +2,7 @@ use super::schedule::Schedule;
 use super::program::{Program, Source, Instruction};
 use super::program::Instruction::{Play, SubProgram};
 use super::tags::Tags;
+use super::error::{self, Error};
 
 #[derive(Clone)]
 pub enum Placement {
@@ -19,7 +20,7 @@ pub enum FillType {
 }
 
 impl FillType {
-    pub fn validate(&self) -> Result<(), String> {
+    pub fn validate(&self) -> error::Result<()> {
         match self {
             &FillType::Once(_) => Ok(()),
             &FillType::Twice(ref x, ref y) => match (x, y) {
@@ -33,11 +34,11 @@ impl FillType {
                     if x <= y { 
                         Ok(())
                     } else {
-                        Err("Mismatched time placements".to_string())
+                        Err(Error::InvalidPlacement("Mismatched time placements".to_string()))
                     }
                 },
                 
-                _ => Err("Bad placement composition".to_string())
+                _ => Err(Error::InvalidPlacement("Bad placement composition".to_string()))
             },
             &FillType::Thrice(ref x, ref y, ref z) => match (x, y, z) {
                 (&Placement::Beginning, &Placement::Beginning, &Placement::Beginning) |
@@ -53,7 +54,7 @@ impl FillType {
                     if x <= y {
                         Ok(())
                     } else {
-                        Err("Mismatched time placements".to_string())
+                        Err(Error::InvalidPlacement("Mismatched time placements".to_string()))
                     }
                 },
 
@@ -61,11 +62,11 @@ impl FillType {
                     if x <= y && y <= z {
                         Ok(())
                     } else {
-                        Err("Mismatched time placements".to_string())
+                        Err(Error::InvalidPlacement("Mismatched time placements".to_string()))
                     }
                 },
 
-                _ => Err("Bad placement composition".to_string()),
+                _ => Err(Error::InvalidPlacement("Bad placement composition".to_string())),
             },
             &FillType::None => Ok(())
         }
@@ -80,7 +81,7 @@ pub struct FillerIterator {
 }
 
 impl FillerIterator {
-    pub fn new(filltype: FillType, filler: Vec<String>) -> Result<FillerIterator, String> {
+    pub fn new(filltype: FillType, filler: Vec<String>) -> error::Result<FillerIterator> {
 
         try!(filltype.validate());
         
@@ -219,23 +220,25 @@ pub struct BlockIterator {
 
 impl BlockIterator {
     pub fn new(nom: &str, entries: &Vec<Vec<String>>, filler: &FillerIterator, 
-               repeat: &Vec<usize>, tags: Option<Vec<Vec<Tags>>>) -> Result<BlockIterator, String> {
+               repeat: &Vec<usize>, tags: Option<Vec<Vec<Tags>>>) -> error::Result<BlockIterator> {
         try!(filler.filltype.validate());
 
         if entries.len() != repeat.len() {
-            return Err("There must be the same number of elements in repeat vector as entries.".to_string())
+            return Err(Error::InvalidPlacement("There must be the same number of elements in repeat vector as \
+                                                entries.".to_string()))
         }
 
         match tags {
             None => { },
             Some(ref x) => {
                 if x.len() != entries.len() {
-                    return Err("There must be the same number of vectors of tags as entries.".to_string())
+                    return Err(Error::InvalidPlacement("There must be the same number of vectors of tags as \
+                                                        entries.".to_string()))
                 }
 
                 if entries.iter().zip(x.iter()).any(|(entry, tag_entry)| entry.len() != tag_entry.len()) {
-                    return Err("The number of tag structures must match the number of elements in an entry vector."
-                        .to_string())
+                    return Err(Error::InvalidPlacement("The number of tag structures must match the number of \
+                                                        elements in an entry vector.".to_string()))
                 }
             }
         }                        
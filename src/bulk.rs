@@ -0,0 +1,253 @@
+//! Changing one tag on every program a query selects, such as renaming a
+//! studio across a whole schedule. The edits are previewed before they are
+//! made and are undone together as one action.
+
+extern crate regex;
+
+use std::fmt;
+use std::str::FromStr;
+use self::regex::Regex;
+use super::EdBuffer;
+use super::schedule::Schedule;
+use super::path::ProgramPath;
+use super::tags::{Tags, TagType};
+use super::query::Query;
+use super::action::{Action, ActionError, CompoundAction, SetTags};
+use super::parse::ParseError;
+use super::parse::ParseError::BadToken;
+
+#[derive(Clone, Debug)]
+pub enum Edit {
+    /// Sets the tag. A tag holding several values gets this as its only one,
+    /// even if it has commas in it, as names such as `Production I.G, Inc.` do.
+    Set(String),
+    Clear,
+    /// Adds a value to a tag holding several, or text to the end of any other.
+    Append(String),
+    /// Replaces every match of the pattern, in each value of a list.
+    Replace(Regex, String)
+}
+
+impl FromStr for Edit {
+    type Err = ParseError;
+
+    /// Reads `set VALUE`, `clear`, `append VALUE` or
+    /// `replace PATTERN => REPLACEMENT`.
+    fn from_str(s: &str) -> Result<Edit, ParseError> {
+        let s = s.trim();
+        let (verb, rest) = match s.find(' ') {
+            Some(idx) => (&s[.. idx], s[idx + 1 ..].trim()),
+            None => (s, "")
+        };
+        match verb {
+            "set" => Ok(Edit::Set(rest.to_string())),
+            "clear" => Ok(Edit::Clear),
+            "append" => Ok(Edit::Append(rest.to_string())),
+            "replace" => {
+                let mut parts = rest.splitn(2, "=>");
+                let pattern = parts.next().unwrap_or("").trim();
+                let with = match parts.next() {
+                    Some(x) => x.trim(),
+                    None => return Err(BadToken("Expected replace PATTERN => REPLACEMENT".to_string()))
+                };
+                match Regex::new(pattern) {
+                    Ok(re) => Ok(Edit::Replace(re, with.to_string())),
+                    Err(f) => Err(BadToken(format!("Bad regular expression {}: {}", pattern, f)))
+                }
+            },
+            x => Err(BadToken(format!("Expected set, clear, append or replace, found {}", x)))
+        }
+    }
+}
+
+/// One program a bulk edit changes, with the tag before and after. A
+/// program that only inherits the tag is left alone, as the edit belongs on
+/// whichever program it inherits from.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BulkChange {
+    pub path: ProgramPath,
+    pub title: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub inherited: bool
+}
+
+impl fmt::Display for BulkChange {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let show = |x: &Option<String>| x.clone().unwrap_or("(unset)".to_string());
+        try!(write!(fmt, "{}", self.path));
+        if let Some(ref title) = self.title {
+            try!(write!(fmt, " {}", title));
+        }
+        if self.inherited {
+            write!(fmt, ": {} is inherited, left alone", show(&self.before))
+        } else {
+            write!(fmt, ": {} -> {}", show(&self.before), show(&self.after))
+        }
+    }
+}
+
+pub struct BulkEdit {
+    query: Query,
+    tag: TagType,
+    edit: Edit
+}
+
+impl BulkEdit {
+    pub fn new(query: Query, tag: TagType, edit: Edit) -> BulkEdit {
+        BulkEdit { query: query, tag: tag, edit: edit }
+    }
+
+    /// The tags after the edit, or `None` when it leaves them as they are.
+    fn edit_tags(&self, tags: &Tags) -> Result<Option<Tags>, ParseError> {
+        let mut novo = tags.clone();
+        match self.edit {
+            Edit::Set(ref x) => if self.tag.is_multi() {
+                try!(novo.modify_multi(&vec!(x.clone()), self.tag.clone()))
+            } else {
+                try!(novo.modify_tag(&self.tag, x))
+            },
+            Edit::Clear => novo.remove_tag(&self.tag),
+            Edit::Append(ref x) => if self.tag.is_multi() {
+                try!(novo.add_value(&self.tag, x))
+            } else {
+                let old = tags.get_tag(&self.tag).unwrap_or(String::new());
+                try!(novo.modify_tag(&self.tag, &format!("{}{}", old, x)))
+            },
+            Edit::Replace(ref re, ref with) => match tags.values(&self.tag) {
+                Some(values) => {
+                    let values = values.iter().map(|x| re.replace_all(x, &with[..]))
+                        .filter(|x| x.trim().len() > 0).collect::<Vec<String>>();
+                    if values.len() == 0 {
+                        novo.remove_tag(&self.tag)
+                    } else {
+                        try!(novo.modify_multi(&values, self.tag.clone()))
+                    }
+                },
+                None => if let Some(old) = tags.get_tag(&self.tag) {
+                    let replaced = re.replace_all(&old, &with[..]);
+                    if replaced.trim().len() == 0 {
+                        novo.remove_tag(&self.tag)
+                    } else {
+                        try!(novo.modify_tag(&self.tag, &replaced))
+                    }
+                }
+            }
+        }
+        Ok(if novo == *tags { None } else { Some(novo) })
+    }
+
+    /// The programs the query selects with their own tags before and after
+    /// the edit, and apart from them, those selected whose tag is inherited
+    /// with their effective tags.
+    fn edits(&self, sched: &Schedule) -> Result<(Vec<(ProgramPath, Tags, Tags)>, Vec<(ProgramPath, Tags)>),
+                                                 ParseError> {
+        let (mut edits, mut inherited) = (Vec::new(), Vec::new());
+        for path in self.query.search(sched) {
+            let (old, effective) = match (path.get(sched), sched.effective_tags(&path)) {
+                (Some(prog), Some(effective)) => (prog.get_tags().clone(), effective),
+                _ => continue
+            };
+            if old.get_tag(&self.tag).is_none() && effective.get_tag(&self.tag).is_some() {
+                inherited.push((path, effective));
+            } else if let Some(novo) = try!(self.edit_tags(&old)) {
+                edits.push((path, old, novo));
+            }
+        }
+        Ok((edits, inherited))
+    }
+
+    /// Every program the edit would change, without changing any, followed
+    /// by those it selects but leaves alone because they inherit the tag.
+    /// Programs whose tag the edit would not change are not listed.
+    pub fn preview(&self, sched: &Schedule) -> Result<Vec<BulkChange>, ParseError> {
+        let (edits, inherited) = try!(self.edits(sched));
+        let mut changes = edits.into_iter().map(|(path, old, novo)| {
+            let title = sched.effective_tags(&path).and_then(|x| x.title);
+            BulkChange { path: path, title: title, before: old.get_tag(&self.tag), after: novo.get_tag(&self.tag),
+                         inherited: false }
+        }).collect::<Vec<BulkChange>>();
+        changes.extend(inherited.into_iter().map(|(path, effective)| {
+            BulkChange { path: path, title: effective.title.clone(), before: effective.get_tag(&self.tag),
+                         after: None, inherited: true }
+        }));
+        Ok(changes)
+    }
+
+    /// One action setting the tags of every program in the preview.
+    pub fn to_action(&self, buffer: &EdBuffer) -> Result<Box<Action>, ActionError> {
+        let edits = match self.edits(buffer.get_schedule()) {
+            Ok((x, _)) => x,
+            Err(f) => return Err(ActionError::rejected(f))
+        };
+        let mut actions = Vec::new();
+        for (path, _, novo) in edits {
+            actions.push(try!(SetTags::new(buffer, &path, &novo)));
+        }
+        let label = match self.edit {
+            Edit::Set(ref x) => format!("Set {} to {}", self.tag, x),
+            Edit::Clear => format!("Clear {}", self.tag),
+            Edit::Append(ref x) => format!("Append {} to {}", x, self.tag),
+            Edit::Replace(ref re, ref with) => format!("Replace {} with {} in {}", re, with, self.tag)
+        };
+        Ok(CompoundAction::new(&format!("{} on {} programs", label, actions.len()), actions))
+    }
+}
+
+#[test]
+fn rename_studio() {
+    use super::parse;
+
+    let sched = parse::parse("(schedule \"night\"
+ (program (local \"fsn\") (tags :title \"fsn\" :studio \"Studio Deen\") (instr (play)))
+ (program (local \"ubw\") (tags :title \"ubw\" :studio \"ufotable\") (instr (play)))
+ (program (local \"higurashi\") (tags :title \"higurashi\" :studio (list \"Studio Deen\" \"Studio Pierrot\"))
+  (instr (play))))").unwrap();
+    let mut buffer = EdBuffer::from_schedule(&sched);
+
+    let rename = BulkEdit::new(Query::parse("studio ~ deen").unwrap(), TagType::Studio,
+                               "replace ^Studio Deen$ => DEEN".parse().unwrap());
+    let preview = rename.preview(buffer.get_schedule()).unwrap();
+    assert_eq!(preview.iter().map(|x| x.to_string()).collect::<Vec<String>>(),
+               vec!("0 fsn: Studio Deen -> DEEN", "2 higurashi: Studio Deen, Studio Pierrot -> DEEN, Studio Pierrot"));
+    assert_eq!(buffer.get_schedule().to_string(), sched.to_string());
+
+    buffer.apply(rename.to_action(&buffer).unwrap()).unwrap();
+    assert_eq!(Query::parse("studio = deen").unwrap().search(buffer.get_schedule()).len(), 2);
+    buffer.undo().unwrap();
+    assert_eq!(buffer.get_schedule().to_string(), sched.to_string());
+
+    let set = BulkEdit::new(Query::parse("title = ubw").unwrap(), TagType::Studio,
+                            "set Production I.G, Inc.".parse().unwrap());
+    buffer.apply(set.to_action(&buffer).unwrap()).unwrap();
+    assert_eq!(buffer.get_schedule().get_program_at(1).unwrap().get_tags().studios(),
+               ["Production I.G, Inc.".to_string()]);
+
+    let clear = BulkEdit::new(Query::parse("has studio").unwrap(), TagType::Studio, Edit::Clear);
+    assert_eq!(clear.preview(&sched).unwrap().len(), 3);
+    assert!("rename x".parse::<Edit>().is_err());
+}
+
+#[test]
+fn inherited_tags_left_alone() {
+    use super::parse;
+
+    let sched = parse::parse("(schedule \"night\" (program (local \"fsn\") (tags :title \"fsn\" :genre (list \"Anime\"))
+ (instr (play 00:00:00 00:10:00) (program (local \"trailer\") (tags :title \"trailer\") (instr (play))) (play))))")
+        .unwrap();
+    let trailer = ProgramPath::new(0).child(1);
+    let mut buffer = EdBuffer::from_schedule(&sched);
+
+    let append = BulkEdit::new(Query::parse("genre = anime").unwrap(), TagType::Genre, Edit::Append("Drama".to_string()));
+    let preview = append.preview(&sched).unwrap();
+    assert_eq!(preview.iter().map(|x| (x.path.clone(), x.inherited)).collect::<Vec<(ProgramPath, bool)>>(),
+               vec!((ProgramPath::new(0), false), (trailer.clone(), true)));
+    buffer.apply(append.to_action(&buffer).unwrap()).unwrap();
+    assert_eq!(buffer.get_schedule().effective_tags(&trailer).unwrap().genres(),
+               ["Anime".to_string(), "Drama".to_string()]);
+    assert_eq!(trailer.get(buffer.get_schedule()).unwrap().get_tags().genre, None);
+
+    let clear = BulkEdit::new(Query::parse("genre = anime").unwrap(), TagType::Genre, Edit::Clear);
+    buffer.apply(clear.to_action(&buffer).unwrap()).unwrap();
+    assert_eq!(Query::parse("has genre").unwrap().search(buffer.get_schedule()).len(), 0);
+}
@@ -0,0 +1,376 @@
+extern crate chrono;
+
+use std::fmt;
+use self::chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime};
+use super::schedule::Schedule;
+use super::program::Program;
+use super::recurrence::Recurrence;
+use super::rating::{check_watershed, WatershedRule, WatershedViolation};
+use super::error::{self, Error};
+
+pub static DATETIME_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S";
+
+/// A schedule bound to the date and time it starts airing, and optionally a
+/// rule for when it airs again.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Airing {
+    start: NaiveDateTime,
+    recurrence: Option<Recurrence>,
+    schedule: Schedule
+}
+
+impl fmt::Display for Airing {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "(airing \"{}\" ", self.start.format(DATETIME_FORMAT)));
+        if let Some(ref rule) = self.recurrence {
+            try!(write!(fmt, "(repeat \"{}\")", rule));
+        }
+        try!(write!(fmt, "{})", self.schedule));
+        Ok(())
+    }
+}
+
+impl<'a> Airing {
+    pub fn new(start: NaiveDateTime, sched: Schedule) -> Airing {
+        Airing {
+            start: start,
+            recurrence: None,
+            schedule: sched
+        }
+    }
+
+    pub fn recurring(start: NaiveDateTime, rule: Recurrence, sched: Schedule) -> Airing {
+        Airing {
+            start: start,
+            recurrence: Some(rule),
+            schedule: sched
+        }
+    }
+
+    pub fn recurrence(&'a self) -> Option<&'a Recurrence> {
+        self.recurrence.as_ref()
+    }
+
+    pub fn set_recurrence(&mut self, rule: Option<Recurrence>) {
+        self.recurrence = rule;
+    }
+
+    /// The wall clock start of every time this airs between `from` and `to`
+    /// inclusive.
+    pub fn occurrences(&self, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDateTime> {
+        match self.recurrence {
+            Some(ref rule) => rule.occurrences(self.start, from, to),
+            None => if self.start.date() >= from && self.start.date() <= to {
+                vec!(self.start)
+            } else {
+                Vec::new()
+            }
+        }
+    }
+
+    /// Every time this airs between `from` and `to` in the local timezone.
+    pub fn expand(&self, from: NaiveDate, to: NaiveDate) -> Vec<DateTime<Local>> {
+        match self.recurrence {
+            Some(ref rule) => rule.expand(self.start, from, to),
+            None => self.occurrences(from, to).iter()
+                .filter_map(|x| super::recurrence::resolve_local(&Local, x)).collect()
+        }
+    }
+
+    /// The start of the occurrence that is on the air at `time`.
+    pub fn occurrence_at(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let length = Duration::seconds(self.schedule.running_time() as i64);
+        let from = (time - length).date();
+        self.occurrences(from, time.date()).into_iter().rev()
+            .find(|&start| start <= time && time < start + length)
+    }
+
+    pub fn start(&self) -> NaiveDateTime {
+        self.start
+    }
+
+    pub fn end(&self) -> NaiveDateTime {
+        self.start + Duration::seconds(self.schedule.running_time() as i64)
+    }
+
+    pub fn set_start(&mut self, start: NaiveDateTime) {
+        self.start = start;
+    }
+
+    pub fn get_schedule(&'a self) -> &'a Schedule {
+        &self.schedule
+    }
+
+    pub fn get_schedule_mut(&'a mut self) -> &'a mut Schedule {
+        &mut self.schedule
+    }
+
+    pub fn set_schedule(&mut self, sched: &Schedule) {
+        self.schedule = sched.clone();
+    }
+
+    pub fn is_airing_at(&self, time: NaiveDateTime) -> bool {
+        self.occurrence_at(time).is_some()
+    }
+
+    /// The program playing at `time`, if this airing covers it.
+    pub fn program_at(&'a self, time: NaiveDateTime) -> Option<&'a Program> {
+        match self.occurrence_at(time) {
+            Some(start) => {
+                let offset = (time - start).num_seconds() as usize;
+                self.schedule.program_at_offset(offset).map(|(_, prog)| prog)
+            },
+            None => None
+        }
+    }
+}
+
+/// A channel of XBTV, with its schedules ordered by the time they air.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Channel {
+    name: String,
+    number: u16,
+    picture: Option<String>,
+    watershed: Vec<WatershedRule>,
+    airings: Vec<Airing>
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "(channel \"{}\" (number \"{}\")", self.name, self.number));
+        if let Some(ref picture) = self.picture {
+            try!(write!(fmt, "(picture \"{}\")", picture));
+        }
+        for rule in self.watershed.iter() {
+            try!(write!(fmt, "{}", rule));
+        }
+        for airing in self.airings.iter() {
+            try!(write!(fmt, "{}", airing));
+        }
+        try!(write!(fmt, ")"));
+        Ok(())
+    }
+}
+
+impl<'a> Channel {
+    pub fn new(nom: &str, number: u16, picture: Option<String>) -> Channel {
+        Channel {
+            name: nom.to_string(),
+            number: number,
+            picture: picture,
+            watershed: Vec::new(),
+            airings: Vec::new()
+        }
+    }
+
+    pub fn name_ref(&'a self) -> &'a str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, nom: &str) {
+        self.name = nom.to_string();
+    }
+
+    pub fn number(&self) -> u16 {
+        self.number
+    }
+
+    pub fn set_number(&mut self, number: u16) {
+        self.number = number;
+    }
+
+    pub fn picture(&'a self) -> Option<&'a str> {
+        match self.picture {
+            Some(ref pic) => Some(pic),
+            None => None
+        }
+    }
+
+    pub fn set_picture(&mut self, picture: Option<String>) {
+        self.picture = picture;
+    }
+
+    pub fn watershed(&'a self) -> &'a Vec<WatershedRule> {
+        &self.watershed
+    }
+
+    pub fn add_watershed_rule(&mut self, rule: WatershedRule) {
+        self.watershed.push(rule);
+    }
+
+    pub fn remove_watershed_rule(&mut self, idx: usize) -> Option<WatershedRule> {
+        if idx < self.watershed.len() {
+            Some(self.watershed.remove(idx))
+        } else {
+            None
+        }
+    }
+
+    /// Checks the first showing of every airing against this channel's
+    /// watershed rules.
+    pub fn check_watershed(&self) -> Result<(), Vec<WatershedViolation>> {
+        let mut violations = Vec::new();
+        for airing in self.airings.iter() {
+            if let Err(mut x) = check_watershed(&self.watershed, airing.get_schedule(), airing.start()) {
+                violations.append(&mut x);
+            }
+        }
+
+        if violations.len() == 0 {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    pub fn airings(&'a self) -> &'a Vec<Airing> {
+        &self.airings
+    }
+
+    pub fn airings_len(&self) -> usize {
+        self.airings.len()
+    }
+
+    pub fn get_airing_at(&'a self, idx: usize) -> Option<&'a Airing> {
+        self.airings.get(idx)
+    }
+
+    pub fn get_airing_mut_at(&'a mut self, idx: usize) -> Option<&'a mut Airing> {
+        self.airings.get_mut(idx)
+    }
+
+    /// Adds an airing, keeping the airings ordered by start time. Returns the
+    /// index it was placed at.
+    pub fn add_airing(&mut self, airing: Airing) -> usize {
+        let idx = match self.airings.iter().position(|x| x.start() > airing.start()) {
+            Some(idx) => idx,
+            None => self.airings.len()
+        };
+        self.airings.insert(idx, airing);
+        idx
+    }
+
+    pub fn remove_airing(&mut self, idx: usize) -> Option<Airing> {
+        if idx < self.airings.len() {
+            Some(self.airings.remove(idx))
+        } else {
+            None
+        }
+    }
+
+    /// The airing covering `time`. When airings overlap, the one that started
+    /// most recently wins.
+    pub fn airing_at(&'a self, time: NaiveDateTime) -> Option<&'a Airing> {
+        self.airings.iter().rev().find(|airing| airing.is_airing_at(time))
+    }
+
+    pub fn program_at(&'a self, time: NaiveDateTime) -> Option<&'a Program> {
+        self.airing_at(time).and_then(|airing| airing.program_at(time))
+    }
+}
+
+/// Every channel XBTV runs.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Lineup {
+    name: String,
+    channels: Vec<Channel>
+}
+
+impl fmt::Display for Lineup {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "(lineup \"{}\" ", self.name));
+        for channel in self.channels.iter() {
+            try!(write!(fmt, "{}", channel));
+        }
+        try!(write!(fmt, ")"));
+        Ok(())
+    }
+}
+
+impl<'a> Lineup {
+    pub fn new(nom: &str, channels: Vec<Channel>) -> Lineup {
+        Lineup {
+            name: nom.to_string(),
+            channels: channels
+        }
+    }
+
+    pub fn name_ref(&'a self) -> &'a str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, nom: &str) {
+        self.name = nom.to_string();
+    }
+
+    pub fn channels(&'a self) -> &'a Vec<Channel> {
+        &self.channels
+    }
+
+    pub fn channels_len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn get_channel_at(&'a self, idx: usize) -> Option<&'a Channel> {
+        self.channels.get(idx)
+    }
+
+    pub fn get_channel_mut_at(&'a mut self, idx: usize) -> Option<&'a mut Channel> {
+        self.channels.get_mut(idx)
+    }
+
+    pub fn get_channel_by_number(&'a self, number: u16) -> Option<&'a Channel> {
+        self.channels.iter().find(|chan| chan.number() == number)
+    }
+
+    pub fn add_channel(&mut self, channel: Channel) -> error::Result<()> {
+        if self.get_channel_by_number(channel.number()).is_some() {
+            Err(Error::Duplicate(format!("Channel {} already exists", channel.number())))
+        } else {
+            self.channels.push(channel);
+            Ok(())
+        }
+    }
+
+    pub fn remove_channel(&mut self, idx: usize) -> Option<Channel> {
+        if idx < self.channels.len() {
+            Some(self.channels.remove(idx))
+        } else {
+            None
+        }
+    }
+
+    /// What is on every channel at `time`, in channel order.
+    pub fn whats_on(&'a self, time: NaiveDateTime) -> Vec<(&'a Channel, Option<&'a Program>)> {
+        self.channels.iter().map(|chan| (chan, chan.program_at(time))).collect()
+    }
+}
+
+#[test]
+fn whats_on_test() {
+    use self::chrono::NaiveDate;
+    use super::parse;
+
+    let sched = parse::parse("(schedule \"evening\"
+ (program (local \"a\") (instr (play 00:00:00 00:10:00)))
+ (program (local \"b\") (instr (play 00:00:00 00:20:00))))").unwrap();
+    let start = NaiveDate::from_ymd(2015, 6, 1).and_hms(18, 0, 0);
+
+    let mut anime = Channel::new("Anime", 5, Some("anime.png".to_string()));
+    anime.add_airing(Airing::new(start, sched));
+    let lineup = Lineup::new("XBTV", vec!(anime, Channel::new("Music", 6, None)));
+
+    let on = lineup.whats_on(start + Duration::seconds(700));
+    assert_eq!(on.len(), 2);
+    assert_eq!(on[0].1.unwrap().get_path(), Some("b"));
+    assert!(on[1].1.is_none());
+    assert!(lineup.whats_on(start + Duration::seconds(1800))[0].1.is_none());
+
+    let mut daily = lineup.get_channel_at(0).unwrap().clone();
+    let rule = Recurrence::daily().count(3);
+    let sched = daily.get_airing_at(0).unwrap().get_schedule().clone();
+    daily.remove_airing(0);
+    daily.add_airing(Airing::recurring(start, rule, sched));
+    assert!(daily.program_at(start + Duration::days(2) + Duration::seconds(60)).is_some());
+    assert!(daily.program_at(start + Duration::days(3) + Duration::seconds(60)).is_none());
+}
@@ -0,0 +1,93 @@
+//! Commands run from the command line instead of opening the editor.
+
+use std::io::{self, Write};
+use std::path::Path;
+use super::path::ProgramPath;
+use super::storage::read_schedule;
+use super::merge;
+use super::query::Query;
+
+fn usage() -> i32 {
+    let _ = writeln!(io::stderr(), "usage: XBTVEd [merge BASE OURS THEIRS [PATH] | show FILE [PROGRAM-PATH...] | query EXPRESSION FILE...]");
+    2
+}
+
+/// Prints the schedule in `args[0]`, or the programs at the paths after it.
+fn show(args: &[String]) -> i32 {
+    if args.len() == 0 {
+        return usage()
+    }
+    let sched = match read_schedule(Path::new(&args[0])) {
+        Ok(x) => x,
+        Err(f) => {
+            let _ = writeln!(io::stderr(), "{}: {}", args[0], f);
+            return 2
+        }
+    };
+    if args.len() == 1 {
+        println!("{}", sched);
+        return 0
+    }
+
+    let mut status = 0;
+    for arg in args[1 ..].iter() {
+        match arg.parse::<ProgramPath>() {
+            Ok(path) => match path.get(&sched) {
+                Some(prog) => println!("{}: {}", path, prog),
+                None => {
+                    let _ = writeln!(io::stderr(), "{}: There is no program at {}", args[0], path);
+                    status = 1;
+                }
+            },
+            Err(f) => {
+                let _ = writeln!(io::stderr(), "{}", f);
+                status = 2;
+            }
+        }
+    }
+    status
+}
+
+/// Prints each program in the files after `args[0]` that the query in
+/// `args[0]` matches, as `file: path title`.
+fn query(args: &[String]) -> i32 {
+    if args.len() < 2 {
+        return usage()
+    }
+    let query = match Query::parse(&args[0]) {
+        Ok(x) => x,
+        Err(f) => {
+            let _ = writeln!(io::stderr(), "{}", f);
+            return 2
+        }
+    };
+
+    let mut status = 1;
+    for file in args[1 ..].iter() {
+        let sched = match read_schedule(Path::new(file)) {
+            Ok(x) => x,
+            Err(f) => {
+                let _ = writeln!(io::stderr(), "{}: {}", file, f);
+                return 2
+            }
+        };
+        for path in query.search(&sched) {
+            let title = sched.effective_tags(&path).and_then(|x| x.title).unwrap_or(String::new());
+            println!("{}: {} {}", file, path, title);
+            status = 0;
+        }
+    }
+    status
+}
+
+/// Runs the command named by `args[0]`, giving its exit status, or `None`
+/// when there is no command and the editor should open. Arguments that are
+/// not a command are left to the editor, as they always were.
+pub fn run(args: &[String]) -> Option<i32> {
+    match args.first().map(|x| &x[..]) {
+        Some("merge") => Some(merge::merge_driver(&args[1 ..])),
+        Some("show") => Some(show(&args[1 ..])),
+        Some("query") => Some(query(&args[1 ..])),
+        _ => None
+    }
+}
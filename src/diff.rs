@@ -0,0 +1,556 @@
+//! Structural differences between two schedules.
+//!
+//! Programs and subprograms are matched up by their source. A matched program
+//! whose place changed is reported as moved, and one whose tags or
+//! instructions changed as modified, down through its subprograms.
+
+use std::fmt;
+use super::EdBuffer;
+use super::schedule::Schedule;
+use super::program::{Instruction, Program, Source};
+use super::tags::{Tags, TagType};
+use super::path::ProgramPath;
+use super::action::{Action, ActionError, AddProgram, CompoundAction, DeleteProgram, InsertInstruction,
+                    InsertProgram, MoveProgram, RemoveInstruction, SetDefaults, SetInstruction, SetName, SetTags};
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TagChange {
+    pub tag: TagType,
+    pub old: Option<String>,
+    pub new: Option<String>
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum InstrChange {
+    /// An instruction added at this index of the new program.
+    Inserted(usize, Instruction),
+    /// The instruction at the first index of the old program was removed. The
+    /// second index is where it was in the program as it is being edited.
+    Deleted(usize, usize, Instruction),
+    /// A play instruction changed, at these old and new indices.
+    Changed(usize, usize, Instruction, Instruction),
+    /// A subprogram changed, at these old and new indices.
+    Nested(usize, usize, ProgramDiff)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ProgramDiff {
+    pub source: Source,
+    pub tags: Vec<TagChange>,
+    pub instrs: Vec<InstrChange>,
+    /// The tags of the new program, to copy changed tags from.
+    tags_after: Tags
+}
+
+impl ProgramDiff {
+    pub fn is_empty(&self) -> bool {
+        self.tags.len() == 0 && self.instrs.len() == 0
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Change {
+    /// A program added at this index of the new schedule.
+    Inserted(usize, Program),
+    /// The program at this index of the old schedule was removed.
+    Deleted(usize, Program),
+    /// A program moved from an old index to a new one.
+    Moved(usize, usize, Source),
+    /// A program changed, at these old and new indices.
+    Modified(usize, usize, ProgramDiff)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ScheduleDiff {
+    pub name: Option<(String, String)>,
+    /// Changes to the tags every program inherits.
+    pub defaults: Vec<TagChange>,
+    pub changes: Vec<Change>,
+    defaults_after: Tags
+}
+
+enum Op {
+    Same(usize, usize),
+    Delete(usize),
+    Insert(usize)
+}
+
+/// Lines up two lists along their longest common subsequence.
+fn align<T, F: Fn(&T, &T) -> bool>(old: &[T], new: &[T], same: F) -> Vec<Op> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec!(vec!(0; m + 1); n + 1);
+    for i in (0 .. n).rev() {
+        for j in (0 .. m).rev() {
+            lcs[i][j] = if same(&old[i], &new[j]) {
+                lcs[i + 1][j + 1] + 1
+            } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                lcs[i + 1][j]
+            } else {
+                lcs[i][j + 1]
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && same(&old[i], &new[j]) && lcs[i][j] == lcs[i + 1][j + 1] + 1 {
+            ops.push(Op::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete(i));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(j));
+            j += 1;
+        }
+    }
+    ops
+}
+
+fn same_instr(old: &Instruction, new: &Instruction) -> bool {
+    match (old, new) {
+        (&Instruction::SubProgram(ref x), &Instruction::SubProgram(ref y)) => x.get_location() == y.get_location(),
+        _ => old == new
+    }
+}
+
+/// Turns a run of removed and added instructions into changes. `pos` is how
+/// many instructions of the new program come before the run.
+fn flush_run(old: &[Instruction], new: &[Instruction], deletes: &mut Vec<usize>, inserts: &mut Vec<usize>,
+             pos: usize, changes: &mut Vec<InstrChange>) {
+    let mut paired = 0;
+    while paired < deletes.len() && paired < inserts.len()
+        && old[deletes[paired]].is_play() && new[inserts[paired]].is_play() {
+        let (i, j) = (deletes[paired], inserts[paired]);
+        changes.push(InstrChange::Changed(i, j, old[i].clone(), new[j].clone()));
+        paired += 1;
+    }
+    for &i in deletes[paired ..].iter() {
+        changes.push(InstrChange::Deleted(i, pos + paired, old[i].clone()));
+    }
+    for &j in inserts[paired ..].iter() {
+        changes.push(InstrChange::Inserted(j, new[j].clone()));
+    }
+    deletes.clear();
+    inserts.clear();
+}
+
+fn diff_tags(old_tags: &Tags, new_tags: &Tags) -> Vec<TagChange> {
+    TagType::all_in(&[old_tags, new_tags]).into_iter().filter(|tag| {
+        let mut copied = old_tags.clone();
+        copied.copy_tag(new_tags, tag);
+        copied != *old_tags
+    }).map(|tag| TagChange { old: old_tags.get_tag(&tag), new: new_tags.get_tag(&tag), tag: tag }).collect()
+}
+
+/// The differences between two versions of a program, which should share a
+/// source.
+pub fn diff_program(old: &Program, new: &Program) -> ProgramDiff {
+    let (old_tags, new_tags) = (old.get_tags(), new.get_tags());
+    let tags = diff_tags(old_tags, new_tags);
+
+    let (old_instrs, new_instrs) = (old.get_instrs(), new.get_instrs());
+    let mut instrs = Vec::new();
+    let (mut deletes, mut inserts) = (Vec::new(), Vec::new());
+    let mut pos = 0;
+    for op in align(old_instrs, new_instrs, same_instr).into_iter() {
+        match op {
+            Op::Same(i, j) => {
+                flush_run(old_instrs, new_instrs, &mut deletes, &mut inserts, pos, &mut instrs);
+                if let (Some(x), Some(y)) = (old_instrs[i].subprogram(), new_instrs[j].subprogram()) {
+                    let nested = diff_program(x, y);
+                    if !nested.is_empty() {
+                        instrs.push(InstrChange::Nested(i, j, nested));
+                    }
+                }
+                pos = j + 1;
+            },
+            Op::Delete(i) => deletes.push(i),
+            Op::Insert(j) => inserts.push(j)
+        }
+    }
+    flush_run(old_instrs, new_instrs, &mut deletes, &mut inserts, pos, &mut instrs);
+
+    ProgramDiff {
+        source: new.get_location().clone(),
+        tags: tags,
+        instrs: instrs,
+        tags_after: new_tags.clone()
+    }
+}
+
+pub fn diff(old: &Schedule, new: &Schedule) -> ScheduleDiff {
+    let (old_progs, new_progs) = (old.programs(), new.programs());
+    let ops = align(&old_progs, &new_progs, |x, y| x.get_location() == y.get_location());
+
+    let inserted = ops.iter().filter_map(|op| match *op { Op::Insert(j) => Some(j), _ => None })
+        .collect::<Vec<usize>>();
+    let mut moved_to = Vec::new();
+    let mut changes = Vec::new();
+
+    for op in ops.iter() {
+        match *op {
+            Op::Same(i, j) => {
+                let modified = diff_program(&old_progs[i], &new_progs[j]);
+                if !modified.is_empty() {
+                    changes.push(Change::Modified(i, j, modified));
+                }
+            },
+            Op::Delete(i) => {
+                let dest = inserted.iter().cloned().find(|&j| !moved_to.contains(&j)
+                                                         && new_progs[j].get_location() == old_progs[i].get_location());
+                match dest {
+                    Some(j) => {
+                        moved_to.push(j);
+                        changes.push(Change::Moved(i, j, old_progs[i].get_location().clone()));
+                        let modified = diff_program(&old_progs[i], &new_progs[j]);
+                        if !modified.is_empty() {
+                            changes.push(Change::Modified(i, j, modified));
+                        }
+                    },
+                    None => changes.push(Change::Deleted(i, old_progs[i].clone()))
+                }
+            },
+            Op::Insert(_) => { }
+        }
+    }
+    for &j in inserted.iter().filter(|j| !moved_to.contains(j)) {
+        changes.push(Change::Inserted(j, new_progs[j].clone()));
+    }
+
+    ScheduleDiff {
+        name: if old.name_ref() != new.name_ref() {
+            Some((old.get_name(), new.get_name()))
+        } else {
+            None
+        },
+        defaults: diff_tags(old.defaults(), new.defaults()),
+        changes: changes,
+        defaults_after: new.defaults().clone()
+    }
+}
+
+fn run(scratch: &mut EdBuffer, action: Box<Action>, actions: &mut Vec<Box<Action>>) -> Result<(), ActionError> {
+    try!(action.apply(scratch));
+    actions.push(action);
+    Ok(())
+}
+
+fn program_actions(scratch: &mut EdBuffer, path: &ProgramPath, diff: &ProgramDiff, actions: &mut Vec<Box<Action>>)
+                   -> Result<(), ActionError> {
+    if diff.tags.len() > 0 {
+        let mut tags = match scratch.get_program_at_path(path) {
+            Some(prog) => prog.get_tags().clone(),
+            None => return Err(ActionError::NotFound(format!("There is no program at {}", path)))
+        };
+        for change in diff.tags.iter() {
+            tags.copy_tag(&diff.tags_after, &change.tag);
+        }
+        let action = try!(SetTags::new(scratch, path, &tags));
+        try!(run(scratch, action, actions));
+    }
+
+    for change in diff.instrs.iter() {
+        match *change {
+            InstrChange::Inserted(j, ref instr) => {
+                let action = try!(InsertInstruction::new(scratch, path, j, instr));
+                try!(run(scratch, action, actions));
+            },
+            InstrChange::Deleted(_, pos, _) => {
+                let action = try!(RemoveInstruction::new(scratch, path, pos));
+                try!(run(scratch, action, actions));
+            },
+            InstrChange::Changed(_, j, _, ref instr) => {
+                let action = try!(SetInstruction::new(scratch, path, j, instr));
+                try!(run(scratch, action, actions));
+            },
+            InstrChange::Nested(_, j, ref nested) => try!(program_actions(scratch, &path.child(j), nested, actions))
+        }
+    }
+    Ok(())
+}
+
+impl ScheduleDiff {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.defaults.len() == 0 && self.changes.len() == 0
+    }
+
+    /// Where each program of the old schedule, which had `old_len` programs,
+    /// ended up in the new one, or `None` if it was deleted.
+    pub fn matching(&self, old_len: usize) -> Vec<Option<usize>> {
+        let mut deleted = Vec::new();
+        let mut moves = Vec::new();
+        let mut inserted = Vec::new();
+        for change in self.changes.iter() {
+            match *change {
+                Change::Deleted(i, _) => deleted.push(i),
+                Change::Moved(i, j, _) => moves.push((i, j)),
+                Change::Inserted(j, _) => inserted.push(j),
+                Change::Modified(_, _, _) => { }
+            }
+        }
+
+        // Programs that did not move keep their order, so they line up with the
+        // new indices that are neither inserted nor moved to.
+        let new_len = old_len - deleted.len() + inserted.len();
+        let mut kept_new = (0 .. new_len)
+            .filter(|j| !inserted.contains(j) && !moves.iter().any(|&(_, y)| y == *j));
+
+        (0 .. old_len).map(|i| {
+            if deleted.contains(&i) {
+                None
+            } else {
+                match moves.iter().find(|&&(x, _)| x == i) {
+                    Some(&(_, j)) => Some(j),
+                    None => kept_new.next()
+                }
+            }
+        }).collect()
+    }
+
+    /// The edits that turn `old`, the schedule this diff was made from, into
+    /// the new one, as a single action.
+    pub fn to_action(&self, old: &Schedule) -> Result<Box<Action>, ActionError> {
+        let mut scratch = EdBuffer::from_schedule(old);
+        let mut actions = Vec::new();
+
+        if let Some((_, ref name)) = self.name {
+            let action = SetName::new(&scratch, name);
+            try!(run(&mut scratch, action, &mut actions));
+        }
+        if self.defaults.len() > 0 {
+            let mut tags = scratch.defaults().clone();
+            for change in self.defaults.iter() {
+                tags.copy_tag(&self.defaults_after, &change.tag);
+            }
+            let action = SetDefaults::new(&scratch, &tags);
+            try!(run(&mut scratch, action, &mut actions));
+        }
+
+        // Programs are edited in place first, while the old indices still hold.
+        for change in self.changes.iter() {
+            if let Change::Modified(i, _, ref diff) = *change {
+                try!(program_actions(&mut scratch, &ProgramPath::new(i), diff, &mut actions));
+            }
+        }
+
+        let mut deleted = self.changes.iter().filter_map(|x| match *x {
+            Change::Deleted(i, _) => Some(i),
+            _ => None
+        }).collect::<Vec<usize>>();
+        deleted.sort();
+        for &i in deleted.iter().rev() {
+            let action = try!(DeleteProgram::new(&scratch, i));
+            try!(run(&mut scratch, action, &mut actions));
+        }
+
+        let inserts = self.changes.iter().filter_map(|x| match *x {
+            Change::Inserted(j, ref prog) => Some((j, prog)),
+            _ => None
+        }).collect::<Vec<(usize, &Program)>>();
+        let new_len = old.programs_len() - deleted.len() + inserts.len();
+
+        // The new index of every program now in the scratch buffer.
+        let mut current = self.matching(old.programs_len()).into_iter().filter(|x| x.is_some())
+            .collect::<Vec<Option<usize>>>();
+
+        for j in 0 .. new_len {
+            if let Some(&(_, prog)) = inserts.iter().find(|&&(x, _)| x == j) {
+                let action = if j == current.len() { AddProgram::new(prog) } else { InsertProgram::new(prog, j) };
+                try!(run(&mut scratch, action, &mut actions));
+                current.insert(j, Some(j));
+            } else {
+                match current.iter().position(|&x| x == Some(j)) {
+                    Some(from) if from != j => {
+                        let action = try!(MoveProgram::new(&scratch, from, j));
+                        try!(run(&mut scratch, action, &mut actions));
+                        let moved = current.remove(from);
+                        current.insert(j, moved);
+                    },
+                    Some(_) => { },
+                    None => return Err(ActionError::Mismatch(format!("Nothing in the diff ends up at {}", j)))
+                }
+            }
+        }
+
+        Ok(CompoundAction::new("Apply diff", actions))
+    }
+
+    /// The diff as a JSON object.
+    pub fn to_json(&self) -> String {
+        let name = match self.name {
+            Some((ref old, ref new)) => format!("{{\"old\": {}, \"new\": {}}}", json_str(old), json_str(new)),
+            None => "null".to_string()
+        };
+        let changes = self.changes.iter().map(|change| match *change {
+            Change::Inserted(j, ref prog) => {
+                format!("{{\"type\": \"inserted\", \"index\": {}, \"program\": {}}}", j, json_str(&prog.to_string()))
+            },
+            Change::Deleted(i, ref prog) => {
+                format!("{{\"type\": \"deleted\", \"index\": {}, \"program\": {}}}", i, json_str(&prog.to_string()))
+            },
+            Change::Moved(i, j, ref source) => {
+                format!("{{\"type\": \"moved\", \"from\": {}, \"to\": {}, \"source\": {}}}", i, j,
+                        json_str(&source.to_string()))
+            },
+            Change::Modified(i, j, ref diff) => program_json("modified", i, j, diff)
+        }).collect::<Vec<String>>();
+        format!("{{\"name\": {}, \"changes\": [{}], \"defaults\": [{}]}}", name, changes.join(", "),
+                tags_json(&self.defaults).join(", "))
+    }
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_opt(s: &Option<String>) -> String {
+    match *s {
+        Some(ref x) => json_str(x),
+        None => "null".to_string()
+    }
+}
+
+fn tags_json(changes: &[TagChange]) -> Vec<String> {
+    changes.iter().map(|x| {
+        format!("{{\"tag\": \"{}\", \"old\": {}, \"new\": {}}}", x.tag, json_opt(&x.old), json_opt(&x.new))
+    }).collect()
+}
+
+fn program_json(kind: &str, i: usize, j: usize, diff: &ProgramDiff) -> String {
+    let tags = tags_json(&diff.tags);
+    let instrs = diff.instrs.iter().map(|change| match *change {
+        InstrChange::Inserted(j, ref instr) => {
+            format!("{{\"type\": \"inserted\", \"index\": {}, \"instruction\": {}}}", j, json_str(&instr.to_string()))
+        },
+        InstrChange::Deleted(i, _, ref instr) => {
+            format!("{{\"type\": \"deleted\", \"index\": {}, \"instruction\": {}}}", i, json_str(&instr.to_string()))
+        },
+        InstrChange::Changed(i, j, ref old, ref new) => {
+            format!("{{\"type\": \"changed\", \"old_index\": {}, \"new_index\": {}, \"old\": {}, \"new\": {}}}",
+                    i, j, json_str(&old.to_string()), json_str(&new.to_string()))
+        },
+        InstrChange::Nested(i, j, ref nested) => program_json("nested", i, j, nested)
+    }).collect::<Vec<String>>();
+
+    format!("{{\"type\": \"{}\", \"old_index\": {}, \"new_index\": {}, \"source\": {}, \"tags\": [{}], \
+             \"instructions\": [{}]}}", kind, i, j, json_str(&diff.source.to_string()), tags.join(", "),
+            instrs.join(", "))
+}
+
+fn write_tag_changes(fmt: &mut fmt::Formatter, changes: &[TagChange], indent: &str) -> fmt::Result {
+    for change in changes.iter() {
+        try!(writeln!(fmt, "{}{}: {} -> {}", indent, change.tag,
+                      change.old.as_ref().map(|x| format!("\"{}\"", x)).unwrap_or("unset".to_string()),
+                      change.new.as_ref().map(|x| format!("\"{}\"", x)).unwrap_or("unset".to_string())));
+    }
+    Ok(())
+}
+
+fn write_program_diff(fmt: &mut fmt::Formatter, diff: &ProgramDiff, depth: usize) -> fmt::Result {
+    let indent = (0 .. depth).map(|_| "    ").collect::<String>();
+    try!(write_tag_changes(fmt, &diff.tags, &indent));
+    for change in diff.instrs.iter() {
+        match *change {
+            InstrChange::Inserted(j, ref instr) => try!(writeln!(fmt, "{}+ instruction {}: {}", indent, j, instr)),
+            InstrChange::Deleted(i, _, ref instr) => try!(writeln!(fmt, "{}- instruction {}: {}", indent, i, instr)),
+            InstrChange::Changed(i, _, ref old, ref new) => {
+                try!(writeln!(fmt, "{}* instruction {}: {} -> {}", indent, i, old, new))
+            },
+            InstrChange::Nested(i, _, ref nested) => {
+                try!(writeln!(fmt, "{}* instruction {}: {}", indent, i, nested.source));
+                try!(write_program_diff(fmt, nested, depth + 1));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for ScheduleDiff {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((ref old, ref new)) = self.name {
+            try!(writeln!(fmt, "name: \"{}\" -> \"{}\"", old, new));
+        }
+        if self.defaults.len() > 0 {
+            try!(writeln!(fmt, "defaults:"));
+            try!(write_tag_changes(fmt, &self.defaults, "    "));
+        }
+        for change in self.changes.iter() {
+            match *change {
+                Change::Inserted(j, ref prog) => try!(writeln!(fmt, "+ program {}: {}", j, prog.get_location())),
+                Change::Deleted(i, ref prog) => try!(writeln!(fmt, "- program {}: {}", i, prog.get_location())),
+                Change::Moved(i, j, ref source) => try!(writeln!(fmt, "> program {} -> {}: {}", i, j, source)),
+                Change::Modified(i, _, ref diff) => {
+                    try!(writeln!(fmt, "* program {}: {}", i, diff.source));
+                    try!(write_program_diff(fmt, diff, 1));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn diff_and_replay() {
+    let parse = |text: &str| super::parse::parse(text).unwrap();
+    let old = parse("(schedule \"old\"
+ (program (local \"a\") (tags :title \"A\")
+  (instr (play 00:00:00 00:00:10)
+         (program (local \"bumper\") (tags :title \"Bumper\") (instr (play 00:00:00 00:00:05)))
+         (play 00:00:10 00:00:00)))
+ (program (local \"b\") (tags :title \"B\") (instr (play)))
+ (program (local \"c\") (tags :title \"C\") (instr (play)))
+ (program (local \"d\") (tags :title \"D\") (instr (play))))");
+    let new = parse("(schedule \"new\"
+ (program (local \"d\") (tags :title \"D\") (instr (play)))
+ (program (local \"a\") (tags :title \"A\")
+  (instr (play 00:00:00 00:00:20)
+         (program (local \"bumper\") (instr (play 00:00:00 00:00:05)))
+         (play 00:00:20 00:00:00) (play 00:00:30 00:00:00)))
+ (program (local \"e\") (tags :title \"E\") (instr (play)))
+ (program (local \"b\") (tags :title \"Bee\") (instr (play))))");
+
+    let diff = diff(&old, &new);
+    assert_eq!(diff.name, Some(("old".to_string(), "new".to_string())));
+    assert!(diff.changes.contains(&Change::Moved(3, 0, Source::Pathname("d".to_string()))));
+    assert!(diff.changes.iter().any(|x| match *x { Change::Deleted(2, _) => true, _ => false }));
+    assert!(diff.changes.iter().any(|x| match *x { Change::Inserted(2, _) => true, _ => false }));
+
+    let modified_a = diff.changes.iter().filter_map(|x| match *x {
+        Change::Modified(0, 1, ref d) => Some(d.clone()),
+        _ => None
+    }).next().unwrap();
+    assert_eq!(modified_a.instrs[0], InstrChange::Changed(0, 0, Instruction::Play(0, 10), Instruction::Play(0, 20)));
+    match modified_a.instrs[1] {
+        InstrChange::Nested(1, 1, ref nested) => assert_eq!(nested.tags[0].old, Some("Bumper".to_string())),
+        ref x => panic!("Expected a nested change, found {:?}", x)
+    }
+
+    let text = diff.to_string();
+    assert!(text.contains("title: \"B\" -> \"Bee\""));
+    assert!(diff.to_json().starts_with("{\"name\": {\"old\": \"old\", \"new\": \"new\"}, \"changes\": ["));
+
+    let mut buffer = EdBuffer::from_schedule(&old);
+    let action = diff.to_action(&old).unwrap();
+    buffer.apply(action).unwrap();
+    // Instruction cursors are not part of the diff, so compare the text.
+    assert_eq!(buffer.get_schedule().to_string(), new.to_string());
+    assert_eq!(buffer.get_name(), "new");
+    buffer.undo().unwrap();
+    assert_eq!(*buffer.get_schedule(), old);
+}
@@ -0,0 +1,109 @@
+//! The errors the library reports, gathered into one type so callers can
+//! handle them alike or pick out the kinds they care about.
+
+use std::error;
+use std::fmt;
+use std::io;
+use super::parse::ParseError;
+use super::action::ActionError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// An index past the end of a list. Holds the index and the list's
+    /// length.
+    OutOfBounds(usize, usize),
+    /// A path that does not lead to a program.
+    NotFound(String),
+    /// Filler or block entries that cannot be laid out as asked.
+    InvalidPlacement(String),
+    /// Something that must be unique, like a channel number, is taken.
+    Duplicate(String),
+    Parse(ParseError),
+    Io(io::Error),
+    Action(ActionError)
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Fails with `OutOfBounds` unless `idx` is below `len`.
+pub fn check_index(idx: usize, len: usize) -> Result<()> {
+    if idx < len {
+        Ok(())
+    } else {
+        Err(Error::OutOfBounds(idx, len))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::OutOfBounds(idx, len) => try!(write!(fmt, "Index {} is out of bounds for length {}.", idx, len)),
+            Error::NotFound(ref x) => try!(write!(fmt, "{}", x)),
+            Error::InvalidPlacement(ref x) => try!(write!(fmt, "{}", x)),
+            Error::Duplicate(ref x) => try!(write!(fmt, "{}", x)),
+            Error::Parse(ref f) => try!(write!(fmt, "{}", f)),
+            Error::Io(ref f) => try!(write!(fmt, "{}", f)),
+            Error::Action(ref f) => try!(write!(fmt, "{}", f))
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::OutOfBounds(_, _) => "index out of bounds",
+            Error::NotFound(_) => "no such program",
+            Error::InvalidPlacement(_) => "invalid placement",
+            Error::Duplicate(_) => "duplicate entry",
+            Error::Parse(ref f) => f.description(),
+            Error::Io(ref f) => error::Error::description(f),
+            Error::Action(ref f) => f.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Parse(ref f) => Some(f),
+            Error::Io(ref f) => Some(f),
+            Error::Action(ref f) => Some(f),
+            _ => None
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<ActionError> for Error {
+    fn from(err: ActionError) -> Error {
+        Error::Action(err)
+    }
+}
+
+#[test]
+fn empty_schedule_bounds() {
+    use super::schedule::Schedule;
+    use super::program::Program;
+
+    let mut sched = Schedule::new("empty", Vec::new());
+    assert!(sched.last_program().is_none());
+    match sched.delete_program(0) {
+        Err(Error::OutOfBounds(0, 0)) => { },
+        x => panic!("{:?}", x)
+    }
+    assert!(sched.insert_program(1, &Program::example()).is_err());
+    sched.insert_program(0, &Program::example()).unwrap();
+    sched.insert_program(1, &Program::example()).unwrap();
+    assert_eq!(sched.programs_len(), 2);
+    assert!(sched.set_current_program_idx(2).is_err());
+}
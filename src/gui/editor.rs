@@ -9,20 +9,48 @@ use self::conrod::{
 use self::opengl_graphics::GlGraphics;
 use self::opengl_graphics::glyph_cache::GlyphCache;
 
-use std::io::{Error, ErrorKind, Read, Write};
+use std::io::{Error, ErrorKind, Read};
 use std::path::{Path, PathBuf};
 use std::fs::File;
 use super::menu::MenuBar;
 use super::super::schedule::Schedule;
 use super::super::program::Program;
-use super::super::action::Action;
+use super::super::tags::Tags;
+use super::super::path::ProgramPath;
+use super::super::action::{Action, ActionError, CompoundAction, SetSchedule, SetTags};
+use super::super::history::UndoTree;
+use super::super::journal::{self, Journal, Recovery};
+use super::super::channel::Lineup;
+use super::super::storage::{self, FileStamp, SaveError};
+use super::super::merge::{self, Conflict};
+use super::super::error;
+use super::super::query::Query;
+use super::super::bulk::{BulkEdit, BulkChange};
+use super::super::metadata;
 
 pub struct EdBuffer {
     schedule: Schedule,
     filepath: Option<PathBuf>,
-    undo_buffer: Vec<Box<Action>>,
-    redo_buffer: Vec<Box<Action>>,
-    modified: bool
+    history: UndoTree,
+    /// The schedule at the root of the history.
+    origin: Schedule,
+    /// Whether the next action may merge into the current node's action.
+    coalesce: bool,
+    /// Where edits since the last save are recorded, once the buffer has a
+    /// file.
+    journal: Option<Journal>,
+    /// The file as it was when last read or written, to notice changes made
+    /// by other programs.
+    disk_stamp: Option<FileStamp>,
+    /// The schedule as it was last read from or written to the file, when
+    /// that differs from the root of the history. Merges start from it.
+    on_disk: Option<Schedule>,
+    /// What the last merge could not settle.
+    conflicts: Vec<Conflict>,
+    /// How many backups to keep when saving over the file.
+    backups: usize,
+    modified: bool,
+    airing: Option<(usize, usize)>
 }
 
 impl<'a> EdBuffer {
@@ -30,9 +58,16 @@ impl<'a> EdBuffer {
         EdBuffer {
             schedule: Schedule::example(),
             filepath: None,
-            undo_buffer: Vec::new(),
-            redo_buffer: Vec::new(),
-            modified: true
+            history: UndoTree::new(),
+            origin: Schedule::example(),
+            coalesce: false,
+            journal: None,
+            disk_stamp: None,
+            on_disk: None,
+            conflicts: Vec::new(),
+            backups: 0,
+            modified: true,
+            airing: None
         }
     }
 
@@ -40,53 +75,206 @@ impl<'a> EdBuffer {
         EdBuffer {
             schedule: sched.clone(),
             filepath: None,
-            undo_buffer: Vec::new(),
-            redo_buffer: Vec::new(),
-            modified: false
+            history: UndoTree::new(),
+            origin: sched.clone(),
+            coalesce: false,
+            journal: None,
+            disk_stamp: None,
+            on_disk: None,
+            conflicts: Vec::new(),
+            backups: 0,
+            modified: false,
+            airing: None
         }
     }
 
-    pub fn apply(&mut self, action: Box<Action>) -> Result<(), String> {
+    /// Applies `action` and records it in the history, merging it into the
+    /// current node's action when the two allow it. Edits made after undoing
+    /// start a new branch; nothing is lost. If the action fails the buffer and
+    /// its history are left as they were.
+    pub fn apply(&mut self, action: Box<Action>) -> Result<(), ActionError> {
+        let snapshot = self.schedule.clone();
         if let Err(f) = action.apply(self) {
-            self.modified = true;
-            self.redo_buffer.clear();
+            self.schedule = snapshot;
             return Err(f)
         }
         self.modified = true;
-        self.undo_buffer.push(action);
-        self.redo_buffer.clear();
+        self.journal_edit(&action.label());
+        let merged = match self.history.current_action() {
+            Some(last) if self.coalesce => last.merge(&*action),
+            _ => None
+        };
+        let unmerged = match merged {
+            Some(x) => self.history.replace_current(x).err().map(|_| action),
+            None => Some(action)
+        };
+        if let Some(x) = unmerged {
+            self.history.push(x);
+        }
+        self.coalesce = true;
         Ok(())
     }
 
-    pub fn undo(&mut self) {
-        if let Some(action) = self.undo_buffer.pop() {
-            if let Err(f) = action.reverse(self) {
-                panic!(format!("Unexpected error occurred: {}", f))
+    /// Stops the next action from merging into the last one.
+    pub fn break_coalescing(&mut self) {
+        self.coalesce = false;
+    }
+
+    /// Runs the action of `node` against the buffer, reversing it if `undo`.
+    /// The action goes back in the tree whether or not it worked.
+    fn run_node(&mut self, node: usize, undo: bool) -> Result<(), ActionError> {
+        let action = match self.history.take_action(node) {
+            Some(x) => x,
+            None => return Err(ActionError::HistoryDiverged)
+        };
+        let result = if undo { action.reverse(self) } else { action.apply(self) };
+        self.history.restore_action(node, action);
+        result
+    }
+
+    /// Reverses the action leading to the current node and moves to its
+    /// parent. If it fails the buffer is left as it was.
+    pub fn undo(&mut self) -> Result<(), ActionError> {
+        let node = self.history.current();
+        match self.history.get(node).and_then(|x| x.parent()) {
+            Some(parent) => self.goto(parent),
+            None => Ok(())
+        }
+    }
+
+    /// Reapplies the most recently visited child of the current node. If it
+    /// fails the buffer is left as it was.
+    pub fn redo(&mut self) -> Result<(), ActionError> {
+        match self.history.redo_target() {
+            Some(child) => self.goto(child),
+            None => Ok(())
+        }
+    }
+
+    /// Undoes and redoes along the tree until the buffer is in the state of
+    /// `node`, which may be on another branch. If any step fails the buffer
+    /// is left as it was.
+    pub fn goto(&mut self, node: usize) -> Result<(), ActionError> {
+        if node >= self.history.len() {
+            return Err(ActionError::NotFound(format!("There is no change {} in the history", node)))
+        }
+        let snapshot = self.schedule.clone();
+        let start = self.history.current();
+        let (up, down) = self.history.route(node);
+
+        for &x in up.iter() {
+            if let Err(f) = self.run_node(x, true) {
+                self.schedule = snapshot;
+                self.history.set_current(start);
+                return Err(f)
             }
+            let parent = self.history.get(x).and_then(|n| n.parent()).unwrap();
+            self.history.set_current(parent);
+        }
+        for &x in down.iter() {
+            if let Err(f) = self.run_node(x, false) {
+                self.schedule = snapshot;
+                self.history.set_current(start);
+                return Err(f)
+            }
+            self.history.set_current(x);
+        }
+
+        if up.len() > 0 || down.len() > 0 {
             self.modified = true;
-            self.redo_buffer.push(action);
+            self.coalesce = false;
+            self.journal_edit(&format!("Go to change {}", node));
         }
+        Ok(())
+    }
+
+    /// Records the schedule as it is now in the journal. If the journal can
+    /// not be written it is dropped, rather than failing the edit.
+    fn journal_edit(&mut self, label: &str) {
+        let failed = match self.journal {
+            Some(ref mut j) => j.record(label, &self.schedule).is_err(),
+            None => false
+        };
+        if failed {
+            self.journal = None;
+        }
+    }
+
+    /// Replays edits recovered from a journal, each as one undoable step.
+    pub fn recover(&mut self, recovery: &Recovery) -> Result<(), ActionError> {
+        for &(ref label, ref sched) in recovery.entries.iter() {
+            let action = SetSchedule::new(self, sched, label);
+            try!(self.apply(action));
+            self.coalesce = false;
+        }
+        Ok(())
     }
 
-    pub fn redo(&mut self) {
-        if let Some(action) = self.redo_buffer.pop() {
-            if let Err(f) = action.apply(self) {
-                panic!(format!("Unexpected error occurred: {}", f))
+    /// Deletes the journal without replaying it.
+    pub fn discard_journal(&mut self) -> Result<(), Error> {
+        match self.journal {
+            Some(ref mut j) => j.clear(&self.schedule),
+            None => Ok(())
+        }
+    }
+
+    /// Checks that replaying every branch of the history from the schedule it
+    /// started at works, and that the current node's branch gives the current
+    /// schedule. The buffer itself is not changed.
+    pub fn verify_history(&self) -> Result<(), ActionError> {
+        let mut stack = vec!((0, self.origin.clone()));
+        while let Some((node, sched)) = stack.pop() {
+            if node == self.history.current() && sched != self.schedule {
+                return Err(ActionError::HistoryDiverged)
+            }
+            for &child in self.history.get(node).unwrap().children().iter() {
+                let mut scratch = EdBuffer::from_schedule(&sched);
+                let applied = match self.history.get(child).and_then(|x| x.action()) {
+                    Some(action) => action.apply(&mut scratch),
+                    None => Err(ActionError::HistoryDiverged)
+                };
+                if applied.is_err() {
+                    return Err(ActionError::HistoryDiverged)
+                }
+                stack.push((child, scratch.schedule));
             }
-            self.undo_buffer.push(action);
         }
+        Ok(())
+    }
+
+    /// Forgets the history, keeping the schedule as it is.
+    pub fn reset_history(&mut self) {
+        self.history = UndoTree::new();
+        self.origin = self.schedule.clone();
+        self.coalesce = false;
+    }
+
+    pub fn history(&'a self) -> &'a UndoTree {
+        &self.history
+    }
+
+    /// The labels of the actions that can be undone, oldest first.
+    pub fn undo_labels(&self) -> Vec<String> {
+        self.history.undo_labels()
+    }
+
+    /// The labels of the actions that can be redone, next to redo first.
+    pub fn redo_labels(&self) -> Vec<String> {
+        self.history.redo_labels()
     }
 
     pub fn is_undo_buffer_empty(&self) -> bool {
-        self.undo_buffer.len() == 0
+        !self.history.can_undo()
     }
 
     pub fn is_redo_buffer_empty(&self) -> bool {
-        self.redo_buffer.len() == 0
+        !self.history.can_redo()
     }
 
+    /// Sets the file of the buffer, and journals edits next to it from now on.
     pub fn set_path(&mut self, path: &Path) {
-        self.filepath = Some(path.to_path_buf())
+        self.filepath = Some(path.to_path_buf());
+        self.journal = Some(Journal::new(path, &self.schedule));
     }
 
     pub fn get_path(&'a self) -> Option<&'a Path> {
@@ -96,10 +284,19 @@ impl<'a> EdBuffer {
         }
     }
 
+    /// The channel and airing of the open lineup this buffer is editing, if any.
+    pub fn airing(&self) -> Option<(usize, usize)> {
+        self.airing
+    }
+
     pub fn get_schedule(&'a self) -> &'a Schedule {
         &self.schedule
     }
 
+    pub fn set_schedule(&mut self, sched: &Schedule) {
+        self.schedule = sched.clone();
+    }
+
     pub fn set_name(&mut self, name: &str) {
         self.schedule.set_name(name)
     }
@@ -108,6 +305,63 @@ impl<'a> EdBuffer {
         self.schedule.name_ref()
     }
 
+    pub fn defaults(&'a self) -> &'a Tags {
+        self.schedule.defaults()
+    }
+
+    pub fn set_defaults(&mut self, tags: &Tags) {
+        self.schedule.set_defaults(tags)
+    }
+
+    /// The tags that apply to the program at `path`, inherited ones included.
+    pub fn effective_tags(&self, path: &ProgramPath) -> Option<Tags> {
+        self.schedule.effective_tags(path)
+    }
+
+    /// The programs whose tags match `query`; see the `query` module.
+    pub fn search(&self, query: &str) -> error::Result<Vec<ProgramPath>> {
+        let query = try!(Query::parse(query));
+        Ok(query.search(&self.schedule))
+    }
+
+    /// The programs `edit` would change and how, leaving the buffer alone.
+    pub fn bulk_preview(&self, edit: &BulkEdit) -> error::Result<Vec<BulkChange>> {
+        Ok(try!(edit.preview(&self.schedule)))
+    }
+
+    /// Makes `edit` as a single undoable change, giving the number of
+    /// programs changed.
+    pub fn bulk_edit(&mut self, edit: &BulkEdit) -> error::Result<usize> {
+        let count = try!(edit.preview(&self.schedule)).iter().filter(|x| !x.inherited).count();
+        if count > 0 {
+            let action = try!(edit.to_action(self));
+            try!(self.apply(action));
+        }
+        Ok(count)
+    }
+
+    /// Fills in tags from the files the programs play, as one undoable change.
+    /// Tags already set are kept. Gives how many programs gained tags, and
+    /// the files that could not be read.
+    pub fn read_metadata(&mut self) -> error::Result<(usize, Vec<String>)> {
+        let (filled, failed) = metadata::fill_schedule(&self.schedule);
+        if filled.len() > 0 {
+            let mut actions = Vec::new();
+            for &(ref path, ref tags) in filled.iter() {
+                actions.push(try!(SetTags::new(self, path, tags)));
+            }
+            try!(self.apply(CompoundAction::new(&format!("Read tags from {} files", filled.len()), actions)));
+        }
+        Ok((filled.len(), failed))
+    }
+
+    /// Writes the tags of every program with a local file to an NFO beside
+    /// it, for media centers to read. Gives how many were written, and the
+    /// ones that could not be.
+    pub fn write_nfos(&self) -> (usize, Vec<String>) {
+        metadata::write_nfos(&self.schedule)
+    }
+
     pub fn get_program(&'a self) -> Option<&'a Program> {
         self.schedule.get_program()
     }
@@ -124,6 +378,14 @@ impl<'a> EdBuffer {
         self.schedule.get_program_mut_at(idx)
     }
 
+    pub fn get_program_at_path(&'a self, path: &ProgramPath) -> Option<&'a Program> {
+        path.get(&self.schedule)
+    }
+
+    pub fn get_program_at_path_mut(&'a mut self, path: &ProgramPath) -> Option<&'a mut Program> {
+        path.get_mut(&mut self.schedule)
+    }
+
     pub fn last_program(&'a self) -> Option<&'a Program> {
         self.schedule.last_program()
     }
@@ -136,40 +398,134 @@ impl<'a> EdBuffer {
         self.schedule.pop_program()
     }
 
-    pub fn insert_program(&mut self, idx: usize, prog: &Program) -> Result<(), String> {
+    pub fn insert_program(&mut self, idx: usize, prog: &Program) -> error::Result<()> {
         self.schedule.insert_program(idx, prog)
     }
 
-    pub fn delete_program(&mut self, idx: usize) -> Result<(), String> {
+    pub fn delete_program(&mut self, idx: usize) -> error::Result<()> {
         self.schedule.delete_program(idx)
     }
 
+    pub fn move_program(&mut self, from: usize, to: usize) -> error::Result<()> {
+        self.schedule.move_program(from, to)
+    }
+
     pub fn modified(&self) -> bool {
         self.modified
     }
 
-    pub fn save(&mut self) -> Result<(), Error> {
+    /// Keeps `count` rotating backups of the file, `.bak.1` being the newest.
+    pub fn set_backups(&mut self, count: usize) {
+        self.backups = count;
+    }
+
+    /// Remembers the file as it is now, so later saves can tell whether
+    /// another program has changed it.
+    pub fn stamp_file(&mut self) -> Result<(), Error> {
+        self.disk_stamp = match self.filepath {
+            Some(ref path) => try!(FileStamp::read(path)),
+            None => None
+        };
+        Ok(())
+    }
+
+    /// Saves the schedule, refusing if the file was changed by another
+    /// program since it was opened or last saved.
+    pub fn save(&mut self) -> Result<(), SaveError> {
+        if let (Some(path), Some(stamp)) = (self.filepath.as_ref(), self.disk_stamp.as_ref()) {
+            match try!(FileStamp::read(path)) {
+                Some(ref now) if !stamp.matches(now) => return Err(SaveError::ChangedOnDisk(path.clone())),
+                _ => { }
+            }
+        }
+        self.force_save()
+    }
+
+    /// Saves the schedule whether or not the file was changed by another
+    /// program. The file is replaced in one step, so a crash leaves either the
+    /// old or the new version.
+    pub fn force_save(&mut self) -> Result<(), SaveError> {
         if self.filepath.is_none() {
-            Err(Error::new(ErrorKind::Other, "There is no file for this buffer yet. Please use save as"))
-        } else { 
+            Err(SaveError::Io(Error::new(ErrorKind::Other,
+                                         "There is no file for this buffer yet. Please use save as")))
+        } else {
             let path = self.get_path().unwrap().to_path_buf();
-            let mut file = try!(File::create(path.as_path()));
-            try!(file.write_all(self.get_schedule().to_string().as_bytes()));
+            try!(storage::rotate_backups(path.as_path(), self.backups));
+            try!(storage::write_atomic(path.as_path(), self.get_schedule().to_string().as_bytes()));
+            try!(self.stamp_file());
+            self.on_disk = Some(self.schedule.clone());
             self.modified = false;
+            self.coalesce = false;
+            if let Some(mut j) = self.journal.take() {
+                try!(j.clear(&self.schedule));
+            }
+            self.journal = Some(Journal::new(path.as_path(), &self.schedule));
             Ok(())
         }
     }
 
-    pub fn save_as(&mut self, path: &str) -> Result<(), Error> {
+    pub fn save_as(&mut self, path: &str) -> Result<(), SaveError> {
         self.filepath = Some(Path::new(path).to_path_buf());
+        self.disk_stamp = None;
         self.save()
     }
+
+    fn read_file(&self) -> error::Result<Schedule> {
+        let path = match self.filepath {
+            Some(ref x) => x.clone(),
+            None => return Err(error::Error::NotFound("There is no file for this buffer yet".to_string()))
+        };
+        let mut s = String::new();
+        try!(try!(File::open(&path)).read_to_string(&mut s));
+        Ok(try!(super::super::parse::parse(&s)))
+    }
+
+    /// Replaces the schedule with the file as it is on disk, dropping unsaved
+    /// edits and the history.
+    pub fn reload(&mut self) -> error::Result<()> {
+        let sched = try!(self.read_file());
+        self.schedule = sched;
+        self.on_disk = None;
+        self.conflicts.clear();
+        self.reset_history();
+        try!(self.stamp_file());
+        try!(self.discard_journal());
+        self.modified = false;
+        Ok(())
+    }
+
+    /// Merges the changes another program made to the file into the buffer,
+    /// as one undoable edit. What could not be merged is kept in our version
+    /// and listed by `conflicts`. The buffer stays modified, so saving writes
+    /// the merge back.
+    pub fn merge_from_disk(&mut self) -> error::Result<()> {
+        let theirs = try!(self.read_file());
+        let merged = {
+            let base = self.on_disk.as_ref().unwrap_or(&self.origin);
+            merge::merge(base, &self.schedule, &theirs)
+        };
+
+        let action = SetSchedule::new(self, &merged.schedule, "Merge changes from disk");
+        try!(self.apply(action));
+        self.break_coalescing();
+        self.conflicts = merged.conflicts;
+        self.on_disk = Some(theirs);
+        Ok(try!(self.stamp_file()))
+    }
+
+    /// The conflicts left by the last merge.
+    pub fn conflicts(&'a self) -> &'a [Conflict] {
+        &self.conflicts
+    }
+
 }
 
 pub struct XBTVEd {
     buffers: Vec<EdBuffer>,
     current_buffer: usize,
     menu_bar: MenuBar,
+    lineup: Option<Lineup>,
+    lineup_path: Option<PathBuf>,
     _width: f64,
     exit_signal: bool
 }
@@ -194,6 +550,8 @@ impl<'a> XBTVEd {
             buffers: vec!(EdBuffer::new()),
             current_buffer: 0,
             menu_bar: MenuBar::new(11.0, entries, width),
+            lineup: None,
+            lineup_path: None,
             _width: width,
             exit_signal: false,
         }
@@ -228,19 +586,15 @@ impl<'a> XBTVEd {
         }
     }
 
-    pub fn set_current_schedule(&mut self, idx: usize) -> Result<(), String> {
-        if idx >= self.buffers.len() {
-            Err("Out of bounds".to_string())
-        } else {
-            self.current_buffer = idx;
-            Ok(())
-        }
+    pub fn set_current_schedule(&mut self, idx: usize) -> error::Result<()> {
+        try!(error::check_index(idx, self.buffers.len()));
+        self.current_buffer = idx;
+        Ok(())
     }
 
-    pub fn remove_buffer(&mut self, idx: usize) -> Result<(), String> {
-        if idx >= self.buffers.len() {
-            Err("Out of bounds".to_string())
-        } else if self.buffers.len() == 1 {
+    pub fn remove_buffer(&mut self, idx: usize) -> error::Result<()> {
+        try!(error::check_index(idx, self.buffers.len()));
+        if self.buffers.len() == 1 {
             self.buffers.remove(idx);
 
             self.buffers.push(EdBuffer::new());
@@ -254,7 +608,7 @@ impl<'a> XBTVEd {
         }
     }
 
-    pub fn open_file(&mut self, path: &str) -> Result<(), Error> {
+    pub fn open_file(&mut self, path: &str) -> error::Result<()> {
         let pathname = Path::new(path);
         if let Some(idx) = self.buffers.iter().position(|ref edbuf| edbuf.get_path() == Some(&pathname)) {
             self.current_buffer = idx;
@@ -264,28 +618,57 @@ impl<'a> XBTVEd {
         let mut file = try!(File::open(path));
         let mut s = String::new();
         try!(file.read_to_string(&mut s));
-        let sched = match super::super::parse::parse(&s) {
-            Ok(x) => x,
-            Err(f) => return Err(Error::new(ErrorKind::Other, f.to_string().as_str()))
-        };
+        let sched = try!(super::super::parse::parse(&s));
 
         let mut buffer = EdBuffer::from_schedule(&sched);
         buffer.set_path(pathname);
+        try!(buffer.stamp_file());
+
+        match Recovery::read(&journal::journal_path(pathname)) {
+            Ok(Some(ref recovery)) if recovery.entries.len() > 0 => {
+                let mut msg = format!("{} has {} unsaved edits from a previous session. Recover them?",
+                                      path, recovery.entries.len());
+                if recovery.base != sched {
+                    msg.push_str(" The file has changed since they were made.");
+                }
+                if super::confirm(&msg) {
+                    if let Err(f) = buffer.recover(recovery) {
+                        super::pop_up_msg(&format!("Could not recover the edits: {}", f));
+                    }
+                } else {
+                    try!(buffer.discard_journal());
+                }
+            },
+            Ok(_) => { },
+            Err(f) => super::pop_up_msg(&format!("Could not read the journal for {}: {}", path, f))
+        }
+
+        // Left by the merge driver when a merge had conflicts. Once shown, the
+        // report has served its purpose.
+        let mut report = String::new();
+        if let Ok(_) = File::open(merge::conflicts_path(pathname)).and_then(|mut f| f.read_to_string(&mut report)) {
+            super::pop_up_msg(&format!("The last merge of {} had conflicts, kept as the local version:\n{}",
+                                       path, report));
+            if let Err(f) = merge::clear_conflicts(pathname) {
+                super::pop_up_msg(&format!("Could not remove {}: {}", merge::conflicts_path(pathname).display(), f));
+            }
+        }
+
         self.buffers.push(buffer);
         self.current_buffer += 1;
 
         Ok(())
     }
 
-    pub fn save(&mut self) -> Result<(), Error> {
+    pub fn save(&mut self) -> Result<(), SaveError> {
         self.current_buffer_mut().save()
     }
 
-    pub fn save_as(&mut self, path: &str) -> Result<(), Error> {
+    pub fn save_as(&mut self, path: &str) -> Result<(), SaveError> {
         self.current_buffer_mut().save_as(path)
     }
 
-    pub fn save_all(&mut self) -> Result<(), Error> {
+    pub fn save_all(&mut self) -> Result<(), SaveError> {
         for buf in self.buffers.iter_mut() {
             if buf.modified() {
                 try!(buf.save());
@@ -294,6 +677,83 @@ impl<'a> XBTVEd {
         Ok(())
     }
 
+    pub fn lineup(&'a self) -> Option<&'a Lineup> {
+        self.lineup.as_ref()
+    }
+
+    pub fn lineup_mut(&'a mut self) -> Option<&'a mut Lineup> {
+        self.lineup.as_mut()
+    }
+
+    pub fn new_lineup(&mut self, name: &str) {
+        self.lineup = Some(Lineup::new(name, Vec::new()));
+        self.lineup_path = None;
+    }
+
+    pub fn open_lineup(&mut self, path: &str) -> error::Result<()> {
+        let mut file = try!(File::open(path));
+        let mut s = String::new();
+        try!(file.read_to_string(&mut s));
+        let lineup = try!(super::super::parse::parse_lineup(&s));
+
+        self.lineup = Some(lineup);
+        self.lineup_path = Some(Path::new(path).to_path_buf());
+        Ok(())
+    }
+
+    /// Opens the schedule of an airing in a new buffer. Use `commit_airing` to
+    /// write the edits back into the lineup.
+    pub fn edit_airing(&mut self, channel: usize, airing: usize) -> error::Result<()> {
+        let sched = match self.lineup.as_ref().and_then(|lineup| lineup.get_channel_at(channel))
+            .and_then(|chan| chan.get_airing_at(airing)) {
+            Some(x) => x.get_schedule().clone(),
+            None => return Err(error::Error::NotFound("There is no such airing in the lineup".to_string()))
+        };
+
+        let mut buffer = EdBuffer::from_schedule(&sched);
+        buffer.airing = Some((channel, airing));
+        self.buffers.push(buffer);
+        self.current_buffer = self.buffers.len() - 1;
+        Ok(())
+    }
+
+    /// Writes the schedule of the current buffer back into the airing it was
+    /// opened from.
+    pub fn commit_airing(&mut self) -> error::Result<()> {
+        let (channel, airing) = match self.current_buffer().airing() {
+            Some(x) => x,
+            None => return Err(error::Error::NotFound("This buffer is not part of the lineup".to_string()))
+        };
+        let sched = self.current_buffer().get_schedule().clone();
+
+        match self.lineup.as_mut().and_then(|lineup| lineup.get_channel_mut_at(channel))
+            .and_then(|chan| chan.get_airing_mut_at(airing)) {
+            Some(x) => {
+                x.set_schedule(&sched);
+                Ok(())
+            },
+            None => Err(error::Error::NotFound("The airing for this buffer no longer exists".to_string()))
+        }
+    }
+
+    pub fn save_lineup(&mut self) -> Result<(), Error> {
+        let path = match self.lineup_path {
+            Some(ref x) => x.clone(),
+            None => return Err(Error::new(ErrorKind::Other,
+                                          "There is no file for this lineup yet. Please use save lineup as"))
+        };
+        let contents = match self.lineup {
+            Some(ref x) => x.to_string(),
+            None => return Err(Error::new(ErrorKind::Other, "There is no lineup open"))
+        };
+        storage::write_atomic(path.as_path(), contents.as_bytes())
+    }
+
+    pub fn save_lineup_as(&mut self, path: &str) -> Result<(), Error> {
+        self.lineup_path = Some(Path::new(path).to_path_buf());
+        self.save_lineup()
+    }
+
     pub fn buffers_len(&self) -> usize {
         self.buffers.len()
     }
@@ -318,12 +778,47 @@ impl<'a> XBTVEd {
         &self.menu_bar
     }
 
+    /// Saves the current buffer. If another program changed the file, offers
+    /// to merge its changes, to reload it or to overwrite it instead.
+    fn save_with_prompts(&mut self) {
+        match self.save() {
+            Ok(_) => { },
+            Err(SaveError::ChangedOnDisk(path)) => {
+                let name = path.display().to_string();
+                if super::confirm(&format!("{} was changed by another program. Merge its changes with yours?",
+                                           name)) {
+                    let buffer = self.current_buffer_mut();
+                    match buffer.merge_from_disk() {
+                        Ok(_) if buffer.conflicts().len() > 0 => {
+                            super::pop_up_msg(&format!("{} conflicts were kept as your version. Check them \
+                                                        before saving again.", buffer.conflicts().len()));
+                        },
+                        Ok(_) => if let Err(f) = buffer.save() {
+                            super::pop_up_msg(&f.to_string());
+                        },
+                        Err(f) => super::pop_up_msg(&format!("Could not merge: {}", f))
+                    }
+                } else if super::confirm(&format!("Reload {} and drop your edits?", name)) {
+                    if let Err(f) = self.current_buffer_mut().reload() {
+                        super::pop_up_msg(&f.to_string());
+                    }
+                } else if super::confirm(&format!("Overwrite the changes to {} with your version?", name)) {
+                    if let Err(f) = self.current_buffer_mut().force_save() {
+                        super::pop_up_msg(&f.to_string());
+                    }
+                }
+            },
+            Err(f) => super::pop_up_msg(&f.to_string())
+        }
+    }
+
     pub fn draw_menus(&mut self, gl: &mut GlGraphics, ui: &mut Ui<GlyphCache<'a>>) {
         self.menu_bar.menu_mut(0).unwrap().draw(ui, gl);
         if let Some(idx) = self.menu_bar.menu(0).unwrap().idx() {
             match idx {
                 0 => { }, //File
                 1 => self.add_buffer(),
+                3 => self.save_with_prompts(),
                 5 => self.exit_signal = true,
                 x => println!("{}", x)
             }
@@ -332,10 +827,22 @@ impl<'a> XBTVEd {
 
         self.menu_bar.menu_mut(1).unwrap().draw(ui, gl);
         if let Some(idx) = self.menu_bar.menu(1).unwrap().idx() {
-            match idx {
+            let result = match idx {
                 1 => self.current_buffer_mut().undo(),
                 2 => self.current_buffer_mut().redo(),
-                _ => { }
+                _ => Ok(())
+            };
+            // A failed step leaves the buffer as it was, so the history is
+            // only dropped when it no longer leads to the buffer at all.
+            if let Err(f) = result {
+                let buffer = self.current_buffer_mut();
+                match buffer.verify_history() {
+                    Err(ActionError::HistoryDiverged) => {
+                        buffer.reset_history();
+                        super::pop_up_msg(&format!("{}. The undo history has been cleared.", f));
+                    },
+                    _ => super::pop_up_msg(&f.to_string())
+                }
             }
         }
     }
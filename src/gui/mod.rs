@@ -3,6 +3,7 @@ extern crate conrod;
 extern crate graphics;
 extern crate opengl_graphics;
 extern crate glutin_window;
+extern crate chrono;
 
 pub mod editor;
 pub mod menu;
@@ -14,6 +15,9 @@ use std::sync::mpsc::channel;
 pub use self::editor::{EdBuffer, XBTVEd};
 //use super::program::{Source, Program, Instruction};
 use super::action;
+use super::query::Query;
+use super::tags::TagType;
+use super::bulk::{BulkEdit, Edit};
 
 use self::conrod::{
     Background,
@@ -35,6 +39,7 @@ use self::opengl_graphics::glyph_cache::GlyphCache;
 use self::piston::event::*;
 use self::piston::window::{WindowSettings, Size};
 use self::glutin_window::GlutinWindow;
+use self::chrono::Local;
 
 pub fn make_window(title: &str, width: u32, height: u32) -> (GlutinWindow, GlGraphics, Ui<GlyphCache>) {
     let opengl = OpenGL::_3_2;
@@ -163,7 +168,12 @@ pub fn confirm(msg: &str) -> bool {
 }    
 
 pub fn change_schedule_name() -> Option<String> {
-    let (window, mut gl, mut ui) = make_window("Add Schedule", 600, 200);
+    prompt("Add Schedule", "Schedule Name", "Please give a name for the schedule.")
+}
+
+/// Asks for a line of text. `empty_msg` is shown if Ok is clicked without one.
+pub fn prompt(title: &str, label: &str, empty_msg: &str) -> Option<String> {
+    let (window, mut gl, mut ui) = make_window(title, 600, 200);
 
     let light_bg = rgb(0.8, 0.8, 0.8);
     let (mut clicked, mut val) = (false, None);
@@ -175,7 +185,7 @@ pub fn change_schedule_name() -> Option<String> {
             gl.draw(args.viewport(), |_, gl| {
                 Background::new().color(light_bg.clone()).draw(&mut ui, gl);
 
-                Label::new("Schedule Name").position(5.0, 10.0)
+                Label::new(label).position(5.0, 10.0)
                     .size(18).color(light_bg.plain_contrast()).draw(&mut ui, gl);
 
                 TextBox::new(0, sched_name)
@@ -196,7 +206,7 @@ pub fn change_schedule_name() -> Option<String> {
                     .label("Ok")
                     .callback(|| {
                         if sched_name.len() == 0 {
-                            pop_up_msg("Please give a name for the schedule.")
+                            pop_up_msg(empty_msg)
                         } else { 
                             clicked = true;
                             val = Some(sched_name.clone());
@@ -266,6 +276,43 @@ pub fn draw_ui<'a>(gl: &mut GlGraphics, ui: &mut Ui<GlyphCache<'a>>, xbtved: &mu
         .color(label_color.clone())
         .draw(ui, gl);
 
+    if let Some(lineup) = xbtved.lineup() {
+        let now = Local::now().naive_local();
+        let on_now = lineup.whats_on(now).iter().map(|&(chan, prog)| {
+            format!("{} {}: {}", chan.number(), chan.name_ref(), match prog {
+                Some(p) => match p.get_tags().title {
+                    Some(ref title) => title.clone(),
+                    None => p.get_location().to_string()
+                },
+                None => "Off air".to_string()
+            })
+        }).collect::<Vec<String>>().join(" | ");
+
+        Label::new(&on_now)
+            .position(10.0, 120.0)
+            .size(16)
+            .color(label_color.clone())
+            .draw(ui, gl);
+    }
+
+    if !xbtved.current_buffer().is_undo_buffer_empty() || !xbtved.current_buffer().is_redo_buffer_empty() {
+        Label::new(&xbtved.current_buffer().history().to_string())
+            .position(700.0, 150.0)
+            .size(14)
+            .color(label_color.clone())
+            .draw(ui, gl);
+    }
+
+    if xbtved.current_buffer().conflicts().len() > 0 {
+        let conflicts = xbtved.current_buffer().conflicts().iter().map(|x| x.to_string())
+            .collect::<Vec<String>>().join("\n");
+        Label::new(&format!("Merge conflicts:\n{}", conflicts))
+            .position(10.0, 500.0)
+            .size(14)
+            .color(label_color.clone())
+            .draw(ui, gl);
+    }
+
     Button::new(100)
         .dimensions(200.0, 40.0)
         .position(50.0, 50.0)
@@ -281,7 +328,7 @@ pub fn draw_ui<'a>(gl: &mut GlGraphics, ui: &mut Ui<GlyphCache<'a>>, xbtved: &mu
                 let namechange = action::SetName::new(xbtved.current_buffer_mut(), &name);
                 match xbtved.current_buffer_mut().apply(namechange) {
                     Ok(_) => {},
-                    Err(ref f) => pop_up_msg(&f)
+                    Err(ref f) => pop_up_msg(&f.to_string())
                 }
             };
         }).draw(ui, gl);
@@ -313,7 +360,10 @@ pub fn draw_ui<'a>(gl: &mut GlGraphics, ui: &mut Ui<GlyphCache<'a>>, xbtved: &mu
         .frame(1.0)
         .label("Undo")
         .callback(|| {
-            xbtved.current_buffer_mut().undo();
+            if let Err(f) = xbtved.current_buffer_mut().undo() {
+                xbtved.current_buffer_mut().reset_history();
+                pop_up_msg(&format!("{}. The undo history has been cleared.", f));
+            }
         }).draw(ui, gl);
 
     Button::new(104)
@@ -323,6 +373,127 @@ pub fn draw_ui<'a>(gl: &mut GlGraphics, ui: &mut Ui<GlyphCache<'a>>, xbtved: &mu
         .frame(1.0)
         .label("Redo")
         .callback(|| {
-            xbtved.current_buffer_mut().redo();
+            if let Err(f) = xbtved.current_buffer_mut().redo() {
+                xbtved.current_buffer_mut().reset_history();
+                pop_up_msg(&format!("{}. The undo history has been cleared.", f));
+            }
+        }).draw(ui, gl);
+
+    Button::new(105)
+        .dimensions(100.0, 40.0)
+        .position(700.0, 100.0)
+        .color(button_color.clone())
+        .frame(1.0)
+        .label("Go to")
+        .callback(|| {
+            let change = prompt("Go to change", "Change number", "Please give a change number.");
+            match change.map(|x| x.trim().parse::<usize>()) {
+                Some(Ok(node)) => if let Err(f) = xbtved.current_buffer_mut().goto(node) {
+                    pop_up_msg(&f.to_string());
+                },
+                Some(Err(_)) => pop_up_msg("The change number must be a whole number."),
+                None => { }
+            }
+        }).draw(ui, gl);
+
+    Button::new(106)
+        .dimensions(100.0, 40.0)
+        .position(820.0, 100.0)
+        .color(button_color.clone())
+        .frame(1.0)
+        .label("Search")
+        .callback(|| {
+            let query = match prompt("Search", "Query", "Please give a query, such as genre = comedy.") {
+                Some(x) => x,
+                None => return
+            };
+            let buffer = xbtved.current_buffer();
+            match buffer.search(&query) {
+                Ok(ref found) if found.len() == 0 => pop_up_msg("No programs match."),
+                Ok(found) => {
+                    let lines = found.iter().map(|path| {
+                        let title = buffer.effective_tags(path).and_then(|x| x.title).unwrap_or(String::new());
+                        format!("{} {}", path, title)
+                    }).collect::<Vec<String>>();
+                    pop_up_msg(&lines.join("\n"))
+                },
+                Err(f) => pop_up_msg(&f.to_string())
+            }
+        }).draw(ui, gl);
+
+    Button::new(107)
+        .dimensions(100.0, 40.0)
+        .position(940.0, 100.0)
+        .color(button_color.clone())
+        .frame(1.0)
+        .label("Bulk edit")
+        .callback(|| {
+            let query = match prompt("Bulk edit", "Programs to change", "Please give a query, such as studio = Deen.") {
+                Some(x) => match Query::parse(&x) {
+                    Ok(q) => q,
+                    Err(f) => return pop_up_msg(&f.to_string())
+                },
+                None => return
+            };
+            let tag = match prompt("Bulk edit", "Tag to change", "Please give a tag, such as studio.") {
+                Some(x) => match x.trim().parse::<TagType>() {
+                    Ok(t) => t,
+                    Err(_) => return pop_up_msg(&format!("{} is not a tag.", x))
+                },
+                None => return
+            };
+            let edit = match prompt("Bulk edit", "set X, clear, append X or replace PATTERN => X",
+                                    "Please say how to change the tag.") {
+                Some(x) => match x.parse::<Edit>() {
+                    Ok(e) => e,
+                    Err(f) => return pop_up_msg(&f.to_string())
+                },
+                None => return
+            };
+
+            let bulk = BulkEdit::new(query, tag, edit);
+            let preview = match xbtved.current_buffer().bulk_preview(&bulk) {
+                Ok(x) => x,
+                Err(f) => return pop_up_msg(&f.to_string())
+            };
+            let count = preview.iter().filter(|x| !x.inherited).count();
+            let lines = preview.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+            if count == 0 {
+                return pop_up_msg(&format!("No programs would change.\n{}", lines.join("\n")))
+            }
+            if confirm(&format!("Change {} programs?\n{}", count, lines.join("\n"))) {
+                if let Err(f) = xbtved.current_buffer_mut().bulk_edit(&bulk) {
+                    pop_up_msg(&f.to_string());
+                }
+            }
+        }).draw(ui, gl);
+
+    Button::new(108)
+        .dimensions(100.0, 40.0)
+        .position(940.0, 50.0)
+        .color(button_color.clone())
+        .frame(1.0)
+        .label("Read tags")
+        .callback(|| {
+            match xbtved.current_buffer_mut().read_metadata() {
+                Ok((count, ref failed)) if failed.len() > 0 =>
+                    pop_up_msg(&format!("Filled in tags for {} programs. Could not read:\n{}", count, failed.join("\n"))),
+                Ok((count, _)) => pop_up_msg(&format!("Filled in tags for {} programs.", count)),
+                Err(f) => pop_up_msg(&f.to_string())
+            }
+        }).draw(ui, gl);
+
+    Button::new(109)
+        .dimensions(100.0, 40.0)
+        .position(1060.0, 50.0)
+        .color(button_color.clone())
+        .frame(1.0)
+        .label("Write NFOs")
+        .callback(|| {
+            match xbtved.current_buffer().write_nfos() {
+                (count, ref failed) if failed.len() > 0 =>
+                    pop_up_msg(&format!("Wrote {} NFO files. Could not write:\n{}", count, failed.join("\n"))),
+                (count, _) => pop_up_msg(&format!("Wrote {} NFO files.", count))
+            }
         }).draw(ui, gl);
 }
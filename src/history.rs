@@ -0,0 +1,244 @@
+//! The undo history of a buffer, kept as a tree so that undoing and then
+//! making a new edit starts a branch instead of throwing the old one away.
+
+extern crate chrono;
+
+use std::fmt;
+use self::chrono::{DateTime, Local};
+use super::action::Action;
+
+/// One state of the buffer. Every node but the root holds the action that led
+/// to it from its parent.
+pub struct Node {
+    action: Option<Box<Action>>,
+    label: String,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// The child that redo moves to: the one last created or visited.
+    redo_child: Option<usize>,
+    time: DateTime<Local>
+}
+
+impl<'a> Node {
+    /// The action leading to this node, or `None` for the root.
+    pub fn action(&'a self) -> Option<&'a Action> {
+        self.action.as_ref().map(|x| &**x)
+    }
+
+    pub fn label(&'a self) -> &'a str {
+        &self.label
+    }
+
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    pub fn children(&'a self) -> &'a [usize] {
+        &self.children
+    }
+
+    /// When the action was first applied, or when the tree was made for the
+    /// root.
+    pub fn time(&self) -> DateTime<Local> {
+        self.time
+    }
+}
+
+/// Nodes are numbered in the order they were made, starting from the root
+/// at 0, so a node's number is also its change number.
+pub struct UndoTree {
+    nodes: Vec<Node>,
+    current: usize
+}
+
+impl<'a> UndoTree {
+    pub fn new() -> UndoTree {
+        UndoTree {
+            nodes: vec!(Node {
+                action: None,
+                label: "Original".to_string(),
+                parent: None,
+                children: Vec::new(),
+                redo_child: None,
+                time: Local::now()
+            }),
+            current: 0
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn get(&'a self, node: usize) -> Option<&'a Node> {
+        self.nodes.get(node)
+    }
+
+    /// The node for the buffer's present state.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.nodes[self.current].parent.is_some()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.nodes[self.current].redo_child.is_some()
+    }
+
+    /// The action leading to the current node, if it is not the root.
+    pub fn current_action(&'a self) -> Option<&'a Action> {
+        self.nodes[self.current].action.as_ref().map(|x| &**x)
+    }
+
+    /// Adds a node for `action` below the current one and moves to it.
+    pub fn push(&mut self, action: Box<Action>) -> usize {
+        let idx = self.nodes.len();
+        let parent = self.current;
+        self.nodes.push(Node {
+            label: action.label(),
+            action: Some(action),
+            parent: Some(parent),
+            children: Vec::new(),
+            redo_child: None,
+            time: Local::now()
+        });
+        self.nodes[parent].children.push(idx);
+        self.nodes[parent].redo_child = Some(idx);
+        self.current = idx;
+        idx
+    }
+
+    /// Swaps the action of the current node for `action`, as when two edits
+    /// are merged. Does nothing at the root or once the node has branches.
+    pub fn replace_current(&mut self, action: Box<Action>) -> Result<(), Box<Action>> {
+        let node = &mut self.nodes[self.current];
+        if node.parent.is_none() || node.children.len() > 0 {
+            return Err(action)
+        }
+        node.label = action.label();
+        node.action = Some(action);
+        Ok(())
+    }
+
+    /// Takes the action of `node` out of the tree so it can be run against the
+    /// buffer owning the tree. It must be given back with `restore_action`.
+    pub fn take_action(&mut self, node: usize) -> Option<Box<Action>> {
+        self.nodes.get_mut(node).and_then(|x| x.action.take())
+    }
+
+    pub fn restore_action(&mut self, node: usize, action: Box<Action>) {
+        self.nodes[node].action = Some(action);
+    }
+
+    /// Moves the current node, after its action has been undone or redone.
+    /// Moving down to a child makes it the one redo picks next.
+    pub fn set_current(&mut self, node: usize) {
+        if let Some(parent) = self.nodes[node].parent {
+            if self.current == parent {
+                self.nodes[parent].redo_child = Some(node);
+            }
+        }
+        self.current = node;
+    }
+
+    /// The child redo would move to.
+    pub fn redo_target(&self) -> Option<usize> {
+        self.nodes[self.current].redo_child
+    }
+
+    /// The nodes from the root down to `node`, both included.
+    pub fn ancestry(&self, node: usize) -> Vec<usize> {
+        let mut path = vec!(node);
+        while let Some(parent) = self.nodes[path[path.len() - 1]].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    /// How to get from the current node to `target`: the nodes to undo, in
+    /// order, and then the nodes to redo, in order.
+    pub fn route(&self, target: usize) -> (Vec<usize>, Vec<usize>) {
+        let from = self.ancestry(self.current);
+        let to = self.ancestry(target);
+        let common = from.iter().zip(to.iter()).take_while(|&(x, y)| x == y).count();
+
+        let up = from[common ..].iter().rev().cloned().collect();
+        let down = to[common ..].to_vec();
+        (up, down)
+    }
+
+    /// The labels of the actions from the root to the current node.
+    pub fn undo_labels(&self) -> Vec<String> {
+        self.ancestry(self.current)[1 ..].iter().map(|&x| self.nodes[x].label.clone()).collect()
+    }
+
+    /// The labels of the actions redo would go through, next first.
+    pub fn redo_labels(&self) -> Vec<String> {
+        let mut labels = Vec::new();
+        let mut node = self.current;
+        while let Some(child) = self.nodes[node].redo_child {
+            labels.push(self.nodes[child].label.clone());
+            node = child;
+        }
+        labels
+    }
+
+    /// The leaves of the tree, like vim's `:undolist`: every branch ends at one.
+    pub fn leaves(&self) -> Vec<usize> {
+        (0 .. self.nodes.len()).filter(|&x| self.nodes[x].children.len() == 0).collect()
+    }
+}
+
+fn write_node(tree: &UndoTree, node: usize, depth: usize, fmt: &mut fmt::Formatter) -> fmt::Result {
+    let n = &tree.nodes[node];
+    let indent = (0 .. depth).map(|_| "  ").collect::<String>();
+    let marker = if node == tree.current { "*" } else { " " };
+    try!(writeln!(fmt, "{}{} {:>3} {} {}", indent, marker, node, n.time.format("%H:%M:%S"), n.label));
+    for &child in n.children.iter() {
+        try!(write_node(tree, child, depth + 1, fmt));
+    }
+    Ok(())
+}
+
+impl fmt::Display for UndoTree {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write_node(self, 0, 0, fmt)
+    }
+}
+
+#[test]
+fn branches_survive() {
+    use super::EdBuffer;
+    use super::action::SetName;
+    use super::schedule::Schedule;
+
+    let mut buffer = EdBuffer::from_schedule(&Schedule::new("a", Vec::new()));
+    let rename = SetName::new(&buffer, "b");
+    buffer.apply(rename).unwrap();
+    buffer.break_coalescing();
+    let rename = SetName::new(&buffer, "c");
+    buffer.apply(rename).unwrap();
+
+    buffer.undo().unwrap();
+    buffer.undo().unwrap();
+    let rename = SetName::new(&buffer, "d");
+    buffer.apply(rename).unwrap();
+    assert_eq!(buffer.history().len(), 4);
+    assert_eq!(buffer.history().leaves(), vec!(2, 3));
+
+    // Across to the end of the other branch.
+    buffer.goto(2).unwrap();
+    assert_eq!(buffer.get_name(), "c");
+    buffer.undo().unwrap();
+    buffer.redo().unwrap();
+    assert_eq!(buffer.get_name(), "c");
+    assert!(buffer.verify_history().is_ok());
+
+    buffer.goto(3).unwrap();
+    assert_eq!(buffer.get_name(), "d");
+    assert!(buffer.goto(9).is_err());
+    assert_eq!(buffer.history().get(3).unwrap().parent(), Some(0));
+}
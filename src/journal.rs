@@ -0,0 +1,226 @@
+//! A record of the edits made to a buffer since it was last saved, kept next
+//! to the schedule's file so the edits can be recovered after a crash.
+//!
+//! The first line holds the saved schedule the edits start from. Every line
+//! after it holds the label of an edit and the whole schedule after it,
+//! separated by a tab, with backslashes, tabs and newlines escaped.
+//!
+//! Whole schedules are kept rather than actions so that recovering gives back
+//! exactly what the user last saw, even when the file has changed since and an
+//! action would no longer apply to it the same way. The cost is size, so once
+//! the journal holds `MAX_ENTRIES` edits it is rewritten with only the latest
+//! `KEPT_ENTRIES`: nothing is lost but the steps to undo the older edits.
+
+use std::io::{Error, ErrorKind, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use super::schedule::Schedule;
+use super::storage::write_atomic;
+use super::parse;
+
+/// How many edits a journal holds before it is compacted.
+pub const MAX_ENTRIES: usize = 64;
+
+/// How many of the latest edits are kept when the journal is compacted.
+pub const KEPT_ENTRIES: usize = 16;
+
+/// The journal for the schedule saved at `path`: the same name with
+/// `.journal` added.
+pub fn journal_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".journal");
+    PathBuf::from(name)
+}
+
+fn escape(s: &str) -> String {
+    s.replace("\\", "\\\\").replace("\t", "\\t").replace("\n", "\\n")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => out.push('\t'),
+                Some('n') => out.push('\n'),
+                Some(x) => out.push(x),
+                None => out.push('\\')
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn entry_line(label: &str, sched: &Schedule) -> String {
+    format!("{}\t{}\n", escape(label), escape(&sched.to_string()))
+}
+
+fn parse_schedule(s: &str) -> Result<Schedule, Error> {
+    parse::parse(&unescape(s)).map_err(|f| Error::new(ErrorKind::InvalidData, f.to_string().as_str()))
+}
+
+/// What a journal file holds.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Recovery {
+    /// The saved schedule the edits were made to.
+    pub base: Schedule,
+    /// The label of every edit and the schedule after it, oldest first.
+    pub entries: Vec<(String, Schedule)>
+}
+
+impl Recovery {
+    /// Reads the journal at `path`, if there is one. An entry cut short by a
+    /// crash while it was written is left out.
+    pub fn read(path: &Path) -> Result<Option<Recovery>, Error> {
+        let mut file = match File::open(path) {
+            Ok(x) => x,
+            Err(ref f) if f.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(f) => return Err(f)
+        };
+        let mut s = String::new();
+        try!(file.read_to_string(&mut s));
+
+        let mut lines = s.lines();
+        let base = match lines.next() {
+            Some(x) => try!(parse_schedule(x)),
+            None => return Ok(None)
+        };
+
+        let mut entries = Vec::new();
+        for line in lines {
+            let mut parts = line.splitn(2, '\t');
+            match (parts.next(), parts.next().map(parse_schedule)) {
+                (Some(label), Some(Ok(sched))) => entries.push((unescape(label), sched)),
+                _ => break
+            }
+        }
+        Ok(Some(Recovery { base: base, entries: entries }))
+    }
+}
+
+pub struct Journal {
+    path: PathBuf,
+    /// The schedule to write as the first line, until an edit is recorded.
+    pending: Option<Schedule>,
+    /// How many edits the file holds.
+    entries: usize
+}
+
+impl<'a> Journal {
+    /// A journal for edits to `base`, saved at `path`. Nothing is written
+    /// until the first edit is recorded.
+    pub fn new(path: &Path, base: &Schedule) -> Journal {
+        Journal {
+            path: journal_path(path),
+            pending: Some(base.clone()),
+            entries: 0
+        }
+    }
+
+    pub fn path(&'a self) -> &'a Path {
+        &self.path
+    }
+
+    /// Appends an edit, starting the file first if this is the first one, and
+    /// compacting it when it is full.
+    pub fn record(&mut self, label: &str, sched: &Schedule) -> Result<(), Error> {
+        let mut file = match self.pending {
+            Some(ref base) => {
+                let mut file = try!(File::create(&self.path));
+                try!(file.write_all(format!("{}\n", escape(&base.to_string())).as_bytes()));
+                file
+            },
+            None => try!(OpenOptions::new().append(true).open(&self.path))
+        };
+        self.pending = None;
+        try!(file.write_all(entry_line(label, sched).as_bytes()));
+        try!(file.sync_data());
+        self.entries += 1;
+        if self.entries >= MAX_ENTRIES {
+            try!(self.compact());
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file with only the latest `KEPT_ENTRIES` edits.
+    fn compact(&mut self) -> Result<(), Error> {
+        let recovery = match try!(Recovery::read(&self.path)) {
+            Some(x) => x,
+            None => return Ok(())
+        };
+        let kept = &recovery.entries[recovery.entries.len().saturating_sub(KEPT_ENTRIES) ..];
+        let mut contents = format!("{}\n", escape(&recovery.base.to_string()));
+        for &(ref label, ref sched) in kept.iter() {
+            contents.push_str(&entry_line(label, sched));
+        }
+        try!(write_atomic(&self.path, contents.as_bytes()));
+        self.entries = kept.len();
+        Ok(())
+    }
+
+    /// Deletes the file and starts over from `base`, as after a save.
+    pub fn clear(&mut self, base: &Schedule) -> Result<(), Error> {
+        self.pending = Some(base.clone());
+        self.entries = 0;
+        match fs::remove_file(&self.path) {
+            Err(ref f) if f.kind() == ErrorKind::NotFound => Ok(()),
+            x => x
+        }
+    }
+}
+
+#[test]
+fn journal_round_trip() {
+    use std::env;
+    use super::program::Program;
+
+    let path = env::temp_dir().join("xbtved-journal-test.xbtv");
+    let base = Schedule::new("base", vec!(Program::example()));
+    let mut journal = Journal::new(&path, &base);
+    journal.clear(&base).unwrap();
+    assert_eq!(Recovery::read(journal.path()).unwrap(), None);
+
+    let mut edited = base.clone();
+    edited.set_name("tab\tand \\ newline\n");
+    journal.record("Rename schedule", &edited).unwrap();
+    edited.add_program(&Program::example());
+    journal.record("Add program", &edited).unwrap();
+
+    // A write cut short by a crash.
+    let mut file = OpenOptions::new().append(true).open(journal.path()).unwrap();
+    file.write_all(b"Delete program 0\t(schedule \"tru").unwrap();
+
+    let recovery = Recovery::read(journal.path()).unwrap().unwrap();
+    assert_eq!(recovery.base, base);
+    assert_eq!(recovery.entries.len(), 2);
+    assert_eq!(recovery.entries[0].1.get_name(), "tab\tand \\ newline\n");
+    assert_eq!(recovery.entries[1], ("Add program".to_string(), edited));
+
+    journal.clear(&base).unwrap();
+    assert!(!journal.path().exists());
+}
+
+#[test]
+fn journal_compaction() {
+    use std::env;
+    use super::program::Program;
+
+    let path = env::temp_dir().join("xbtved-journal-compaction-test.xbtv");
+    let base = Schedule::new("base", vec!(Program::example()));
+    let mut journal = Journal::new(&path, &base);
+    let mut edited = base.clone();
+    for n in 0 .. MAX_ENTRIES + 1 {
+        edited.set_name(&format!("edit {}", n));
+        journal.record(&format!("Rename to edit {}", n), &edited).unwrap();
+    }
+
+    let recovery = Recovery::read(journal.path()).unwrap().unwrap();
+    assert_eq!(recovery.base, base);
+    assert_eq!(recovery.entries.len(), KEPT_ENTRIES + 1);
+    assert_eq!(recovery.entries[0].0, format!("Rename to edit {}", MAX_ENTRIES - KEPT_ENTRIES));
+    assert_eq!(recovery.entries[KEPT_ENTRIES], (format!("Rename to edit {}", MAX_ENTRIES), edited));
+    journal.clear(&base).unwrap();
+}
@@ -3,16 +3,37 @@
 #![feature(core, convert, collections, unboxed_closures)]
 #[deny(warnings)]
 
+pub mod error;
 pub mod action;
+pub mod history;
+pub mod journal;
+pub mod storage;
+pub mod diff;
+pub mod merge;
+pub mod cli;
 pub mod parse;
 pub mod schedule;
 pub mod program;
 pub mod tags;
+pub mod track;
 pub mod blocks;
+pub mod channel;
+pub mod recurrence;
+pub mod analysis;
+pub mod solver;
+pub mod rating;
+pub mod path;
+pub mod query;
+pub mod bulk;
+pub mod metadata;
 pub mod gui;
 
 pub use gui::EdBuffer;
 
 fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    if let Some(status) = cli::run(&args[1 ..]) {
+        std::process::exit(status);
+    }
     gui::draw_gui();
 }
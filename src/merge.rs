@@ -0,0 +1,459 @@
+//! Three-way merging of schedules, for when two people edit the same one.
+//!
+//! Both sides are diffed against the common base. Edits to different programs,
+//! tags or instructions are combined; where both sides changed the same thing
+//! differently our side is kept and a `Conflict` is reported.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use super::schedule::Schedule;
+use super::program::{Instruction, Program};
+use super::tags::{Tags, TagType};
+use super::path::ProgramPath;
+use super::diff::diff;
+use super::storage::{read_schedule, write_atomic};
+
+/// Something both sides changed differently. Our version is the one kept in
+/// the merged schedule.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Conflict {
+    /// The program the conflict is in, or `None` for the schedule itself.
+    pub path: Option<ProgramPath>,
+    pub what: String,
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>
+}
+
+fn show(val: &Option<String>) -> String {
+    match *val {
+        Some(ref x) => format!("\"{}\"", x),
+        None => "nothing".to_string()
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.path {
+            Some(ref path) => try!(write!(fmt, "program {}, ", path)),
+            None => try!(write!(fmt, "schedule, "))
+        }
+        try!(write!(fmt, "{}: base {}, ours {}, theirs {}", self.what, show(&self.base), show(&self.ours),
+                    show(&self.theirs)));
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Merge {
+    pub schedule: Schedule,
+    pub conflicts: Vec<Conflict>
+}
+
+impl Merge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.len() == 0
+    }
+}
+
+/// A conflict inside a program, before the program's place in the merged
+/// schedule is known.
+struct Pending {
+    instrs: Vec<usize>,
+    what: String,
+    base: Option<String>,
+    ours: Option<String>,
+    theirs: Option<String>
+}
+
+/// Compares by the written form, which leaves out editing cursors.
+fn same<T: fmt::Display>(x: &T, y: &T) -> bool {
+    x.to_string() == y.to_string()
+}
+
+fn compatible(x: &Instruction, y: &Instruction) -> bool {
+    match (x.subprogram(), y.subprogram()) {
+        (Some(a), Some(b)) => a.get_location() == b.get_location(),
+        (None, None) => true,
+        _ => false
+    }
+}
+
+/// A tag both sides set differently, with its base, our and their values.
+type Clash = (TagType, Option<String>, Option<String>, Option<String>);
+
+fn merge_tags(base: &Tags, ours: &Tags, theirs: &Tags, clashes: &mut Vec<Clash>) -> Tags {
+    let mut tags = ours.clone();
+    for tag in TagType::all_in(&[base, ours, theirs]).into_iter() {
+        let (b, o, t) = (base.get_tag(&tag), ours.get_tag(&tag), theirs.get_tag(&tag));
+        if o == t || t == b {
+            continue
+        } else if o == b {
+            tags.copy_tag(theirs, &tag);
+        } else {
+            clashes.push((tag, b, o, t));
+        }
+    }
+    tags
+}
+
+fn merge_program(base: &Program, ours: &Program, theirs: &Program, at: &[usize], pending: &mut Vec<Pending>)
+                 -> Program {
+    let mut clashes = Vec::new();
+    let tags = merge_tags(base.get_tags(), ours.get_tags(), theirs.get_tags(), &mut clashes);
+    pending.extend(clashes.into_iter().map(|(tag, b, o, t)| {
+        Pending { instrs: at.to_vec(), what: format!("tag {}", tag), base: b, ours: o, theirs: t }
+    }));
+
+    let (bi, oi, ti) = (base.get_instrs(), ours.get_instrs(), theirs.get_instrs());
+    let unchanged = |x: &Vec<Instruction>, y: &Vec<Instruction>| {
+        x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| same(a, b))
+    };
+
+    let mut instrs = oi.clone();
+    if unchanged(oi, ti) || unchanged(ti, bi) {
+        // Ours already has the instructions.
+    } else if unchanged(oi, bi) {
+        instrs = ti.clone();
+    } else if bi.len() == oi.len() && bi.len() == ti.len()
+        && (0 .. bi.len()).all(|k| compatible(&bi[k], &oi[k]) && compatible(&bi[k], &ti[k])) {
+        // The same instructions were edited in place; merge them one by one.
+        for k in 0 .. bi.len() {
+            match (bi[k].subprogram(), oi[k].subprogram(), ti[k].subprogram()) {
+                (Some(b), Some(o), Some(t)) => {
+                    let mut nested_at = at.to_vec();
+                    nested_at.push(k);
+                    let sub = merge_program(b, o, t, &nested_at, pending);
+                    instrs[k] = Instruction::SubProgram(sub);
+                },
+                _ => {
+                    if same(&oi[k], &ti[k]) || same(&ti[k], &bi[k]) {
+                        continue
+                    } else if same(&oi[k], &bi[k]) {
+                        instrs[k] = ti[k].clone();
+                    } else {
+                        pending.push(Pending {
+                            instrs: at.to_vec(),
+                            what: format!("instruction {}", k),
+                            base: Some(bi[k].to_string()),
+                            ours: Some(oi[k].to_string()),
+                            theirs: Some(ti[k].to_string())
+                        });
+                    }
+                }
+            }
+        }
+    } else {
+        let list = |x: &Vec<Instruction>| x.iter().map(|y| y.to_string()).collect::<Vec<String>>().join(" ");
+        pending.push(Pending {
+            instrs: at.to_vec(),
+            what: "instructions".to_string(),
+            base: Some(list(bi)),
+            ours: Some(list(oi)),
+            theirs: Some(list(ti))
+        });
+    }
+
+    Program::new(ours.get_location().clone(), tags, instrs)
+}
+
+/// Where a program in the merged schedule came from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Origin {
+    Base(usize),
+    Ours(usize),
+    Theirs(usize)
+}
+
+/// Merges the edits made in `ours` and `theirs` since `base`.
+pub fn merge(base: &Schedule, ours: &Schedule, theirs: &Schedule) -> Merge {
+    let (base_progs, our_progs, their_progs) = (base.programs(), ours.programs(), theirs.programs());
+    let (our_diff, their_diff) = (diff(base, ours), diff(base, theirs));
+    let (our_match, their_match) = (our_diff.matching(base_progs.len()), their_diff.matching(base_progs.len()));
+    let mut conflicts = Vec::new();
+
+    let name = if ours.name_ref() == theirs.name_ref() || theirs.name_ref() == base.name_ref() {
+        ours.get_name()
+    } else if ours.name_ref() == base.name_ref() {
+        theirs.get_name()
+    } else {
+        conflicts.push(Conflict {
+            path: None,
+            what: "name".to_string(),
+            base: Some(base.get_name()),
+            ours: Some(ours.get_name()),
+            theirs: Some(theirs.get_name())
+        });
+        ours.get_name()
+    };
+
+    let mut clashes = Vec::new();
+    let defaults = merge_tags(base.defaults(), ours.defaults(), theirs.defaults(), &mut clashes);
+    conflicts.extend(clashes.into_iter().map(|(tag, b, o, t)| Conflict {
+        path: None,
+        what: format!("default tag {}", tag),
+        base: b,
+        ours: o,
+        theirs: t
+    }));
+
+    // What becomes of each base program, and the conflicts found inside it.
+    let mut kept: Vec<Option<Program>> = Vec::new();
+    let mut pending: Vec<(usize, Pending)> = Vec::new();
+    for b in 0 .. base_progs.len() {
+        let removed = |by: &str, other: &Program| Pending {
+            instrs: Vec::new(),
+            what: format!("program deleted by {} but changed by the other side", by),
+            base: Some(base_progs[b].to_string()),
+            ours: if by == "us" { None } else { Some(other.to_string()) },
+            theirs: if by == "them" { None } else { Some(other.to_string()) }
+        };
+
+        kept.push(match (our_match[b], their_match[b]) {
+            (Some(o), Some(t)) => {
+                let mut found = Vec::new();
+                let prog = merge_program(&base_progs[b], &our_progs[o], &their_progs[t], &[], &mut found);
+                pending.extend(found.into_iter().map(|x| (b, x)));
+                Some(prog)
+            },
+            (Some(o), None) if !same(&our_progs[o], &base_progs[b]) => {
+                pending.push((b, removed("them", &our_progs[o])));
+                Some(our_progs[o].clone())
+            },
+            (None, Some(t)) if !same(&their_progs[t], &base_progs[b]) => {
+                pending.push((b, removed("us", &their_progs[t])));
+                Some(their_progs[t].clone())
+            },
+            _ => None
+        });
+    }
+
+    // The side that reordered programs decides the order. If both did and
+    // disagree, ours wins.
+    let reordered = |d: &super::diff::ScheduleDiff| d.changes.iter().any(|x| match *x {
+        super::diff::Change::Moved(_, _, _) => true,
+        _ => false
+    });
+    let theirs_lead = reordered(&their_diff) && !reordered(&our_diff);
+    if reordered(&their_diff) && reordered(&our_diff) {
+        let common = |matching: &Vec<Option<usize>>, other: &Vec<Option<usize>>| {
+            let mut order = (0 .. base_progs.len()).filter(|&b| matching[b].is_some() && other[b].is_some())
+                .collect::<Vec<usize>>();
+            order.sort_by(|x, y| matching[*x].cmp(&matching[*y]));
+            order
+        };
+        if common(&our_match, &their_match) != common(&their_match, &our_match) {
+            conflicts.push(Conflict {
+                path: None,
+                what: "program order".to_string(),
+                base: None,
+                ours: None,
+                theirs: None
+            });
+        }
+    }
+
+    let origins = |progs: &Vec<Program>, matching: &Vec<Option<usize>>, side: fn(usize) -> Origin| {
+        (0 .. progs.len()).map(|k| match matching.iter().position(|&x| x == Some(k)) {
+            Some(b) => Origin::Base(b),
+            None => side(k)
+        }).collect::<Vec<Origin>>()
+    };
+    let our_origins = origins(&our_progs, &our_match, Origin::Ours);
+    let their_origins = origins(&their_progs, &their_match, Origin::Theirs);
+    let (lead, follow) = if theirs_lead { (&their_origins, &our_origins) } else { (&our_origins, &their_origins) };
+
+    let program = |origin: Origin| match origin {
+        Origin::Base(b) => kept[b].clone(),
+        Origin::Ours(k) => Some(our_progs[k].clone()),
+        Origin::Theirs(k) => Some(their_progs[k].clone())
+    };
+
+    let mut merged: Vec<(Origin, Program)> = lead.iter().filter_map(|&x| program(x).map(|p| (x, p))).collect();
+    for (k, &origin) in follow.iter().enumerate() {
+        if merged.iter().any(|&(x, _)| x == origin) {
+            continue
+        }
+        let prog = match program(origin) {
+            Some(x) => x,
+            None => continue
+        };
+        // Both sides adding the same program counts once.
+        if merged.iter().any(|&(x, ref p)| !matches_base(x) && same(p, &prog)) {
+            continue
+        }
+        let after = follow[.. k].iter().rev()
+            .filter_map(|x| merged.iter().position(|&(y, _)| y == *x)).next();
+        let at = match after {
+            Some(idx) => idx + 1,
+            None => 0
+        };
+        merged.insert(at, (origin, prog));
+    }
+
+    for (b, p) in pending.into_iter() {
+        let top = merged.iter().position(|&(x, _)| x == Origin::Base(b));
+        conflicts.push(Conflict {
+            path: top.map(|idx| {
+                let mut indices = vec!(idx);
+                indices.extend(p.instrs.into_iter());
+                ProgramPath::from_indices(&indices).unwrap()
+            }),
+            what: p.what,
+            base: p.base,
+            ours: p.ours,
+            theirs: p.theirs
+        });
+    }
+
+    let mut schedule = Schedule::new(&name, merged.into_iter().map(|(_, p)| p).collect());
+    schedule.set_defaults(&defaults);
+    Merge {
+        schedule: schedule,
+        conflicts: conflicts
+    }
+}
+
+fn matches_base(origin: Origin) -> bool {
+    match origin {
+        Origin::Base(_) => true,
+        _ => false
+    }
+}
+
+/// Runs as a git merge driver, given `%O %A %B` and optionally `%P`. The
+/// merge is written over `%A`. Conflicts are printed, and also written next
+/// to `%P` with `.conflicts` added, so the editor can show them when the file
+/// is opened; a clean merge removes any left by an earlier one. Returns the
+/// exit status: 0 for a clean merge, 1 for conflicts and 2 if the files could
+/// not be read or written.
+pub fn merge_driver(args: &[String]) -> i32 {
+    if args.len() < 3 {
+        let _ = writeln!(io::stderr(), "usage: XBTVEd merge BASE OURS THEIRS [PATH]");
+        return 2
+    }
+
+    let mut read = Vec::new();
+    for path in args[.. 3].iter() {
+        match read_schedule(Path::new(path)) {
+            Ok(x) => read.push(x),
+            Err(f) => {
+                let _ = writeln!(io::stderr(), "{}: {}", path, f);
+                return 2
+            }
+        }
+    }
+    let (base, ours, theirs) = (&read[0], &read[1], &read[2]);
+
+    let merged = merge(base, ours, theirs);
+    if let Err(f) = write_atomic(Path::new(&args[1]), merged.schedule.to_string().as_bytes()) {
+        let _ = writeln!(io::stderr(), "{}: {}", args[1], f);
+        return 2
+    }
+    if merged.is_clean() {
+        if let Some(path) = args.get(3) {
+            if let Err(f) = clear_conflicts(Path::new(path)) {
+                let _ = writeln!(io::stderr(), "{}: {}", path, f);
+            }
+        }
+        return 0
+    }
+
+    let report = merged.conflicts.iter().map(|x| format!("{}\n", x)).collect::<String>();
+    let _ = write!(io::stderr(), "{}", report);
+    if let Some(path) = args.get(3) {
+        if let Err(f) = write_atomic(&conflicts_path(Path::new(path)), report.as_bytes()) {
+            let _ = writeln!(io::stderr(), "{}: {}", path, f);
+        }
+    }
+    1
+}
+
+/// Where the merge driver leaves the conflicts it found in the file at `path`.
+pub fn conflicts_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".conflicts");
+    PathBuf::from(name)
+}
+
+/// Removes the conflicts the merge driver left for the file at `path`, once
+/// they have been seen or merged away. Nothing left is not an error.
+pub fn clear_conflicts(path: &Path) -> io::Result<()> {
+    match fs::remove_file(conflicts_path(path)) {
+        Err(ref f) if f.kind() == ErrorKind::NotFound => Ok(()),
+        x => x
+    }
+}
+
+#[test]
+fn independent_edits_merge() {
+    let parse = |text: &str| super::parse::parse(text).unwrap();
+    let base = parse("(schedule \"week\"
+ (program (local \"a\") (tags :title \"A\") (instr (play)))
+ (program (local \"b\") (tags :title \"B\") (instr (play)))
+ (program (local \"c\") (tags :title \"C\") (instr (play))))");
+
+    // We retitle a and add d after b; they rename the schedule, retitle b,
+    // drop c and add e at the start.
+    let ours = parse("(schedule \"week\"
+ (program (local \"a\") (tags :title \"Aa\") (instr (play)))
+ (program (local \"b\") (tags :title \"B\") (instr (play)))
+ (program (local \"d\") (tags :title \"D\") (instr (play)))
+ (program (local \"c\") (tags :title \"C\") (instr (play))))");
+    let theirs = parse("(schedule \"week 2\"
+ (program (local \"e\") (tags :title \"E\") (instr (play)))
+ (program (local \"a\") (tags :title \"A\") (instr (play)))
+ (program (local \"b\") (tags :title \"Bb\") (instr (play))))");
+
+    let merged = merge(&base, &ours, &theirs);
+    assert!(merged.is_clean());
+    assert_eq!(merged.schedule.to_string(), parse("(schedule \"week 2\"
+ (program (local \"e\") (tags :title \"E\") (instr (play)))
+ (program (local \"a\") (tags :title \"Aa\") (instr (play)))
+ (program (local \"b\") (tags :title \"Bb\") (instr (play)))
+ (program (local \"d\") (tags :title \"D\") (instr (play))))").to_string());
+
+    // Both retitle b.
+    let ours = parse(&base.to_string().replace("\"B\"", "\"Ours\""));
+    let theirs = parse(&base.to_string().replace("\"B\"", "\"Theirs\""));
+    let merged = merge(&base, &ours, &theirs);
+    assert_eq!(merged.conflicts, vec!(Conflict {
+        path: Some(ProgramPath::new(1)),
+        what: "tag title".to_string(),
+        base: Some("B".to_string()),
+        ours: Some("Ours".to_string()),
+        theirs: Some("Theirs".to_string())
+    }));
+    assert_eq!(merged.schedule.get_program_at(1).unwrap().get_tags().title, Some("Ours".to_string()));
+}
+
+#[test]
+fn driver_conflict_report() {
+    use std::env;
+
+    let dir = env::temp_dir();
+    let files = ["base", "ours", "theirs"].iter().map(|x| dir.join(format!("xbtved-merge-test.{}", x)))
+        .collect::<Vec<PathBuf>>();
+    let target = dir.join("xbtved-merge-test.xbtv");
+    let text = |title: &str| format!("(schedule \"week\" (program (local \"a\") (tags :title \"{}\") \
+                                      (instr (play 00:00:00 00:00:00))))", title);
+    let args = files.iter().chain(Some(&target).into_iter()).map(|x| x.to_string_lossy().into_owned())
+        .collect::<Vec<String>>();
+
+    for (file, title) in files.iter().zip(["A", "Ours", "Theirs"].iter()) {
+        write_atomic(file, text(title).as_bytes()).unwrap();
+    }
+    assert_eq!(merge_driver(&args), 1);
+    assert!(fs::metadata(conflicts_path(&target)).is_ok());
+
+    for (file, title) in files.iter().zip(["A", "A", "Theirs"].iter()) {
+        write_atomic(file, text(title).as_bytes()).unwrap();
+    }
+    assert_eq!(merge_driver(&args), 0);
+    assert!(fs::metadata(conflicts_path(&target)).is_err());
+    for file in files.iter() {
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+//! How long a media file plays, read from its headers rather than by
+//! decoding it: the segment info of Matroska and WebM, the last granule
+//! position of Ogg, the `mvhd` box of MP4, the frame headers of MP3 and the
+//! format and data chunks of WAV.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use super::{vorbis, matroska, mp4};
+use super::{read_bytes, be_u32, le_u32};
+
+thread_local!(static DURATIONS: RefCell<HashMap<PathBuf, (SystemTime, u64, Option<usize>)>> =
+              RefCell::new(HashMap::new()));
+
+/// Kilobits per second, by version (MPEG-1 or later), layer and index.
+const BITRATES: [[[u32; 15]; 3]; 2] = [
+    [[0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+     [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+     [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320]],
+    [[0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+     [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+     [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160]]];
+
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// Finds the first MPEG audio frame after any ID3 tag. A Xing, Info or VBRI
+/// header in it gives the number of frames of a variable bitrate file;
+/// otherwise the bitrate is taken to hold for the whole file.
+fn mp3<R: Read + Seek>(file: &mut R) -> io::Result<Option<f64>> {
+    let len = try!(file.seek(SeekFrom::End(0)));
+    try!(file.seek(SeekFrom::Start(0)));
+    let mut start = 0;
+    let id3 = try!(read_bytes(file, 10));
+    if id3.starts_with(b"ID3") {
+        start = 10 + ((id3[6] as u64 & 0x7F) << 21 | (id3[7] as u64 & 0x7F) << 14 |
+                      (id3[8] as u64 & 0x7F) << 7 | id3[9] as u64 & 0x7F);
+    }
+    try!(file.seek(SeekFrom::Start(start)));
+    let mut head = Vec::new();
+    try!(file.by_ref().take(64 * 1024).read_to_end(&mut head));
+
+    let found = (0 .. head.len().saturating_sub(4)).find(|&i| head[i] == 0xFF && head[i + 1] & 0xE0 == 0xE0
+                                                          && head[i + 1] & 0x06 != 0 && head[i + 2] & 0xF0 != 0xF0
+                                                          && head[i + 2] & 0x0C != 0x0C);
+    let pos = match found {
+        Some(x) => x,
+        None => return Ok(None)
+    };
+    let frame = &head[pos ..];
+    // 3 is MPEG-1, 2 is MPEG-2 and 0 is MPEG-2.5.
+    let version = (frame[1] >> 3) & 3;
+    let layer = 3 - ((frame[1] >> 1) & 3) as usize;
+    let bitrate = BITRATES[if version == 3 { 0 } else { 1 }][layer][(frame[2] >> 4) as usize] * 1000;
+    let rate = SAMPLE_RATES[((frame[2] >> 2) & 3) as usize] >> match version { 3 => 0, 2 => 1, _ => 2 };
+    let samples = match (version, layer) {
+        (_, 0) => 384,
+        (3, _) | (_, 1) => 1152,
+        _ => 576
+    };
+
+    let mono = frame[3] >> 6 == 3;
+    let side = match (version == 3, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9
+    };
+    let xing = 4 + side;
+    let frames = if frame.len() >= xing + 12 && (&frame[xing .. xing + 4] == b"Xing" || &frame[xing .. xing + 4] == b"Info")
+        && be_u32(&frame[xing + 4 ..]) & 1 != 0 {
+        Some(be_u32(&frame[xing + 8 ..]))
+    } else if frame.len() >= 36 + 18 && &frame[36 .. 40] == b"VBRI" {
+        Some(be_u32(&frame[36 + 14 ..]))
+    } else {
+        None
+    };
+
+    Ok(match frames {
+        Some(n) => Some(n as f64 * samples as f64 / rate as f64),
+        None if bitrate > 0 => Some((len - start - pos as u64) as f64 * 8.0 / bitrate as f64),
+        None => None
+    })
+}
+
+/// A WAV file is a RIFF of chunks; `fmt ` gives the bytes per second and
+/// `data` holds the samples.
+fn wav<R: Read + Seek>(file: &mut R) -> io::Result<Option<f64>> {
+    try!(file.seek(SeekFrom::Start(12)));
+    let mut byte_rate = None;
+    loop {
+        let mut header = Vec::new();
+        try!(file.by_ref().take(8).read_to_end(&mut header));
+        if header.len() < 8 {
+            return Ok(None)
+        }
+        let size = le_u32(&header[4 ..]) as u64;
+        match &String::from_utf8_lossy(&header[.. 4])[..] {
+            "fmt " if size >= 12 => {
+                let fmt = try!(read_bytes(file, 12));
+                byte_rate = Some(le_u32(&fmt[8 ..]));
+                try!(file.seek(SeekFrom::Current(size as i64 - 12 + (size & 1) as i64)));
+            },
+            "data" => return Ok(byte_rate.and_then(|x| if x == 0 { None } else { Some(size as f64 / x as f64) })),
+            _ => { try!(file.seek(SeekFrom::Current(size as i64 + (size & 1) as i64))); }
+        }
+    }
+}
+
+/// The length of an opened file in seconds, telling its format from its first
+/// bytes. `None` when the format is not known here or its headers do not say.
+pub fn probe_from<R: Read + Seek>(file: &mut R) -> io::Result<Option<f64>> {
+    let mut magic = Vec::new();
+    try!(file.by_ref().take(12).read_to_end(&mut magic));
+    try!(file.seek(SeekFrom::Start(0)));
+
+    if magic.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        matroska::duration(file)
+    } else if magic.starts_with(b"OggS") {
+        vorbis::ogg_duration(file)
+    } else if magic.len() >= 8 && &magic[4 .. 8] == b"ftyp" {
+        mp4::duration(file)
+    } else if magic.len() >= 12 && magic.starts_with(b"RIFF") && &magic[8 .. 12] == b"WAVE" {
+        wav(file)
+    } else if magic.starts_with(b"ID3") || (magic.len() >= 2 && magic[0] == 0xFF && magic[1] & 0xE0 == 0xE0) {
+        mp3(file)
+    } else {
+        Ok(None)
+    }
+}
+
+/// The length of the file at `path`, rounded to whole seconds. What is read
+/// is kept until the file's size or modification time changes, so asking
+/// again is cheap.
+pub fn duration(path: &Path) -> Option<usize> {
+    let (modified, len) = match fs::metadata(path).and_then(|x| x.modified().map(|when| (when, x.len()))) {
+        Ok(x) => x,
+        Err(_) => return None
+    };
+    DURATIONS.with(|cache| {
+        if let Some(&(when, size, secs)) = cache.borrow().get(path) {
+            if when == modified && size == len {
+                return secs
+            }
+        }
+        let secs = match File::open(path).and_then(|mut file| probe_from(&mut file)) {
+            Ok(Some(x)) if x >= 0.0 => Some(x.round() as usize),
+            _ => None
+        };
+        cache.borrow_mut().insert(path.to_path_buf(), (modified, len, secs));
+        secs
+    })
+}
+
+#[test]
+fn header_durations() {
+    use std::io::Cursor;
+
+    let probe = |bytes: Vec<u8>| probe_from(&mut Cursor::new(bytes)).unwrap();
+
+    // Two seconds of 8 kHz, 16 bit mono.
+    let mut wav = b"RIFF\x00\x00\x00\x00WAVEfmt \x10\x00\x00\x00\x01\x00\x01\x00\x40\x1f\x00\x00\x80\x3e\x00\x00\
+                    \x02\x00\x10\x00data\x00\x7d\x00\x00".to_vec();
+    wav.extend(vec!(0; 32000).into_iter());
+    assert_eq!(probe(wav), Some(2.0));
+
+    // 128 kbps, 44.1 kHz MPEG-1 layer III: 16000 bytes a second.
+    let mut mp3 = vec!(0xFF, 0xFB, 0x90, 0x00);
+    mp3.extend(vec!(0; 48000 - 4).into_iter());
+    assert_eq!(probe(mp3), Some(3.0));
+
+    // 600 units of a 1000 Hz timescale.
+    let mvhd = b"\x00\x00\x00\x1cmvhd\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                 \x00\x00\x03\xe8\x00\x09\x27\xc0";
+    let mut mp4 = b"\x00\x00\x00\x0cftypmp42\x00\x00\x00\x24moov".to_vec();
+    mp4.extend(mvhd.iter().cloned());
+    assert_eq!(probe(mp4), Some(600.0));
+
+    // A timecode scale of a millisecond and a duration of 90000.0 as an f32,
+    // in the segment info of a segment of unknown size.
+    let mkv = b"\x1a\x45\xdf\xa3\x84\x42\x86\x81\x01\x18\x53\x80\x67\xff\x15\x49\xa9\x66\x8e\
+                \x2a\xd7\xb1\x83\x0f\x42\x40\x44\x89\x84\x47\xaf\xc8\x00\x1f\x43\xb6\x75\xff";
+    assert_eq!(probe(mkv.to_vec()), Some(90.0));
+
+    // 8 kHz Vorbis whose last page is 16000 samples in, followed by a page of
+    // another stream further along.
+    let page = |serial: u8, granule: u64, packet: &[u8]| {
+        let mut page = b"OggS\x00\x00".to_vec();
+        page.extend((0 .. 8).map(|i| (granule >> (8 * i)) as u8));
+        page.extend([serial, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, packet.len() as u8].iter().cloned());
+        page.extend(packet.iter().cloned());
+        page
+    };
+    let mut ogg = page(1, 0, b"\x01vorbis\x00\x00\x00\x00\x01\x40\x1f\x00\x00\x00\x00\x00\x00");
+    ogg.extend(page(1, 16000, b"audio").into_iter());
+    ogg.extend(page(2, 999999, b"video").into_iter());
+    assert_eq!(probe(ogg), Some(2.0));
+
+    assert_eq!(probe(b"plain text".to_vec()), None);
+}
+
+#[test]
+fn cached_durations() {
+    use std::env;
+    use std::io::Write;
+
+    let path = env::temp_dir().join("xbtved-duration-test.wav");
+    let write = |secs: u32| {
+        let mut wav = b"RIFF\x00\x00\x00\x00WAVEfmt \x10\x00\x00\x00\x01\x00\x01\x00\x40\x1f\x00\x00\x80\x3e\x00\x00\
+                        \x02\x00\x10\x00data".to_vec();
+        wav.extend((0 .. 4).map(|i| ((secs * 16000) >> (8 * i)) as u8));
+        wav.extend(vec!(0; secs as usize * 16000).into_iter());
+        File::create(&path).unwrap().write_all(&wav).unwrap();
+    };
+
+    write(2);
+    assert_eq!(duration(&path), Some(2));
+    // A file rewritten within the same second keeps its modification time,
+    // but not its size.
+    write(3);
+    assert_eq!(duration(&path), Some(3));
+    // A program without a runtime tag runs for as long as its file does.
+    let sched = format!("(schedule \"test\" (program (local \"{}\") (instr (play) (play 00:00:01 00:00:00))))",
+                        path.display());
+    let sched = super::super::parse::parse(&sched).unwrap();
+    assert_eq!(sched.running_time(), 5);
+    DURATIONS.with(|cache| cache.borrow_mut().get_mut(&path).unwrap().2 = Some(7));
+    assert_eq!(duration(&path), Some(7));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,154 @@
+//! ID3v2 tags, versions 2.2 to 2.4, as found at the start of MP3 files. Only
+//! text frames are read.
+
+use std::io::{self, Read};
+use super::super::tags::TagType;
+use super::{Field, read_bytes, be_u32};
+
+/// The genres of ID3v1, which later versions still refer to by number.
+pub const GENRES: [&'static str; 80] = [
+    "Blues", "Classic Rock", "Country", "Dance", "Disco", "Funk", "Grunge", "Hip-Hop", "Jazz", "Metal",
+    "New Age", "Oldies", "Other", "Pop", "R&B", "Rap", "Reggae", "Rock", "Techno", "Industrial",
+    "Alternative", "Ska", "Death Metal", "Pranks", "Soundtrack", "Euro-Techno", "Ambient", "Trip-Hop",
+    "Vocal", "Jazz+Funk", "Fusion", "Trance", "Classical", "Instrumental", "Acid", "House", "Game",
+    "Sound Clip", "Gospel", "Noise", "Alternative Rock", "Bass", "Soul", "Punk", "Space", "Meditative",
+    "Instrumental Pop", "Instrumental Rock", "Ethnic", "Gothic", "Darkwave", "Techno-Industrial",
+    "Electronic", "Pop-Folk", "Eurodance", "Dream", "Southern Rock", "Comedy", "Cult", "Gangsta",
+    "Top 40", "Christian Rap", "Pop/Funk", "Jungle", "Native American", "Cabaret", "New Wave",
+    "Psychedelic", "Rave", "Showtunes", "Trailer", "Lo-Fi", "Tribal", "Acid Punk", "Acid Jazz", "Polka",
+    "Retro", "Musical", "Rock & Roll", "Hard Rock"];
+
+/// The genre numbered `n` in the ID3v1 list.
+pub fn genre_name(n: usize) -> Option<String> {
+    GENRES.get(n).map(|x| x.to_string())
+}
+
+fn frame_tag(id: &[u8]) -> Option<TagType> {
+    Some(match &String::from_utf8_lossy(id)[..] {
+        "TIT2" | "TT2" => TagType::Title,
+        "TPE1" | "TP1" => TagType::Artist,
+        "TALB" | "TAL" => TagType::Album,
+        "TCON" | "TCO" => TagType::Genre,
+        "TRCK" | "TRK" => TagType::TrackNo,
+        "TCOM" | "TCM" => TagType::Composer,
+        "TYER" | "TYE" | "TDRC" => TagType::Airdate,
+        _ => return None
+    })
+}
+
+fn syncsafe(b: &[u8]) -> usize {
+    (b[0] as usize & 0x7F) << 21 | (b[1] as usize & 0x7F) << 14 | (b[2] as usize & 0x7F) << 7 | b[3] as usize & 0x7F
+}
+
+/// Undoes unsynchronisation, which puts a zero after every 0xFF.
+fn resync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut last = 0;
+    for &b in data.iter() {
+        if !(last == 0xFF && b == 0) {
+            out.push(b);
+        }
+        last = b;
+    }
+    out
+}
+
+fn utf16(data: &[u8], big_endian: bool) -> String {
+    let units = data.chunks(2).filter(|x| x.len() == 2).map(|x| {
+        if big_endian { (x[0] as u16) << 8 | x[1] as u16 } else { (x[1] as u16) << 8 | x[0] as u16 }
+    }).collect::<Vec<u16>>();
+    String::from_utf16_lossy(&units)
+}
+
+/// The values of a text frame. Version 2.4 separates several values with
+/// nulls.
+fn decode_text(data: &[u8]) -> Vec<String> {
+    if data.len() == 0 {
+        return Vec::new()
+    }
+    let rest = &data[1 ..];
+    let text = match data[0] {
+        0 => rest.iter().map(|&b| b as char).collect::<String>(),
+        1 => utf16(rest, rest.starts_with(&[0xFE, 0xFF])),
+        2 => utf16(rest, true),
+        _ => String::from_utf8_lossy(rest).into_owned()
+    };
+    text.split('\0').map(|x| x.trim_matches('\u{FEFF}').trim().to_string()).filter(|x| x.len() > 0).collect()
+}
+
+/// Genres are written as names, as `(17)` referring to the ID3v1 list,
+/// `(17)Punk Rock` refining one, or in 2.4 as a bare number.
+fn genre(value: &str) -> Option<String> {
+    if value.starts_with("(") && !value.starts_with("((") {
+        if let Some(end) = value.find(')') {
+            let refined = value[end + 1 ..].trim();
+            return if refined.len() > 0 {
+                Some(refined.to_string())
+            } else {
+                value[1 .. end].parse::<usize>().ok().and_then(genre_name)
+            }
+        }
+    }
+    match value.parse::<usize>() {
+        Ok(n) => genre_name(n),
+        Err(_) => Some(value.to_string())
+    }
+}
+
+pub fn read<R: Read>(file: &mut R) -> io::Result<Vec<Field>> {
+    let header = try!(read_bytes(file, 10));
+    let (version, flags) = (header[3], header[5]);
+    let mut body = try!(read_bytes(file, syncsafe(&header[6 .. 10])));
+    if flags & 0x80 != 0 && version < 4 {
+        body = resync(&body);
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 && version >= 3 && body.len() >= 4 {
+        pos = if version == 3 { be_u32(&body[.. 4]) as usize + 4 } else { syncsafe(&body[.. 4]) };
+    }
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+
+    let mut fields = Vec::new();
+    while pos + header_len <= body.len() && body[pos] != 0 {
+        let frame = &body[pos .. pos + header_len];
+        let size = match version {
+            2 => (frame[3] as usize) << 16 | (frame[4] as usize) << 8 | frame[5] as usize,
+            3 => be_u32(&frame[4 .. 8]) as usize,
+            _ => syncsafe(&frame[4 .. 8])
+        };
+        let start = pos + header_len;
+        if start + size > body.len() {
+            break
+        }
+        pos = start + size;
+
+        let tag = match frame_tag(&frame[.. id_len]) {
+            Some(x) => x,
+            None => continue
+        };
+        let format = if version == 2 { 0 } else { frame[9] };
+        let mut data = body[start .. start + size].to_vec();
+        match version {
+            3 if format & 0xC0 != 0 => continue,
+            4 if format & 0x0C != 0 => continue,
+            4 => {
+                if format & 0x02 != 0 {
+                    data = resync(&data);
+                }
+                if format & 0x01 != 0 && data.len() >= 4 {
+                    data = data[4 ..].to_vec();
+                }
+            },
+            _ => { }
+        }
+
+        for value in decode_text(&data).into_iter() {
+            let value = if tag == TagType::Genre { genre(&value) } else { Some(value) };
+            if let Some(x) = value {
+                fields.push((tag.clone(), x));
+            }
+        }
+    }
+    Ok(fields)
+}
@@ -0,0 +1,149 @@
+//! Matroska and WebM files: the segment title and the simple tags of the
+//! `Tags` element. Elements that cannot hold either, such as clusters of
+//! video, are skipped over without being read.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::mem;
+use std::u64;
+use super::super::tags::TagType;
+use super::{Field, invalid, read_bytes};
+
+const SEGMENT: u64 = 0x18538067;
+const INFO: u64 = 0x1549A966;
+const TIMECODE_SCALE: u64 = 0x2AD7B1;
+const DURATION: u64 = 0x4489;
+const CLUSTER: u64 = 0x1F43B675;
+const TITLE: u64 = 0x7BA9;
+const TAGS: u64 = 0x1254C367;
+const TAG: u64 = 0x7373;
+const SIMPLE_TAG: u64 = 0x67C8;
+const TAG_NAME: u64 = 0x45A3;
+const TAG_STRING: u64 = 0x4487;
+
+fn simple_tag(name: &str) -> Option<TagType> {
+    Some(match name {
+        "TITLE" => TagType::Title,
+        "ARTIST" => TagType::Artist,
+        "DIRECTOR" => TagType::Director,
+        "COMPOSER" => TagType::Composer,
+        "PRODUCTION_STUDIO" => TagType::Studio,
+        "ACTOR" => TagType::Cast,
+        "GENRE" => TagType::Genre,
+        "DATE_RELEASED" | "DATE_RECORDED" => TagType::Airdate,
+        _ => return None
+    })
+}
+
+/// Reads a variable length integer: the number of leading zeros in the first
+/// byte gives how many bytes follow. IDs keep the length marker; sizes drop
+/// it, and a size of all ones means unknown. `None` at the end of the file.
+fn vint<R: Read>(file: &mut R, keep_marker: bool) -> io::Result<Option<u64>> {
+    let mut first = Vec::new();
+    try!(file.by_ref().take(1).read_to_end(&mut first));
+    if first.len() == 0 {
+        return Ok(None)
+    }
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(invalid("Bad Matroska element"))
+    }
+    let mask = (0xFFu16 >> len) as u8;
+    let mut value = if keep_marker { first[0] as u64 } else { (first[0] & mask) as u64 };
+    let mut all_ones = first[0] | !mask == 0xFF;
+    for &b in try!(read_bytes(file, len - 1)).iter() {
+        value = value << 8 | b as u64;
+        all_ones = all_ones && b == 0xFF;
+    }
+    Ok(Some(if all_ones && !keep_marker { u64::MAX } else { value }))
+}
+
+/// Walks the elements up to `end`, descending into those that lead to tags.
+fn walk<R: Read + Seek>(file: &mut R, end: u64, name: &mut Option<String>, fields: &mut Vec<Field>)
+                        -> io::Result<()> {
+    loop {
+        let pos = try!(file.seek(SeekFrom::Current(0)));
+        if pos >= end {
+            return Ok(())
+        }
+        let id = match try!(vint(file, true)) {
+            Some(x) => x,
+            None => return Ok(())
+        };
+        let size = match try!(vint(file, false)) {
+            Some(x) => x,
+            None => return Ok(())
+        };
+        let start = try!(file.seek(SeekFrom::Current(0)));
+        let inner_end = if size == u64::MAX { end } else { start + size };
+
+        match id {
+            SEGMENT | INFO | TAGS | TAG => try!(walk(file, inner_end, name, fields)),
+            SIMPLE_TAG => {
+                *name = None;
+                try!(walk(file, inner_end, name, fields));
+            },
+            TITLE | TAG_NAME | TAG_STRING if size != u64::MAX => {
+                let text = String::from_utf8_lossy(&try!(read_bytes(file, size as usize))).into_owned();
+                match id {
+                    TITLE => fields.push((TagType::Title, text)),
+                    TAG_NAME => *name = Some(text.to_uppercase()),
+                    _ => if let Some(tag) = name.as_ref().and_then(|x| simple_tag(x)) {
+                        fields.push((tag, text));
+                    }
+                }
+            },
+            // Anything else of unknown size, like a live stream's clusters,
+            // cannot be skipped.
+            _ if size == u64::MAX => return Ok(()),
+            _ => { }
+        }
+        if inner_end == end && size == u64::MAX {
+            return Ok(())
+        }
+        try!(file.seek(SeekFrom::Start(inner_end)));
+    }
+}
+
+pub fn read<R: Read + Seek>(file: &mut R) -> io::Result<Vec<Field>> {
+    let mut fields = Vec::new();
+    try!(walk(file, u64::MAX, &mut None, &mut fields));
+    Ok(fields)
+}
+
+/// The segment's duration, a float counted in units of the timecode scale,
+/// which is in nanoseconds. Both are in the segment info, before any cluster.
+pub fn duration<R: Read + Seek>(file: &mut R) -> io::Result<Option<f64>> {
+    let (mut scale, mut length) = (1000000.0, None);
+    let mut info_end = u64::MAX;
+    loop {
+        if try!(file.seek(SeekFrom::Current(0))) >= info_end {
+            break
+        }
+        let (id, size) = match (try!(vint(file, true)), try!(vint(file, false))) {
+            (Some(id), Some(size)) => (id, size),
+            _ => break
+        };
+        let start = try!(file.seek(SeekFrom::Current(0)));
+        match id {
+            SEGMENT => continue,
+            INFO => {
+                info_end = if size == u64::MAX { u64::MAX } else { start + size };
+                continue
+            },
+            TIMECODE_SCALE if size <= 8 => {
+                scale = try!(read_bytes(file, size as usize)).iter().fold(0, |n, &b| n << 8 | b as u64) as f64;
+            },
+            DURATION if size == 4 || size == 8 => {
+                let bits = try!(read_bytes(file, size as usize)).iter().fold(0, |n, &b| n << 8 | b as u64);
+                length = Some(unsafe {
+                    if size == 4 { mem::transmute::<u32, f32>(bits as u32) as f64 } else { mem::transmute::<u64, f64>(bits) }
+                });
+            },
+            CLUSTER => break,
+            _ if size == u64::MAX => break,
+            _ => { }
+        }
+        try!(file.seek(SeekFrom::Start(start + size)));
+    }
+    Ok(length.map(|x| x * scale / 1e9))
+}
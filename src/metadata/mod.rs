@@ -0,0 +1,329 @@
+//! Reading the tags media files carry inside them: ID3v2 in MP3s, Vorbis
+//! comments in Ogg, Opus and FLAC files, Matroska and WebM tags, and the
+//! iTunes style atoms of MP4s. What a file says only fills in tags a program
+//! does not already have, so nothing typed by hand is overwritten. A file's
+//! length is read from its headers too, for programs without a runtime tag.
+//! An `.nfo` file beside the media, as Kodi and Jellyfin keep, is read before
+//! the file itself, and can be written back from the program's tags.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use super::schedule::Schedule;
+use super::program::Source;
+use super::path::ProgramPath;
+use super::tags::{Tags, TagType};
+use super::error;
+
+mod id3;
+mod vorbis;
+mod matroska;
+mod mp4;
+mod duration;
+pub mod nfo;
+
+pub use self::duration::{duration, probe_from};
+
+/// Tags larger than this are taken to be corrupt rather than read into memory.
+const MAX_TAG_SIZE: usize = 16 * 1024 * 1024;
+
+/// A tag and a value for it, as found in a file.
+type Field = (TagType, String);
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn read_bytes<R: Read>(file: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    if len > MAX_TAG_SIZE {
+        return Err(invalid("The tag is too large"))
+    }
+    let mut buf = Vec::new();
+    try!(file.by_ref().take(len as u64).read_to_end(&mut buf));
+    if buf.len() < len {
+        Err(invalid("The file ends in the middle of its tags"))
+    } else {
+        Ok(buf)
+    }
+}
+
+fn be_u16(b: &[u8]) -> u16 {
+    (b[0] as u16) << 8 | b[1] as u16
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    (b[3] as u32) << 24 | (b[2] as u32) << 16 | (b[1] as u32) << 8 | b[0] as u32
+}
+
+/// The number before any `/`, as in a track number of `3/12`.
+fn leading_number(s: &str) -> &str {
+    s.split('/').next().unwrap_or("").trim()
+}
+
+/// Puts the fields into tags. The first value found for a tag wins, except
+/// for tags holding several values, which gather them all. Values the tags
+/// cannot hold are skipped; a badly tagged file is not worth failing over.
+fn to_tags(fields: Vec<Field>) -> Tags {
+    let mut tags = Tags::new();
+    for (tag, value) in fields.into_iter() {
+        let value = value.trim();
+        if value.len() == 0 {
+            continue
+        }
+        match tag {
+            TagType::Airdate => {
+                if tags.year.is_none() && value.len() >= 4 && value.is_char_boundary(4) {
+                    let _ = tags.modify_tag(&TagType::Year, &value[.. 4]);
+                }
+                // A full date, or failing that the month.
+                for &len in [10, 7].iter() {
+                    if tags.airdate.is_none() && value.len() >= len && value.is_char_boundary(len) {
+                        let _ = tags.modify_tag(&TagType::Airdate, &value[.. len]);
+                    }
+                }
+            },
+            TagType::TrackNo | TagType::Season | TagType::Episode => if tags.get_tag(&tag).is_none() {
+                let _ = tags.modify_tag(&tag, leading_number(value));
+            },
+            ref x if x.is_multi() => {
+                let _ = tags.add_value(x, value);
+            },
+            x => if tags.get_tag(&x).is_none() {
+                let _ = tags.modify_tag(&x, value);
+            }
+        }
+    }
+    tags
+}
+
+/// Reads the tags of a file already opened, telling its format from its first
+/// bytes. Formats not known here give no tags.
+pub fn read_from<R: Read + Seek>(file: &mut R) -> io::Result<Tags> {
+    let mut magic = Vec::new();
+    try!(file.by_ref().take(12).read_to_end(&mut magic));
+    try!(file.seek(SeekFrom::Start(0)));
+
+    let fields = if magic.starts_with(b"ID3") {
+        try!(id3::read(file))
+    } else if magic.starts_with(b"fLaC") {
+        try!(vorbis::read_flac(file))
+    } else if magic.starts_with(b"OggS") {
+        try!(vorbis::read_ogg(file))
+    } else if magic.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        try!(matroska::read(file))
+    } else if magic.len() >= 8 && &magic[4 .. 8] == b"ftyp" {
+        try!(mp4::read(file))
+    } else {
+        Vec::new()
+    };
+    Ok(to_tags(fields))
+}
+
+/// Reads the tags of the file at `path`.
+pub fn read(path: &Path) -> error::Result<Tags> {
+    let mut file = try!(File::open(path));
+    Ok(try!(read_from(&mut file)))
+}
+
+/// Where a program's file is, with a leading `~` standing for the home
+/// directory. URLs have no local file.
+pub fn local_path(source: &Source) -> Option<PathBuf> {
+    match source.path() {
+        Some(x) if x.starts_with("~/") => env::home_dir().map(|home| home.join(&x[2 ..])),
+        Some(x) => Some(PathBuf::from(x)),
+        None => None
+    }
+}
+
+/// For every program in `sched` whose file carries tags the program lacks,
+/// its path and its own tags with those filled in. Files whose tags cannot be
+/// read are listed separately, each with the reason, but still give what their
+/// NFO holds and their length.
+pub fn fill_schedule(sched: &Schedule) -> (Vec<(ProgramPath, Tags)>, Vec<String>) {
+    let (mut filled, mut failed) = (Vec::new(), Vec::new());
+    for path in ProgramPath::all(sched) {
+        let prog = match path.get(sched) {
+            Some(x) => x,
+            None => continue
+        };
+        let file = match local_path(prog.get_location()) {
+            Some(x) => x,
+            None => continue
+        };
+        let mut found = match nfo::read(&file) {
+            Ok(x) => x.unwrap_or(Tags::new()),
+            Err(f) => {
+                failed.push(format!("{}: {}", nfo::sidecar(&file).display(), f));
+                Tags::new()
+            }
+        };
+        match read(&file) {
+            Ok(embedded) => {
+                found.fill_from(&embedded);
+            },
+            Err(f) => failed.push(format!("{}: {}", file.display(), f))
+        }
+        if found.runtime.is_none() {
+            found.runtime = duration(&file);
+        }
+        let mut tags = prog.get_tags().clone();
+        if tags.fill_from(&found).len() > 0 {
+            filled.push((path, tags));
+        }
+    }
+    (filled, failed)
+}
+
+/// Writes an NFO beside the file of every program in `sched` with one, from
+/// the tags that apply to it. Gives how many were written, and the ones that
+/// could not be, each with the reason.
+pub fn write_nfos(sched: &Schedule) -> (usize, Vec<String>) {
+    let (mut written, mut failed) = (Vec::new(), Vec::new());
+    for path in ProgramPath::all(sched) {
+        let file = match path.get(sched).and_then(|x| local_path(x.get_location())) {
+            Some(x) => x,
+            None => continue
+        };
+        if written.contains(&file) || fs::metadata(&file).is_err() {
+            continue
+        }
+        let tags = match sched.effective_tags(&path) {
+            Some(x) => x,
+            None => continue
+        };
+        match nfo::write(&file, &tags) {
+            Ok(()) => written.push(file),
+            Err(f) => failed.push(format!("{}: {}", nfo::sidecar(&file).display(), f))
+        }
+    }
+    (written.len(), failed)
+}
+
+#[cfg(test)]
+fn read_bytes_as_tags(bytes: Vec<u8>) -> Tags {
+    read_from(&mut ::std::io::Cursor::new(bytes)).unwrap()
+}
+
+#[test]
+fn embedded_tags() {
+    let text = |id: &str, value: &str| {
+        let mut frame = id.as_bytes().to_vec();
+        frame.extend([0, 0, 0, value.len() as u8 + 1, 0, 0, 3].iter().cloned());
+        frame.extend(value.as_bytes().iter().cloned());
+        frame
+    };
+    let mut frames = Vec::new();
+    for frame in [text("TIT2", "Punk"), text("TPE1", "Gorillaz"), text("TCON", "(17)"),
+                  text("TRCK", "5/15"), text("TDRC", "1998-03-23")].iter() {
+        frames.extend(frame.iter().cloned());
+    }
+    let mut id3 = b"ID3\x04\x00\x00\x00\x00\x00".to_vec();
+    id3.push(frames.len() as u8);
+    id3.extend(frames.into_iter());
+    let tags = read_bytes_as_tags(id3);
+    assert_eq!(tags.title, Some("Punk".to_string()));
+    assert_eq!(tags.artists(), &["Gorillaz".to_string()]);
+    assert_eq!(tags.genres(), &["Rock".to_string()]);
+    assert_eq!(tags.track_no, Some(5));
+    assert_eq!(tags.get_tag(&TagType::Airdate), Some("1998-03-23".to_string()));
+    assert_eq!(tags.year, Some(1998));
+    assert_eq!(to_tags(vec!((TagType::Airdate, "198€".to_string()))).year, None);
+
+    let comments = ["TITLE=Clint Eastwood", "GENRE=Trip-Hop", "GENRE=Rap"];
+    let mut flac = b"fLaC\x84\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    flac.extend([comments.len() as u8, 0, 0, 0].iter().cloned());
+    for comment in comments.iter() {
+        flac.extend([comment.len() as u8, 0, 0, 0].iter().cloned());
+        flac.extend(comment.as_bytes().iter().cloned());
+    }
+    let len = flac.len() - 8;
+    flac[7] = len as u8;
+    let tags = read_bytes_as_tags(flac);
+    assert_eq!(tags.title, Some("Clint Eastwood".to_string()));
+    assert_eq!(tags.genres(), &["Trip-Hop".to_string(), "Rap".to_string()]);
+
+    // Theora with Vorbis audio, whose header page comes between the video's.
+    let page = |serial: u8, packet: &[u8]| {
+        let mut page = b"OggS\x00\x02\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        page.extend([serial, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, packet.len() as u8].iter().cloned());
+        page.extend(packet.iter().cloned());
+        page
+    };
+    let mut theora = b"\x81theora\x00\x00\x00\x00\x01\x00\x00\x00".to_vec();
+    theora.extend([20, 0, 0, 0].iter().cloned());
+    theora.extend(b"TITLE=Clint Eastwood".iter().cloned());
+    let mut ogg = page(1, b"\x80theora");
+    ogg.extend(page(2, b"\x01vorbis").into_iter());
+    ogg.extend(page(1, &theora).into_iter());
+    assert_eq!(read_bytes_as_tags(ogg).title, Some("Clint Eastwood".to_string()));
+
+    let simple_tag = |name: &str, value: &str| {
+        let mut tag = vec!(0x67, 0xC8, 0x80 | (name.len() + value.len() + 6) as u8);
+        tag.extend([0x45, 0xA3, 0x80 | name.len() as u8].iter().cloned());
+        tag.extend(name.as_bytes().iter().cloned());
+        tag.extend([0x44, 0x87, 0x80 | value.len() as u8].iter().cloned());
+        tag.extend(value.as_bytes().iter().cloned());
+        tag
+    };
+    let mut inner = simple_tag("DIRECTOR", "Shinsuke Sato");
+    inner.extend(simple_tag("PRODUCTION_STUDIO", "Studio Deen").into_iter());
+    let mut mkv = vec!(0x1A, 0x45, 0xDF, 0xA3, 0x80, 0x18, 0x53, 0x80, 0x67, 0xFF,
+                       0x12, 0x54, 0xC3, 0x67, 0x80 | (inner.len() + 3) as u8, 0x73, 0x73, 0x80 | inner.len() as u8);
+    mkv.extend(inner.into_iter());
+    let tags = read_bytes_as_tags(mkv);
+    assert_eq!(tags.directors(), &["Shinsuke Sato".to_string()]);
+    assert_eq!(tags.studios(), &["Studio Deen".to_string()]);
+
+    let boxed = |kind: &[u8], body: Vec<u8>| {
+        let len = 8 + body.len();
+        let mut out = (0 .. 4).rev().map(|i| (len >> (8 * i)) as u8).collect::<Vec<u8>>();
+        out.extend(kind.iter().cloned());
+        out.extend(body.into_iter());
+        out
+    };
+    let item = |kind: &[u8], value: &[u8]| {
+        let mut data = vec!(0, 0, 0, 1, 0, 0, 0, 0);
+        data.extend(value.iter().cloned());
+        boxed(kind, boxed(b"data", data))
+    };
+    let mut ilst = item(b"\xA9nam", b"Fate/stay night");
+    // Artwork is not read, however large.
+    ilst.extend(item(b"covr", &vec!(0; MAX_TAG_SIZE + 1)).into_iter());
+    ilst.extend(item(b"tves", b"\x00\x00\x00\x07").into_iter());
+    let mut meta = vec!(0; 4);
+    meta.extend(boxed(b"ilst", ilst).into_iter());
+    let mut mp4 = b"\x00\x00\x00\x0cftypmp42".to_vec();
+    mp4.extend(boxed(b"moov", boxed(b"udta", boxed(b"meta", meta))).into_iter());
+    let tags = read_bytes_as_tags(mp4);
+    assert_eq!(tags.title, Some("Fate/stay night".to_string()));
+    assert_eq!(tags.episode, Some(7));
+
+    let mut typed = Tags::new();
+    typed.title = Some("My title".to_string());
+    assert_eq!(typed.fill_from(&tags), vec!(TagType::Episode));
+    assert_eq!(typed.title, Some("My title".to_string()));
+}
+
+#[test]
+fn unreadable_tags() {
+    use std::io::Write;
+
+    let media = env::temp_dir().join("xbtved-fill-test.mp3");
+    // An ID3 header promising more than the file holds.
+    File::create(&media).unwrap().write_all(b"ID3\x04\x00\x00\x00\x00\x01\x00").unwrap();
+    File::create(nfo::sidecar(&media)).unwrap().write_all(b"<movie><title>Night Train</title></movie>").unwrap();
+    let sched = format!("(schedule \"test\" (program (local \"{}\") (instr (play))))", media.display());
+    let sched = super::parse::parse(&sched).unwrap();
+
+    let (filled, failed) = fill_schedule(&sched);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(filled.len(), 1);
+    assert_eq!(filled[0].1.title, Some("Night Train".to_string()));
+    fs::remove_file(nfo::sidecar(&media)).unwrap();
+    fs::remove_file(&media).unwrap();
+}
@@ -0,0 +1,144 @@
+//! MP4 and M4A files, whose tags are the items of `moov/udta/meta/ilst` in
+//! the form iTunes writes them.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use super::super::tags::TagType;
+use super::id3::genre_name;
+use super::{Field, invalid, read_bytes, be_u16, be_u32};
+
+/// Box types are four bytes, some starting with 0xA9 (`©`), so they are read
+/// as Latin-1.
+fn kind_name(kind: &[u8]) -> String {
+    kind.iter().map(|&b| b as char).collect()
+}
+
+/// The types of the items `item_field` reads. Others, like artwork, are
+/// skipped without being read.
+const ITEMS: [&'static str; 11] = ["\u{A9}nam", "\u{A9}ART", "\u{A9}alb", "\u{A9}gen", "\u{A9}wrt", "\u{A9}day",
+                                  "tvsh", "tvsn", "tves", "trkn", "gnre"];
+
+/// The field an item holds, given its type and the payload of its `data` box.
+fn item_field(kind: &str, value: &[u8]) -> Option<Field> {
+    let text = || String::from_utf8_lossy(value).into_owned();
+    let number = |at: usize, len: usize| if value.len() >= at + len {
+        let n = if len == 2 { be_u16(&value[at ..]) as u32 } else { be_u32(&value[at ..]) };
+        Some(n.to_string())
+    } else {
+        None
+    };
+    match kind {
+        "\u{A9}nam" => Some((TagType::Title, text())),
+        "\u{A9}ART" => Some((TagType::Artist, text())),
+        "\u{A9}alb" => Some((TagType::Album, text())),
+        "\u{A9}gen" => Some((TagType::Genre, text())),
+        "\u{A9}wrt" => Some((TagType::Composer, text())),
+        "\u{A9}day" => Some((TagType::Airdate, text())),
+        "tvsh" => Some((TagType::Series, text())),
+        "tvsn" => number(0, 4).map(|x| (TagType::Season, x)),
+        "tves" => number(0, 4).map(|x| (TagType::Episode, x)),
+        "trkn" => number(2, 2).map(|x| (TagType::TrackNo, x)),
+        "gnre" if value.len() >= 2 => match be_u16(value) {
+            0 => None,
+            n => genre_name(n as usize - 1).map(|x| (TagType::Genre, x))
+        },
+        _ => None
+    }
+}
+
+/// Reads a box header, giving the type and where the box ends.
+fn header<R: Read + Seek>(file: &mut R, end: u64) -> io::Result<Option<(String, u64)>> {
+    let start = try!(file.seek(SeekFrom::Current(0)));
+    if start + 8 > end {
+        return Ok(None)
+    }
+    let header = try!(read_bytes(file, 8));
+    let size = match be_u32(&header[.. 4]) as u64 {
+        0 => end - start,
+        1 => {
+            let large = try!(read_bytes(file, 8));
+            (be_u32(&large[.. 4]) as u64) << 32 | be_u32(&large[4 ..]) as u64
+        },
+        x => x
+    };
+    if size < 8 || start + size > end {
+        return Err(invalid("Bad MP4 box"))
+    }
+    Ok(Some((kind_name(&header[4 ..]), start + size)))
+}
+
+/// Reads each item of an `ilst` box. The value is in a `data` box inside the
+/// item, after four bytes of type and four of locale.
+fn items<R: Read + Seek>(file: &mut R, end: u64, fields: &mut Vec<Field>) -> io::Result<()> {
+    while let Some((kind, item_end)) = try!(header(file, end)) {
+        if !ITEMS.iter().any(|x| *x == kind) {
+            try!(file.seek(SeekFrom::Start(item_end)));
+            continue
+        }
+        while let Some((inner, data_end)) = try!(header(file, item_end)) {
+            if inner == "data" {
+                let pos = try!(file.seek(SeekFrom::Current(0)));
+                let data = try!(read_bytes(file, (data_end - pos) as usize));
+                if data.len() >= 8 {
+                    if let Some(field) = item_field(&kind, &data[8 ..]) {
+                        fields.push(field);
+                    }
+                }
+            }
+            try!(file.seek(SeekFrom::Start(data_end)));
+        }
+        try!(file.seek(SeekFrom::Start(item_end)));
+    }
+    Ok(())
+}
+
+fn walk<R: Read + Seek>(file: &mut R, end: u64, fields: &mut Vec<Field>) -> io::Result<()> {
+    while let Some((kind, box_end)) = try!(header(file, end)) {
+        match &kind[..] {
+            "moov" | "udta" => try!(walk(file, box_end, fields)),
+            "meta" => {
+                try!(read_bytes(file, 4));
+                try!(walk(file, box_end, fields));
+            },
+            "ilst" => try!(items(file, box_end, fields)),
+            _ => { }
+        }
+        try!(file.seek(SeekFrom::Start(box_end)));
+    }
+    Ok(())
+}
+
+pub fn read<R: Read + Seek>(file: &mut R) -> io::Result<Vec<Field>> {
+    let end = try!(file.seek(SeekFrom::End(0)));
+    try!(file.seek(SeekFrom::Start(0)));
+    let mut fields = Vec::new();
+    try!(walk(file, end, &mut fields));
+    Ok(fields)
+}
+
+/// The duration and timescale in the movie header, `moov/mvhd`, whose fields
+/// are twice as wide in version 1.
+pub fn duration<R: Read + Seek>(file: &mut R) -> io::Result<Option<f64>> {
+    let end = try!(file.seek(SeekFrom::End(0)));
+    try!(file.seek(SeekFrom::Start(0)));
+    while let Some((kind, box_end)) = try!(header(file, end)) {
+        if kind == "moov" {
+            while let Some((inner, inner_end)) = try!(header(file, box_end)) {
+                if inner == "mvhd" {
+                    let version = try!(read_bytes(file, 4))[0];
+                    let (scale, length) = if version == 1 {
+                        let b = try!(read_bytes(file, 28));
+                        (be_u32(&b[16 ..]), (be_u32(&b[20 ..]) as u64) << 32 | be_u32(&b[24 ..]) as u64)
+                    } else {
+                        let b = try!(read_bytes(file, 16));
+                        (be_u32(&b[8 ..]), be_u32(&b[12 ..]) as u64)
+                    };
+                    return Ok(if scale == 0 { None } else { Some(length as f64 / scale as f64) })
+                }
+                try!(file.seek(SeekFrom::Start(inner_end)));
+            }
+            return Ok(None)
+        }
+        try!(file.seek(SeekFrom::Start(box_end)));
+    }
+    Ok(None)
+}
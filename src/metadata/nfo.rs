@@ -0,0 +1,489 @@
+//! The `.nfo` files Kodi and Jellyfin keep next to videos: XML documents with
+//! a `<movie>`, `<episodedetails>` or `<musicvideo>` root. Writing one back
+//! replaces only the elements for tags the program has, keeping the rest,
+//! such as artwork, scraper ids and anything a tag left unset.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use super::super::tags::{Tags, TagType, MediaType, VideoType};
+use super::super::rating::Rating;
+use super::super::storage;
+use super::super::parse::ParseError;
+use super::super::parse::ParseError::BadToken;
+use super::super::error;
+
+const ROOTS: [&'static str; 3] = ["movie", "episodedetails", "musicvideo"];
+
+#[derive(Clone, PartialEq, Debug)]
+enum Node {
+    Element(Element),
+    Text(String)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>
+}
+
+impl Element {
+    fn new(name: &str) -> Element {
+        Element { name: name.to_string(), attrs: Vec::new(), children: Vec::new() }
+    }
+
+    fn with_text(name: &str, text: &str) -> Element {
+        let mut elem = Element::new(name);
+        elem.children.push(Node::Text(text.to_string()));
+        elem
+    }
+
+    fn elements(&self) -> Vec<&Element> {
+        self.children.iter().filter_map(|x| match *x {
+            Node::Element(ref elem) => Some(elem),
+            Node::Text(_) => None
+        }).collect()
+    }
+
+    fn text(&self) -> String {
+        self.children.iter().map(|x| match *x {
+            Node::Element(ref elem) => elem.text(),
+            Node::Text(ref text) => text.clone()
+        }).collect::<Vec<String>>().concat().trim().to_string()
+    }
+
+    fn is_named(node: &Node, name: &str) -> bool {
+        match *node {
+            Node::Element(ref elem) => elem.name == name,
+            Node::Text(_) => false
+        }
+    }
+
+    /// Puts `elems` in place of the children called `name`, where the first
+    /// of them was, or at the end when there were none.
+    fn replace_children(&mut self, name: &str, elems: Vec<Element>) {
+        let at = self.children.iter().position(|x| Element::is_named(x, name));
+        self.children.retain(|x| !Element::is_named(x, name));
+        let at = at.unwrap_or(self.children.len());
+        for (i, elem) in elems.into_iter().enumerate() {
+            self.children.insert(at + i, Node::Element(elem));
+        }
+    }
+
+    fn child_text(&self, name: &str) -> Option<String> {
+        self.elements().into_iter().find(|x| x.name == name).map(|x| x.text()).and_then(|x| {
+            if x.len() == 0 { None } else { Some(x) }
+        })
+    }
+}
+
+fn unescape(s: &str) -> Result<String, ParseError> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[.. idx]);
+        let end = match rest[idx ..].find(';') {
+            Some(x) => idx + x,
+            None => return Err(BadToken("Unterminated entity in NFO".to_string()))
+        };
+        let entity = &rest[idx + 1 .. end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            x if x.starts_with("#x") => u32::from_str_radix(&x[2 ..], 16).ok().and_then(::std::char::from_u32),
+            x if x.starts_with("#") => x[1 ..].parse::<u32>().ok().and_then(::std::char::from_u32),
+            _ => None
+        };
+        match c {
+            Some(c) => out.push(c),
+            None => return Err(BadToken(format!("Unknown entity &{}; in NFO", entity)))
+        }
+        rest = &rest[end + 1 ..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn escape(s: &str) -> String {
+    s.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+/// A reader of just the XML NFO files use: elements, attributes, text,
+/// CDATA, comments and the declaration.
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos ..]
+    }
+
+    fn skip_to(&mut self, end: &str) -> Result<&'a str, ParseError> {
+        match self.rest().find(end) {
+            Some(idx) => {
+                let skipped = &self.rest()[.. idx];
+                self.pos += idx + end.len();
+                Ok(skipped)
+            },
+            None => Err(BadToken(format!("Expected {} in NFO", end)))
+        }
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_left().len();
+    }
+
+    /// Skips the declaration, comments and doctype before the root.
+    fn skip_prolog(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_space();
+            if self.rest().starts_with("<?") {
+                try!(self.skip_to("?>"));
+            } else if self.rest().starts_with("<!--") {
+                try!(self.skip_to("-->"));
+            } else if self.rest().starts_with("<!") {
+                try!(self.skip_to(">"));
+            } else {
+                return Ok(())
+            }
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/' || c == '=').unwrap_or(rest.len());
+        self.pos += len;
+        rest[.. len].to_string()
+    }
+
+    fn element(&mut self) -> Result<Element, ParseError> {
+        if !self.rest().starts_with("<") {
+            return Err(BadToken("Expected an element in NFO".to_string()))
+        }
+        self.pos += 1;
+        let mut elem = Element::new(&self.name());
+        loop {
+            self.skip_space();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(elem)
+            } else if self.rest().starts_with(">") {
+                self.pos += 1;
+                break
+            }
+            let name = self.name();
+            self.skip_space();
+            if name.len() == 0 || !self.rest().starts_with("=") {
+                return Err(BadToken(format!("Bad attribute in <{}> in NFO", elem.name)))
+            }
+            self.pos += 1;
+            self.skip_space();
+            let quote = match self.rest().chars().next() {
+                Some(x) if x == '"' || x == '\'' => x,
+                _ => return Err(BadToken(format!("Expected a quoted value for {} in NFO", name)))
+            };
+            self.pos += 1;
+            let value = try!(unescape(try!(self.skip_to(&quote.to_string()))));
+            elem.attrs.push((name, value));
+        }
+
+        loop {
+            let text = match self.rest().find('<') {
+                Some(idx) => &self.rest()[.. idx],
+                None => return Err(BadToken(format!("<{}> is not closed in NFO", elem.name)))
+            };
+            self.pos += text.len();
+            if text.trim().len() > 0 {
+                elem.children.push(Node::Text(try!(unescape(text))));
+            }
+
+            if self.rest().starts_with("</") {
+                self.pos += 2;
+                let name = self.name();
+                try!(self.skip_to(">"));
+                return if name == elem.name {
+                    Ok(elem)
+                } else {
+                    Err(BadToken(format!("Expected </{}>, found </{}> in NFO", elem.name, name)))
+                }
+            } else if self.rest().starts_with("<!--") {
+                try!(self.skip_to("-->"));
+            } else if self.rest().starts_with("<![CDATA[") {
+                self.pos += 9;
+                let text = try!(self.skip_to("]]>")).to_string();
+                elem.children.push(Node::Text(text));
+            } else {
+                elem.children.push(Node::Element(try!(self.element())));
+            }
+        }
+    }
+}
+
+fn parse_root(s: &str) -> Result<Element, ParseError> {
+    let mut parser = Parser { s: s, pos: 0 };
+    try!(parser.skip_prolog());
+    let root = try!(parser.element());
+    if ROOTS.contains(&&root.name[..]) {
+        Ok(root)
+    } else {
+        Err(BadToken(format!("Expected a movie, episodedetails or musicvideo NFO, found <{}>", root.name)))
+    }
+}
+
+fn write_element(elem: &Element, depth: usize, out: &mut String) {
+    let indent = (0 .. depth).map(|_| "    ").collect::<Vec<&str>>().concat();
+    out.push_str(&indent);
+    out.push_str(&format!("<{}", elem.name));
+    for &(ref name, ref value) in elem.attrs.iter() {
+        out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+    }
+    let only_text = match elem.children.first() {
+        Some(&Node::Text(ref text)) if elem.children.len() == 1 => Some(text),
+        _ => None
+    };
+    if elem.children.len() == 0 {
+        out.push_str(" />\n");
+    } else if let Some(text) = only_text {
+        out.push_str(&format!(">{}</{}>\n", escape(text), elem.name));
+    } else {
+        out.push_str(">\n");
+        for child in elem.children.iter() {
+            match *child {
+                Node::Element(ref x) => write_element(x, depth + 1, out),
+                Node::Text(ref x) => out.push_str(&format!("{}    {}\n", indent, escape(x.trim())))
+            }
+        }
+        out.push_str(&format!("{}</{}>\n", indent, elem.name));
+    }
+}
+
+/// Kodi writes ratings like `Rated PG-13`, `US:PG-13` or `UK:15`.
+fn parse_mpaa(s: &str) -> Option<Rating> {
+    let s = s.trim();
+    let s = if s.starts_with("Rated ") { &s[6 ..] } else { s };
+    if s.starts_with("UK:") {
+        format!("BBFC {}", &s[3 ..]).parse().ok()
+    } else if s.starts_with("US:") {
+        s[3 ..].parse().ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn write_mpaa(rating: &Rating) -> String {
+    match *rating {
+        Rating::Bbfc(_) => format!("UK:{}", &rating.to_string()[5 ..]),
+        ref x => x.to_string()
+    }
+}
+
+/// The tags an NFO holds. Runtimes are in minutes.
+pub fn parse(s: &str) -> Result<Tags, ParseError> {
+    let root = try!(parse_root(s));
+    let mut tags = Tags::new();
+    match &root.name[..] {
+        "movie" => tags.media_type = Some(MediaType::Video(VideoType::Movie)),
+        "musicvideo" => tags.media_type = Some(MediaType::Video(VideoType::MusicVideo)),
+        _ => { }
+    }
+
+    let single = [("title", TagType::Title), ("showtitle", TagType::Series), ("album", TagType::Album),
+                  ("plot", TagType::Summary), ("season", TagType::Season), ("episode", TagType::Episode),
+                  ("track", TagType::TrackNo), ("year", TagType::Year)];
+    for &(name, ref tag) in single.iter() {
+        if let Some(text) = root.child_text(name) {
+            let _ = tags.modify_tag(tag, &text);
+        }
+    }
+    if let Some(date) = root.child_text("aired").or(root.child_text("premiered")) {
+        let _ = tags.modify_tag(&TagType::Airdate, &date);
+        if tags.year.is_none() && date.len() >= 4 && date.is_char_boundary(4) {
+            let _ = tags.modify_tag(&TagType::Year, &date[.. 4]);
+        }
+    }
+    if let Some(minutes) = root.child_text("runtime").and_then(|x| x.parse::<usize>().ok()) {
+        tags.runtime = Some(minutes * 60);
+    }
+    tags.rating = root.child_text("mpaa").and_then(|x| parse_mpaa(&x));
+
+    let multi = [("genre", TagType::Genre), ("studio", TagType::Studio), ("director", TagType::Director),
+                 ("country", TagType::Country), ("artist", TagType::Artist)];
+    for elem in root.elements().into_iter() {
+        let tag = match multi.iter().find(|x| x.0 == elem.name) {
+            Some(x) => x.1.clone(),
+            None if elem.name == "actor" => match elem.child_text("name") {
+                Some(name) => {
+                    let _ = tags.add_value(&TagType::Cast, &name);
+                    continue
+                },
+                None => continue
+            },
+            None => continue
+        };
+        for value in elem.text().split(" / ").map(|x| x.trim()).filter(|x| x.len() > 0) {
+            let _ = tags.add_value(&tag, value);
+        }
+    }
+    Ok(tags)
+}
+
+/// The NFO for `tags`. The root is `<musicvideo>` for music videos,
+/// `<episodedetails>` for anything with a season or episode, and `<movie>`
+/// otherwise, unless `existing` already has one. Only the elements of
+/// `existing` for tags that are set are replaced; an actor already there
+/// keeps its role and picture.
+pub fn to_nfo(tags: &Tags, existing: Option<&str>) -> Result<String, ParseError> {
+    let mut root = match existing {
+        Some(s) => try!(parse_root(s)),
+        None if tags.media_type == Some(MediaType::Video(VideoType::MusicVideo)) => Element::new("musicvideo"),
+        None if tags.season.is_some() || tags.episode.is_some() => Element::new("episodedetails"),
+        None => Element::new("movie")
+    };
+    root.children.retain(|x| match *x {
+        Node::Element(_) => true,
+        Node::Text(_) => false
+    });
+
+    let aired = if root.name == "movie" { "premiered" } else { "aired" };
+    let single = [("title", tags.title.clone()),
+                  ("showtitle", tags.series.clone()),
+                  ("album", tags.album.clone()),
+                  ("season", tags.season.map(|x| x.to_string())),
+                  ("episode", tags.episode.map(|x| x.to_string())),
+                  ("track", tags.track_no.map(|x| x.to_string())),
+                  (aired, tags.airdate.map(|x| x.to_string())),
+                  ("year", tags.year.map(|x| x.to_string())),
+                  ("runtime", tags.runtime.map(|x| ((x + 30) / 60).to_string())),
+                  ("plot", tags.summary.clone()),
+                  ("mpaa", tags.rating.as_ref().map(write_mpaa))];
+    for &(name, ref value) in single.iter() {
+        if let Some(ref x) = *value {
+            root.replace_children(name, vec!(Element::with_text(name, x)));
+        }
+    }
+
+    let multi = [("genre", &tags.genre), ("studio", &tags.studio), ("director", &tags.director),
+                 ("country", &tags.country), ("artist", &tags.artist)];
+    for &(name, values) in multi.iter() {
+        if let Some(ref values) = *values {
+            root.replace_children(name, values.iter().map(|x| Element::with_text(name, x)).collect());
+        }
+    }
+
+    if let Some(ref cast) = tags.cast {
+        let actors = cast.iter().map(|name| {
+            root.elements().into_iter().find(|x| x.name == "actor" && x.child_text("name").as_ref() == Some(name))
+                .cloned().unwrap_or_else(|| {
+                    let mut actor = Element::new("actor");
+                    actor.children.push(Node::Element(Element::with_text("name", name)));
+                    actor
+                })
+        }).collect::<Vec<Element>>();
+        root.replace_children("actor", actors);
+    }
+
+    let mut out = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>\n".to_string();
+    write_element(&root, 0, &mut out);
+    Ok(out)
+}
+
+/// Where the NFO for the media at `media` goes: beside it, named the same
+/// with an `.nfo` extension.
+pub fn sidecar(media: &Path) -> PathBuf {
+    media.with_extension("nfo")
+}
+
+/// Reads the NFO beside `media`, if there is one.
+pub fn read(media: &Path) -> error::Result<Option<Tags>> {
+    let mut s = String::new();
+    match File::open(sidecar(media)) {
+        Ok(mut file) => try!(file.read_to_string(&mut s)),
+        Err(ref f) if f.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(f) => return Err(From::from(f))
+    };
+    Ok(Some(try!(parse(&s))))
+}
+
+/// Writes `tags` to the NFO beside `media`, keeping what it held that tags
+/// do not cover.
+pub fn write(media: &Path, tags: &Tags) -> error::Result<()> {
+    let path = sidecar(media);
+    let mut existing = String::new();
+    let had = match File::open(&path) {
+        Ok(mut file) => {
+            try!(file.read_to_string(&mut existing));
+            true
+        },
+        Err(ref f) if f.kind() == ErrorKind::NotFound => false,
+        Err(f) => return Err(From::from(f))
+    };
+    let nfo = try!(to_nfo(tags, if had { Some(&existing[..]) } else { None }));
+    Ok(try!(storage::write_atomic(&path, nfo.as_bytes())))
+}
+
+#[test]
+fn episode_round_trip() {
+    let nfo = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!-- written by Kodi -->
+<episodedetails>
+    <title>Unlimited Blade Works</title>
+    <showtitle>Fate/stay night</showtitle>
+    <season>1</season>
+    <episode>12</episode>
+    <aired>2015-01-10</aired>
+    <runtime>24</runtime>
+    <plot><![CDATA[Shirou & Archer <fight>.]]></plot>
+    <mpaa>Rated TV-14</mpaa>
+    <studio>ufotable</studio>
+    <director>Takahiro Miura</director>
+    <actor><name>Noriaki Sugiyama</name><role>Shirou</role></actor>
+    <actor><name>Junichi Suwabe</name></actor>
+    <uniqueid type=\"tvdb\" default=\"true\">5030021</uniqueid>
+    <thumb aspect=\"thumb\" />
+</episodedetails>";
+    let tags = parse(nfo).unwrap();
+    assert_eq!(tags.title, Some("Unlimited Blade Works".to_string()));
+    assert_eq!(tags.series, Some("Fate/stay night".to_string()));
+    assert_eq!((tags.season, tags.episode), (Some(1), Some(12)));
+    assert_eq!(tags.get_tag(&TagType::Airdate), Some("2015-01-10".to_string()));
+    assert_eq!(tags.year, Some(2015));
+    assert_eq!(tags.runtime, Some(24 * 60));
+    assert_eq!(tags.summary, Some("Shirou & Archer <fight>.".to_string()));
+    assert_eq!(tags.rating, Some("TV-14".parse::<Rating>().unwrap()));
+    assert_eq!(tags.studios(), &["ufotable".to_string()]);
+    assert_eq!(tags.cast, Some(vec!("Noriaki Sugiyama".to_string(), "Junichi Suwabe".to_string())));
+
+    let mut edited = tags.clone();
+    edited.title = Some("Unlimited Blade Works & more".to_string());
+    edited.cast = Some(vec!("Noriaki Sugiyama".to_string()));
+    let written = to_nfo(&edited, Some(nfo)).unwrap();
+    assert!(written.contains("<title>Unlimited Blade Works &amp; more</title>"));
+    assert!(written.contains("<uniqueid type=\"tvdb\" default=\"true\">5030021</uniqueid>"));
+    assert!(written.contains("<role>Shirou</role>"));
+    assert!(!written.contains("Junichi Suwabe"));
+    assert_eq!(parse(&written).unwrap(), edited);
+
+    // A program with only a title keeps everything else the file held.
+    let mut sparse = Tags::new();
+    sparse.title = Some("UBW".to_string());
+    let kept = nfo.replace("</episodedetails>", "<credits>Kinoko Nasu</credits><tag>favourite</tag></episodedetails>");
+    let written = to_nfo(&sparse, Some(&kept)).unwrap();
+    let mut expected = tags.clone();
+    expected.title = sparse.title.clone();
+    assert_eq!(parse(&written).unwrap(), expected);
+    for element in ["<credits>Kinoko Nasu</credits>", "<tag>favourite</tag>", "<role>Shirou</role>"].iter() {
+        assert!(written.contains(element));
+    }
+
+    let movie = to_nfo(&Tags::new(), None).unwrap();
+    assert!(movie.contains("<movie />"));
+    assert!(parse("<tvshow><title>x</title></tvshow>").is_err());
+    assert_eq!(parse("<movie><premiered>198€</premiered></movie>").unwrap().year, None);
+}
@@ -0,0 +1,148 @@
+//! Vorbis comments, the `KEY=value` tags of FLAC files and of Ogg streams
+//! holding Vorbis, Opus or Theora.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use super::super::tags::TagType;
+use super::{Field, MAX_TAG_SIZE, invalid, read_bytes, le_u32};
+
+fn comment_tag(key: &str) -> Option<TagType> {
+    Some(match &key.to_uppercase()[..] {
+        "TITLE" => TagType::Title,
+        "ARTIST" | "PERFORMER" => TagType::Artist,
+        "ALBUM" => TagType::Album,
+        "GENRE" => TagType::Genre,
+        "TRACKNUMBER" => TagType::TrackNo,
+        "COMPOSER" => TagType::Composer,
+        "DIRECTOR" => TagType::Director,
+        "DATE" => TagType::Airdate,
+        _ => return None
+    })
+}
+
+/// The next `len` bytes of `block`, moving `pos` past them.
+fn take<'a>(block: &'a [u8], pos: &mut usize, len: usize) -> io::Result<&'a [u8]> {
+    if *pos + len > block.len() {
+        Err(invalid("The comments end too soon"))
+    } else {
+        *pos += len;
+        Ok(&block[*pos - len .. *pos])
+    }
+}
+
+/// Reads a comment block: the vendor string, then the number of comments and
+/// each comment, all lengths being little endian.
+fn comments(block: &[u8]) -> io::Result<Vec<Field>> {
+    let mut pos = 0;
+    let vendor_len = le_u32(try!(take(block, &mut pos, 4))) as usize;
+    try!(take(block, &mut pos, vendor_len));
+    let count = le_u32(try!(take(block, &mut pos, 4)));
+
+    let mut fields = Vec::new();
+    for _ in 0 .. count {
+        let len = le_u32(try!(take(block, &mut pos, 4))) as usize;
+        let comment = String::from_utf8_lossy(try!(take(block, &mut pos, len))).into_owned();
+        let mut parts = comment.splitn(2, '=');
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            if let Some(tag) = comment_tag(key) {
+                fields.push((tag, value.to_string()));
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// A FLAC file is `fLaC` then metadata blocks, each with a header giving
+/// whether it is the last, its type and its length.
+pub fn read_flac<R: Read>(file: &mut R) -> io::Result<Vec<Field>> {
+    try!(read_bytes(file, 4));
+    loop {
+        let header = try!(read_bytes(file, 4));
+        let len = (header[1] as usize) << 16 | (header[2] as usize) << 8 | header[3] as usize;
+        let block = try!(read_bytes(file, len));
+        if header[0] & 0x7F == 4 {
+            return comments(&block)
+        }
+        if header[0] & 0x80 != 0 {
+            return Ok(Vec::new())
+        }
+    }
+}
+
+/// The comments are the second packet of an Ogg stream. Pages are read until
+/// it is whole; a packet continues across segments of 255 bytes. Only pages
+/// with the serial number of the first belong to its stream; the headers of
+/// any others in the file are passed over.
+pub fn read_ogg<R: Read>(file: &mut R) -> io::Result<Vec<Field>> {
+    let mut packets = 0;
+    let mut packet = Vec::new();
+    let mut serial = None;
+    while packets < 2 {
+        let header = try!(read_bytes(file, 27));
+        if &header[.. 4] != b"OggS" {
+            return Err(invalid("Expected an Ogg page"))
+        }
+        let lacing = try!(read_bytes(file, header[26] as usize));
+        if serial.is_none() {
+            serial = Some(header[14 .. 18].to_vec());
+        }
+        if serial.as_ref().map(|x| &x[..]) != Some(&header[14 .. 18]) {
+            try!(read_bytes(file, lacing.iter().fold(0, |n, &x| n + x as usize)));
+            continue
+        }
+        for &len in lacing.iter() {
+            let data = try!(read_bytes(file, len as usize));
+            if packets == 1 {
+                packet.extend(data.into_iter());
+                if packet.len() > MAX_TAG_SIZE {
+                    return Err(invalid("The tag is too large"))
+                }
+            }
+            if len < 255 {
+                packets += 1;
+                if packets == 2 {
+                    break
+                }
+            }
+        }
+    }
+
+    for prefix in [&b"\x03vorbis"[..], &b"OpusTags"[..], &b"\x81theora"[..]].iter() {
+        if packet.starts_with(prefix) {
+            return comments(&packet[prefix.len() ..])
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// The granule position of the last page counts samples, so the duration is
+/// that over the sample rate from the identification header, the first
+/// packet. Opus always counts at 48 kHz and starts after a pre-skip. Only
+/// pages of the first stream count, as others in the file have rates of their
+/// own.
+pub fn ogg_duration<R: Read + Seek>(file: &mut R) -> io::Result<Option<f64>> {
+    let header = try!(read_bytes(file, 27));
+    let lacing = try!(read_bytes(file, header[26] as usize));
+    let first = lacing.iter().position(|&x| x < 255).map(|x| x + 1).unwrap_or(lacing.len());
+    let packet = try!(read_bytes(file, lacing[.. first].iter().fold(0, |n, &x| n + x as usize)));
+    let (rate, skip) = if packet.starts_with(b"\x01vorbis") && packet.len() >= 16 {
+        (le_u32(&packet[12 ..]) as u64, 0)
+    } else if packet.starts_with(b"OpusHead") && packet.len() >= 12 {
+        (48000, packet[10] as u64 | (packet[11] as u64) << 8)
+    } else {
+        return Ok(None)
+    };
+
+    let len = try!(file.seek(SeekFrom::End(0)));
+    try!(file.seek(SeekFrom::Start(len.saturating_sub(64 * 1024))));
+    let mut tail = Vec::new();
+    try!(file.read_to_end(&mut tail));
+    // A granule position of all ones marks a page on which no packet ends.
+    let granule = (0 .. tail.len().saturating_sub(17)).rev()
+        .filter(|&i| &tail[i .. i + 4] == b"OggS" && &tail[i + 14 .. i + 18] == &header[14 .. 18])
+        .map(|i| tail[i + 6 .. i + 14].iter().rev().fold(0, |n, &b| n << 8 | b as u64))
+        .find(|&x| x != !0);
+    Ok(match granule {
+        Some(x) if rate > 0 && x >= skip => Some((x - skip) as f64 / rate as f64),
+        _ => None
+    })
+}
@@ -6,7 +6,9 @@ pub use self::tokenize::{TokenStream, MaybeToken};
 pub use super::schedule::Schedule;
 pub use super::program::Source;
 pub use super::tags::{TagType, Tags};
-use self::translate::translate;
+pub use super::channel::Lineup;
+use self::translate::{translate, translate_lineup};
+use std::error;
 use std::fmt;
 
 mod tokenize;
@@ -22,11 +24,21 @@ pub enum Token {
     List,
     Instr,
     Play,
+    Audio,
+    Subtitle,
     Local,
     Network,
     Tag,
     Prog,
-    Sched
+    Sched,
+    LineupKw,
+    ChannelKw,
+    Number,
+    Picture,
+    AiringKw,
+    Repeat,
+    Watershed,
+    Defaults
 }
 
 impl fmt::Display for Token {
@@ -40,11 +52,21 @@ impl fmt::Display for Token {
             Token::List => "list".to_string(),
             Token::Instr => "instr".to_string(),
             Token::Play => "play".to_string(),
+            Token::Audio => "audio".to_string(),
+            Token::Subtitle => "subtitle".to_string(),
             Token::Local => "local".to_string(),
             Token::Network => "network".to_string(),
             Token::Tag => "tag".to_string(), 
             Token::Prog => "program".to_string(),
-            Token::Sched => "schedule".to_string()
+            Token::Sched => "schedule".to_string(),
+            Token::LineupKw => "lineup".to_string(),
+            Token::ChannelKw => "channel".to_string(),
+            Token::Number => "number".to_string(),
+            Token::Picture => "picture".to_string(),
+            Token::AiringKw => "airing".to_string(),
+            Token::Repeat => "repeat".to_string(),
+            Token::Watershed => "watershed".to_string(),
+            Token::Defaults => "defaults".to_string()
         }));
         Ok(())
     }
@@ -70,6 +92,17 @@ impl fmt::Display for ParseError {
     }
 }
 
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseError::BadToken(ref x) => x,
+            ParseError::UnbalancedParens => "unbalanced parens",
+            ParseError::BadAction => "inappropriate action",
+            ParseError::BadTime => "improper time"
+        }
+    }
+}
+
 pub fn is_paren(expr: &str) -> MaybeToken<Token, ParseError> {
     match expr.chars().next().unwrap() {
         '(' => (Some(Ok(Token::LParen)), 1),
@@ -97,6 +130,10 @@ pub fn is_keyword(expr: &str) -> MaybeToken<Token, ParseError> {
         (Some(Ok(Token::Tag)), 4)
     } else if expr.starts_with("play") { 
         (Some(Ok(Token::Play)), 4)
+    } else if expr.starts_with("audio ") {
+        (Some(Ok(Token::Audio)), 5)
+    } else if expr.starts_with("subtitle ") {
+        (Some(Ok(Token::Subtitle)), 8)
     } else if expr.starts_with("local ") {
         (Some(Ok(Token::Local)), 5)
     } else if expr.starts_with("network ") {
@@ -107,6 +144,22 @@ pub fn is_keyword(expr: &str) -> MaybeToken<Token, ParseError> {
         (Some(Ok(Token::Sched)), 8)
     } else if expr.starts_with("instr ") {
         (Some(Ok(Token::Instr)), 5)
+    } else if expr.starts_with("lineup ") {
+        (Some(Ok(Token::LineupKw)), 6)
+    } else if expr.starts_with("channel ") {
+        (Some(Ok(Token::ChannelKw)), 7)
+    } else if expr.starts_with("number ") {
+        (Some(Ok(Token::Number)), 6)
+    } else if expr.starts_with("picture ") {
+        (Some(Ok(Token::Picture)), 7)
+    } else if expr.starts_with("airing ") {
+        (Some(Ok(Token::AiringKw)), 6)
+    } else if expr.starts_with("repeat ") {
+        (Some(Ok(Token::Repeat)), 6)
+    } else if expr.starts_with("watershed ") {
+        (Some(Ok(Token::Watershed)), 9)
+    } else if expr.starts_with("defaults ") {
+        (Some(Ok(Token::Defaults)), 8)
     } else {
         (None, 0)
     }
@@ -119,6 +172,10 @@ pub fn is_keyword_rev(expr: &str) -> MaybeToken<Token, ParseError> {
         (Some(Ok(Token::Tag)), 4)
     } else if expr.ends_with("play") { 
         (Some(Ok(Token::Play)), 4)
+    } else if expr.ends_with("audio") {
+        (Some(Ok(Token::Audio)), 5)
+    } else if expr.ends_with("subtitle") {
+        (Some(Ok(Token::Subtitle)), 8)
     } else if expr.ends_with("local") {
         (Some(Ok(Token::Local)), 5)
     } else if expr.ends_with("network") {
@@ -129,6 +186,22 @@ pub fn is_keyword_rev(expr: &str) -> MaybeToken<Token, ParseError> {
         (Some(Ok(Token::Sched)), 8)
     } else if expr.ends_with("instr") {
         (Some(Ok(Token::Instr)), 5)
+    } else if expr.ends_with("lineup") {
+        (Some(Ok(Token::LineupKw)), 6)
+    } else if expr.ends_with("channel") {
+        (Some(Ok(Token::ChannelKw)), 7)
+    } else if expr.ends_with("number") {
+        (Some(Ok(Token::Number)), 6)
+    } else if expr.ends_with("picture") {
+        (Some(Ok(Token::Picture)), 7)
+    } else if expr.ends_with("airing") {
+        (Some(Ok(Token::AiringKw)), 6)
+    } else if expr.ends_with("repeat") {
+        (Some(Ok(Token::Repeat)), 6)
+    } else if expr.ends_with("watershed") {
+        (Some(Ok(Token::Watershed)), 9)
+    } else if expr.ends_with("defaults") {
+        (Some(Ok(Token::Defaults)), 8)
     } else {
         (None, 0)
     }
@@ -265,16 +338,21 @@ pub fn is_tag_rev(expr: &str) -> MaybeToken<Token, ParseError> {
     }
 }
 
-pub fn parse(s: &str) -> Result<Schedule, ParseError> {
+fn tokenize(s: &str) -> TokenStream<Token, ParseError> {
     let next_rules: Vec<fn(&str) -> MaybeToken<Token, ParseError>> = 
         vec!(is_paren, is_keyword, is_data, is_time, is_tag);
     let back_rules: Vec<fn(&str) -> MaybeToken<Token, ParseError>> =
         vec!(is_paren_rev, is_keyword_rev, is_data_rev, is_time_rev, is_tag);
 
-    let mut tokens = TokenStream::new(s, next_rules, back_rules,
-                                      ParseError::BadToken("Unrecognized token".to_string()));
+    TokenStream::new(s, next_rules, back_rules, ParseError::BadToken("Unrecognized token".to_string()))
+}
+
+pub fn parse(s: &str) -> Result<Schedule, ParseError> {
+    translate(&mut tokenize(s))
+}
 
-    translate(&mut tokens)
+pub fn parse_lineup(s: &str) -> Result<Lineup, ParseError> {
+    translate_lineup(&mut tokenize(s))
 }
 
 
@@ -331,7 +409,59 @@ fn toy_example() {
 
     assert!(parse(should_work2).is_ok());
 
+    // A single genre, as written before genres became lists.
+    let one_genre = parse(&should_work2.replace(":director \"Bar Baz\"", ":genre \"Anime\"")).unwrap();
+    let many = parse(&should_work2.replace(":director \"Bar Baz\"", ":genre (list \"Anime\" \"Drama\")")).unwrap();
+    assert_eq!(one_genre.get_program_at(0).unwrap().get_tags().genres(), ["Anime".to_string()]);
+    assert_eq!(many.get_program_at(0).unwrap().get_tags().genres().len(), 2);
+    assert_eq!(parse(&many.to_string()), Ok(many.clone()));
+
     if let Err(f) =  parse(no_tags) {
         panic!(f)
     }
 }
+
+#[test]
+fn custom_tags() {
+    let custom =
+"(schedule \"foo\" (program (local \"foo\") (tags :title \"Foo\" :x-tvdb_id \"81797\" :x-source.site \"tvdb\")
+ (instr (play 00:00:00 00:00:00))))";
+
+    let parsed = match parse(custom) {
+        Ok(x) => x,
+        Err(f) => panic!(f)
+    };
+    let tags = parsed.get_program_at(0).unwrap().get_tags();
+    assert_eq!(tags.get_tag(&TagType::Custom("tvdb_id".to_string())), Some("81797".to_string()));
+    assert_eq!(tags.title, Some("Foo".to_string()));
+    assert_eq!(parse(&parsed.to_string()), Ok(parsed.clone()));
+
+    assert!(parse(&custom.replace(":x-tvdb_id", ":x-")).is_err());
+    assert!(parse(&custom.replace(":x-tvdb_id", ":tvdb_id")).is_err());
+}
+
+#[test]
+fn lineup_example() {
+    let lineup =
+"(lineup \"XBTV\" (channel \"Anime\" (number \"5\") (picture \"anime.png\") (watershed \"TV-MA\" \"21:00\" \"05:30\")
+ (airing \"2015-06-01 18:00:00\" (repeat \"FREQ=WEEKLY;BYDAY=MO,FR;COUNT=10\") (schedule \"foo\" (program (local \"foo\")
+ (tags :director \"Bar Baz\" :runtime \"24m\") (instr (play 00:00:00 00:00:00))))))
+ (channel \"Music\" (number \"6\")))";
+
+    let no_number =
+"(lineup \"XBTV\" (channel \"Anime\" (picture \"anime.png\")))";
+
+    let bad_airing =
+"(lineup \"XBTV\" (channel \"Anime\" (number \"5\") (airing \"June 1st\" (schedule \"foo\"))))";
+
+    let parsed = match parse_lineup(lineup) {
+        Ok(x) => x,
+        Err(f) => panic!(f)
+    };
+    assert_eq!(parsed.channels_len(), 2);
+    assert_eq!(parse_lineup(&parsed.to_string()), Ok(parsed));
+
+    assert!(parse_lineup(no_number).is_err());
+    assert!(parse_lineup(bad_airing).is_err());
+    assert!(parse_lineup(&lineup.replace("COUNT=10", "COUNT=ten")).is_err());
+}
@@ -1,13 +1,20 @@
 //! Translate tokens into expressions and atoms.
 
+extern crate chrono;
+
+use self::chrono::{NaiveDateTime, NaiveTime};
+
 use super::ParseError::*;
 use super::{Token, Schedule, ParseError};
 use super::Token::*;
 use super::tokenize::TokenStream;
-use super::super::tags::TagType::{Cast, AudioTracks, Subtitles};
 use super::super::tags::Tags;
 use super::super::program::{Program, Source, Instruction};
 use super::super::program::Source::*;
+use super::super::track::{TrackKind, TrackSelector};
+use super::super::channel::{Airing, Channel, Lineup, DATETIME_FORMAT};
+use super::super::recurrence::Recurrence;
+use super::super::rating::{Rating, WatershedRule};
 
 pub type SchedResult = Result<Schedule, ParseError>;
 pub type ParseResult = Result<(), ParseError>;
@@ -57,40 +64,44 @@ pub fn get_location(tokens: &mut TokenStream<Token, ParseError>) -> Result<Sourc
     }
 }
 
+/// Reads tags and their data up to and including the closing paren.
+fn tag_body(tokens: &mut TokenStream<Token, ParseError>) -> Result<Tags, ParseError> {
+    let mut tags = Tags::new();
+    loop {
+        let tag_type = match try!(strip(tokens.next())) {
+            TagKind(x) => x,
+            RParen => break,
+            x => return Err(BadToken(format!("Expected tag type or rparen, found {}", x)))
+        };
+
+        if tag_type.is_multi() {
+            let list = match try!(strip(tokens.next())) {
+                LParen => match try!(strip(tokens.next())) {
+                    List => try!(create_list(tokens)),
+                    x => return Err(BadToken(format!("Expected a list for tag data, found {}", x)))
+                },
+                // Genres, people and countries used to hold one value.
+                Data(x) => vec!(x),
+                x => return Err(BadToken(format!("Expected a list for tag data, found {}", x)))
+            };
+            try!(tags.modify_multi(&list, tag_type));
+        } else {
+            let tagdata = match try!(strip(tokens.next())) {
+                Data(x) => x,
+                x => return Err(BadToken(format!("Expected tag data, found {}", x)))
+            };
+            try!(tags.modify_tag(&tag_type, &tagdata));
+        }
+    }
+    Ok(tags)
+}
+
 pub fn get_tags(tokens: &mut TokenStream<Token, ParseError>) -> Result<Tags, ParseError> {
     try!(begin_expr(tokens));
     let mut tokens2 = tokens.clone();
     if let Some(Ok(Tag)) = tokens2.next() {
         try!(strip(tokens.next()));
-        let mut tags = Tags::new();
-        loop {
-            let tag_type = match try!(strip(tokens.next())) {
-                TagKind(x) => x,
-                RParen => break,
-                x => return Err(BadToken(format!("Expected tag type or rparen, found {}", x)))
-            };
-
-            match tag_type {
-                Cast |
-                AudioTracks |
-                Subtitles => {
-                    try!(begin_expr(tokens));
-                    let list = match try!(strip(tokens.next())) {
-                        List => try!(create_list(tokens)),
-                        x => return Err(BadToken(format!("Expected a list for tag data, found {}", x)))
-                    };
-                    try!(tags.modify_multi(&list, tag_type));
-                },
-                single => {
-                    let tagdata = match try!(strip(tokens.next())) {
-                        Data(x) => x,
-                        x => return Err(BadToken(format!("Expected tag data, found {}", x)))
-                    };
-                    try!(tags.modify_tag(&single, &tagdata));
-                }
-            }
-        }
-        Ok(tags)
+        tag_body(tokens)
     } else {
         match try!(strip(tokens.previous())) {
             LParen => { },
@@ -124,6 +135,17 @@ pub fn play_handler(tokens: &mut TokenStream<Token, ParseError>) -> Result<Instr
     }
 }
 
+/// Reads the track an `audio` or `subtitle` instruction chooses, and its
+/// closing paren.
+fn select_handler(tokens: &mut TokenStream<Token, ParseError>, kind: TrackKind) -> Result<Instruction, ParseError> {
+    let selector = match try!(strip(tokens.next())) {
+        Data(x) => try!(TrackSelector::parse(kind, &x)),
+        x => return Err(BadToken(format!("Expected a track number, a language or off, found {}", x)))
+    };
+    try!(end_expr(tokens));
+    Ok(Instruction::Select(kind, selector))
+}
+
 pub fn add_instrs(tokens: &mut TokenStream<Token, ParseError>) -> Result<Vec<Instruction>, ParseError> {
     try!(begin_expr(tokens));
     match try!(strip(tokens.next())) {
@@ -136,6 +158,8 @@ pub fn add_instrs(tokens: &mut TokenStream<Token, ParseError>) -> Result<Vec<Ins
                     Play => { 
                         instructions.push(try!(play_handler(tokens)));
                     },
+                    Audio => instructions.push(try!(select_handler(tokens, TrackKind::Audio))),
+                    Subtitle => instructions.push(try!(select_handler(tokens, TrackKind::Subtitle))),
                     Prog => {
                         instructions.push(Instruction::SubProgram(try!(add_program(tokens))));
                     },
@@ -166,12 +190,11 @@ pub fn add_program(tokens: &mut TokenStream<Token, ParseError>) -> Result<Progra
     Ok(prog)
 }
 
-pub fn translate(tokens: &mut TokenStream<Token, ParseError>) -> SchedResult {
-    try!(begin_expr(tokens));
-    let mut progs: Vec<Program> = match try!(strip(tokens.next())) {
-        Sched => Vec::new(),
-        _ => return Err(BadAction)
-    };
+/// Reads the name and programs of a schedule whose opening paren and keyword
+/// have already been consumed, up to and including its closing paren.
+pub fn schedule_body(tokens: &mut TokenStream<Token, ParseError>) -> SchedResult {
+    let mut progs: Vec<Program> = Vec::new();
+    let mut defaults = None;
 
     let name = match try!(strip(tokens.next())) {
         Data(x) => x,
@@ -184,15 +207,190 @@ pub fn translate(tokens: &mut TokenStream<Token, ParseError>) -> SchedResult {
             Some(Ok(Prog)) => {
                 progs.push(try!(add_program(tokens)));
             },
-            Some(Ok(RParen)) => match tokens.next() {
-                None => break,
-                Some(Ok(x)) => return Err(BadToken(format!("Expected end of tokenstream, but found {}", x))),
-                Some(Err(f)) => return Err(f)
+            Some(Ok(Defaults)) if progs.len() == 0 && defaults.is_none() => {
+                defaults = Some(try!(tag_body(tokens)));
             },
+            Some(Ok(RParen)) => break,
             Some(Ok(x)) => return Err(BadToken(format!("Expected (, ), or Program but found {}", x))),
             Some(Err(f)) => return Err(f),
             None => return Err(UnbalancedParens)
         }
     }
-    Ok(Schedule::new(&name, progs))
+    let mut sched = Schedule::new(&name, progs);
+    if let Some(ref tags) = defaults {
+        sched.set_defaults(tags);
+    }
+    Ok(sched)
+}
+
+fn end_of_stream(tokens: &mut TokenStream<Token, ParseError>) -> ParseResult {
+    match tokens.next() {
+        None => Ok(()),
+        Some(Ok(x)) => Err(BadToken(format!("Expected end of tokenstream, but found {}", x))),
+        Some(Err(f)) => Err(f)
+    }
+}
+
+pub fn translate(tokens: &mut TokenStream<Token, ParseError>) -> SchedResult {
+    try!(begin_expr(tokens));
+    match try!(strip(tokens.next())) {
+        Sched => { },
+        _ => return Err(BadAction)
+    }
+
+    let sched = try!(schedule_body(tokens));
+    try!(end_of_stream(tokens));
+    Ok(sched)
+}
+
+fn end_expr(tokens: &mut TokenStream<Token, ParseError>) -> ParseResult {
+    match try!(strip(tokens.next())) {
+        RParen => Ok(()),
+        x => Err(BadToken(format!("Expected rparen, found {}", x)))
+    }
+}
+
+pub fn add_airing(tokens: &mut TokenStream<Token, ParseError>) -> Result<Airing, ParseError> {
+    let start = match try!(strip(tokens.next())) {
+        Data(x) => match NaiveDateTime::parse_from_str(&x, DATETIME_FORMAT) {
+            Ok(start) => start,
+            Err(_) => return Err(BadToken(format!("Expected airing time as yyyy-mm-dd hh:mm:ss, found {}", x)))
+        },
+        x => return Err(BadToken(format!("Expected airing time, found {}", x)))
+    };
+
+    try!(begin_expr(tokens));
+    let recurrence = match try!(strip(tokens.next())) {
+        Repeat => {
+            let rule = match try!(strip(tokens.next())) {
+                Data(x) => try!(x.parse::<Recurrence>()),
+                x => return Err(BadToken(format!("Expected recurrence rule, found {}", x)))
+            };
+            try!(end_expr(tokens));
+            try!(begin_expr(tokens));
+            match try!(strip(tokens.next())) {
+                Sched => { },
+                x => return Err(BadToken(format!("Expected schedule, found {}", x)))
+            }
+            Some(rule)
+        },
+        Sched => None,
+        x => return Err(BadToken(format!("Expected repeat or schedule, found {}", x)))
+    };
+    let sched = try!(schedule_body(tokens));
+    try!(end_expr(tokens));
+
+    Ok(match recurrence {
+        Some(rule) => Airing::recurring(start, rule, sched),
+        None => Airing::new(start, sched)
+    })
+}
+
+pub fn add_watershed(tokens: &mut TokenStream<Token, ParseError>) -> Result<WatershedRule, ParseError> {
+    let rating = match try!(strip(tokens.next())) {
+        Data(x) => try!(x.parse::<Rating>()),
+        x => return Err(BadToken(format!("Expected rating, found {}", x)))
+    };
+    if rating.level().is_none() {
+        return Err(BadToken(format!("A watershed needs a rating with a level, found {}", rating)))
+    }
+
+    let mut times = Vec::new();
+    for _ in 0 .. 2 {
+        times.push(match try!(strip(tokens.next())) {
+            Data(x) => match NaiveTime::parse_from_str(&x, "%H:%M") {
+                Ok(time) => time,
+                Err(_) => return Err(BadToken(format!("Expected time as hh:mm, found {}", x)))
+            },
+            x => return Err(BadToken(format!("Expected time, found {}", x)))
+        });
+    }
+    try!(end_expr(tokens));
+
+    Ok(WatershedRule::new(rating, times[0], times[1]))
+}
+
+pub fn add_channel(tokens: &mut TokenStream<Token, ParseError>) -> Result<Channel, ParseError> {
+    let name = match try!(strip(tokens.next())) {
+        Data(x) => x,
+        x => return Err(BadToken(format!("Expected channel name but found {}", x)))
+    };
+
+    let mut number = None;
+    let mut picture = None;
+    let mut watershed = Vec::new();
+    let mut airings = Vec::new();
+
+    loop {
+        match try!(strip(tokens.next())) {
+            LParen => continue,
+            Number => {
+                number = match try!(strip(tokens.next())) {
+                    Data(x) => match x.parse::<u16>() {
+                        Ok(n) => Some(n),
+                        Err(_) => return Err(BadToken(format!("Expected channel number, found {}", x)))
+                    },
+                    x => return Err(BadToken(format!("Expected channel number, found {}", x)))
+                };
+                try!(end_expr(tokens));
+            },
+            Picture => {
+                picture = match try!(strip(tokens.next())) {
+                    Data(x) => Some(x),
+                    x => return Err(BadToken(format!("Expected picture location, found {}", x)))
+                };
+                try!(end_expr(tokens));
+            },
+            Watershed => watershed.push(try!(add_watershed(tokens))),
+            AiringKw => airings.push(try!(add_airing(tokens))),
+            RParen => break,
+            x => return Err(BadToken(format!("Expected number, picture, watershed or airing but found {}", x)))
+        }
+    }
+
+    let number = match number {
+        Some(n) => n,
+        None => return Err(BadToken(format!("Channel {} has no number", name)))
+    };
+
+    let mut channel = Channel::new(&name, number, picture);
+    for rule in watershed.into_iter() {
+        channel.add_watershed_rule(rule);
+    }
+    for airing in airings.into_iter() {
+        channel.add_airing(airing);
+    }
+    Ok(channel)
+}
+
+pub fn translate_lineup(tokens: &mut TokenStream<Token, ParseError>) -> Result<Lineup, ParseError> {
+    try!(begin_expr(tokens));
+    match try!(strip(tokens.next())) {
+        LineupKw => { },
+        _ => return Err(BadAction)
+    }
+
+    let name = match try!(strip(tokens.next())) {
+        Data(x) => x,
+        x => return Err(BadToken(format!("{}{}", "Expected name but found ", x)))
+    };
+
+    let mut lineup = Lineup::new(&name, Vec::new());
+    loop {
+        match tokens.next() {
+            Some(Ok(LParen)) => continue,
+            Some(Ok(ChannelKw)) => {
+                if let Err(f) = lineup.add_channel(try!(add_channel(tokens))) {
+                    return Err(BadToken(f.to_string()))
+                }
+            },
+            Some(Ok(RParen)) => break,
+            Some(Ok(x)) => return Err(BadToken(format!("Expected (, ), or channel but found {}", x))),
+            Some(Err(f)) => return Err(f),
+            None => return Err(UnbalancedParens)
+        }
+    }
+
+    try!(end_of_stream(tokens));
+    Ok(lineup)
 }
@@ -0,0 +1,230 @@
+//! Addresses programs nested anywhere inside a schedule.
+
+use std::fmt;
+use std::str::FromStr;
+use super::schedule::Schedule;
+use super::program::{Instruction, Program};
+use super::parse::ParseError;
+use super::error::{self, Error};
+
+/// The location of a program in a schedule. The first index is the program's
+/// place in the schedule; every index after it picks an instruction of the
+/// program found so far, which must be a subprogram. All indices start at 0.
+///
+/// Written as the indices separated by slashes, so `5/3/2` is the subprogram
+/// at instruction 2 of the subprogram at instruction 3 of program 5.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProgramPath {
+    indices: Vec<usize>
+}
+
+fn descend<'a>(prog: &'a Program, rest: &[usize]) -> Option<&'a Program> {
+    if rest.len() == 0 {
+        Some(prog)
+    } else {
+        match prog.get_instr_at(rest[0]).and_then(|instr| instr.subprogram()) {
+            Some(sub) => descend(sub, &rest[1..]),
+            None => None
+        }
+    }
+}
+
+fn descend_mut<'a>(prog: &'a mut Program, rest: &[usize]) -> Option<&'a mut Program> {
+    if rest.len() == 0 {
+        Some(prog)
+    } else {
+        match prog.get_instr_mut_at(rest[0]).and_then(|instr| instr.subprogram_mut()) {
+            Some(sub) => descend_mut(sub, &rest[1..]),
+            None => None
+        }
+    }
+}
+
+fn collect(prog: &Program, path: &ProgramPath, paths: &mut Vec<ProgramPath>) {
+    paths.push(path.clone());
+    for (idx, instr) in prog.get_instrs().iter().enumerate() {
+        if let Some(sub) = instr.subprogram() {
+            collect(sub, &path.child(idx), paths);
+        }
+    }
+}
+
+impl<'a> ProgramPath {
+    /// The path of a top level program.
+    pub fn new(idx: usize) -> ProgramPath {
+        ProgramPath { indices: vec!(idx) }
+    }
+
+    /// The path of every program in the schedule, subprograms included,
+    /// each program before those inside it.
+    pub fn all(sched: &Schedule) -> Vec<ProgramPath> {
+        let mut paths = Vec::new();
+        for idx in 0 .. sched.programs_len() {
+            if let Some(prog) = sched.get_program_at(idx) {
+                collect(prog, &ProgramPath::new(idx), &mut paths);
+            }
+        }
+        paths
+    }
+
+    pub fn from_indices(indices: &[usize]) -> Option<ProgramPath> {
+        if indices.len() == 0 {
+            None
+        } else {
+            Some(ProgramPath { indices: indices.to_vec() })
+        }
+    }
+
+    pub fn indices(&'a self) -> &'a [usize] {
+        &self.indices
+    }
+
+    /// How deeply nested the program is. Top level programs have a depth of 1.
+    pub fn depth(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_top_level(&self) -> bool {
+        self.indices.len() == 1
+    }
+
+    /// The path of the subprogram at instruction `instr` of this program.
+    pub fn child(&self, instr: usize) -> ProgramPath {
+        let mut indices = self.indices.clone();
+        indices.push(instr);
+        ProgramPath { indices: indices }
+    }
+
+    /// The path of the program containing this one, if it is a subprogram.
+    pub fn parent(&self) -> Option<ProgramPath> {
+        if self.is_top_level() {
+            None
+        } else {
+            Some(ProgramPath { indices: self.indices[.. self.indices.len() - 1].to_vec() })
+        }
+    }
+
+    /// The last index in the path.
+    pub fn last(&self) -> usize {
+        self.indices[self.indices.len() - 1]
+    }
+
+    pub fn get<'b>(&self, sched: &'b Schedule) -> Option<&'b Program> {
+        match sched.get_program_at(self.indices[0]) {
+            Some(prog) => descend(prog, &self.indices[1..]),
+            None => None
+        }
+    }
+
+    pub fn get_mut<'b>(&self, sched: &'b mut Schedule) -> Option<&'b mut Program> {
+        match sched.get_program_mut_at(self.indices[0]) {
+            Some(prog) => descend_mut(prog, &self.indices[1..]),
+            None => None
+        }
+    }
+
+    fn parent_mut<'b>(&self, sched: &'b mut Schedule) -> error::Result<&'b mut Program> {
+        let parent = self.parent().unwrap();
+        match parent.get_mut(sched) {
+            Some(x) => Ok(x),
+            None => Err(Error::NotFound(format!("There is no program at {}", parent)))
+        }
+    }
+
+    /// Inserts `prog` so that it is found at this path, moving what was there
+    /// and everything after it along by one. A subprogram is inserted as a new
+    /// instruction of its parent.
+    pub fn insert(&self, sched: &mut Schedule, prog: &Program) -> error::Result<()> {
+        if self.is_top_level() {
+            sched.insert_program(self.last(), prog)
+        } else {
+            try!(self.parent_mut(sched)).insert_instruction(self.last(), &Instruction::SubProgram(prog.clone()))
+        }
+    }
+
+    /// Removes the program at this path and gives it back. A subprogram's
+    /// instruction is removed from its parent along with it.
+    pub fn remove(&self, sched: &mut Schedule) -> error::Result<Program> {
+        if self.is_top_level() {
+            let prog = match sched.get_program_at(self.last()) {
+                Some(x) => x.clone(),
+                None => return Err(Error::OutOfBounds(self.last(), sched.programs_len()))
+            };
+            try!(sched.delete_program(self.last()));
+            Ok(prog)
+        } else {
+            let parent = try!(self.parent_mut(sched));
+            if parent.get_instr_at(self.last()).map_or(true, |x| x.subprogram().is_none()) {
+                return Err(Error::NotFound(format!("There is no program at {}", self)))
+            }
+            match try!(parent.remove_instruction(self.last())) {
+                Instruction::SubProgram(prog) => Ok(prog),
+                _ => unreachable!()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ProgramPath {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (n, idx) in self.indices.iter().enumerate() {
+            if n > 0 {
+                try!(write!(fmt, "/"));
+            }
+            try!(write!(fmt, "{}", idx));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ProgramPath {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<ProgramPath, ParseError> {
+        let mut indices = Vec::new();
+        for part in s.trim().split('/') {
+            match part.parse::<usize>() {
+                Ok(x) => indices.push(x),
+                Err(_) => return Err(ParseError::BadToken(format!("{} is not a program path like 5/3/2", s)))
+            }
+        }
+        Ok(ProgramPath { indices: indices })
+    }
+}
+
+#[test]
+fn nested_lookup() {
+    use super::program::Source;
+
+    let mut sched = super::parse::parse("(schedule \"nested\"
+ (program (local \"first\") (instr (play)))
+ (program (local \"top\")
+  (instr (program (local \"middle\")
+          (instr (play 00:00:00 00:00:10) (program (local \"leaf\") (instr (play))))))))").unwrap();
+
+    let path = ProgramPath::new(1).child(0).child(1);
+    assert_eq!(path.get(&sched).unwrap().get_path(), Some("leaf"));
+    assert_eq!(path.parent().unwrap().get(&sched).unwrap().get_path(), Some("middle"));
+    assert!(ProgramPath::new(1).child(0).child(0).get(&sched).is_none());
+    assert!(ProgramPath::new(2).get(&sched).is_none());
+
+    path.get_mut(&mut sched).unwrap().set_location(&Source::Pathname("changed".to_string()));
+    assert_eq!(path.get(&sched).unwrap().get_path(), Some("changed"));
+
+    assert_eq!(path.to_string(), "1/0/1");
+    assert_eq!("1/0/1".parse::<ProgramPath>(), Ok(path.clone()));
+    assert!("1//2".parse::<ProgramPath>().is_err());
+    assert_eq!(ProgramPath::all(&sched).iter().map(|x| x.to_string()).collect::<Vec<String>>(),
+               vec!("0", "1", "1/0", "1/0/1"));
+
+    let leaf = path.remove(&mut sched).unwrap();
+    assert_eq!(leaf.get_path(), Some("changed"));
+    assert!(path.get(&sched).is_none());
+    assert!(path.remove(&mut sched).is_err());
+    assert!(ProgramPath::new(1).child(0).child(0).remove(&mut sched).is_err());
+
+    path.insert(&mut sched, &leaf).unwrap();
+    assert_eq!(path.get(&sched), Some(&leaf));
+    ProgramPath::new(2).insert(&mut sched, &leaf).unwrap();
+    assert_eq!(sched.programs_len(), 3);
+}
@@ -1,5 +1,8 @@
 use std::fmt;
 use super::tags::Tags;
+use super::track::{Track, TrackKind, TrackSelector};
+use super::metadata;
+use super::error::{self, Error};
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Source {
@@ -50,7 +53,9 @@ impl fmt::Display for Source {
 #[derive(Clone, PartialEq, Debug)]
 pub enum Instruction {
     Play(usize, usize), //Start Time and End Time.
-    SubProgram(Program)
+    SubProgram(Program),
+    /// Chooses the audio track or subtitles for the plays that follow.
+    Select(TrackKind, TrackSelector)
 }
 
 impl<'a> Instruction {
@@ -89,6 +94,20 @@ impl<'a> Instruction {
         }
     }
 
+    /// How long this instruction plays for, in seconds, given the runtime of
+    /// the program it belongs to.
+    pub fn running_time(&self, runtime: Option<usize>) -> usize {
+        match *self {
+            Instruction::Play(start, 0) => match runtime {
+                Some(runtime) if runtime > start => runtime - start,
+                _ => 0
+            },
+            Instruction::Play(_, duration) => duration,
+            Instruction::SubProgram(ref prog) => prog.running_time(),
+            Instruction::Select(_, _) => 0
+        }
+    }
+
     pub fn subprogram(&'a self) -> Option<&'a Program> {
         match self {
             &Instruction::SubProgram(ref p) => Some(p),
@@ -160,6 +179,9 @@ impl fmt::Display for Instruction {
             },
             Instruction::SubProgram(ref x) => {
                 try!(write!(fmt, "{}", x.to_string()));
+            },
+            Instruction::Select(kind, ref selector) => {
+                try!(write!(fmt, "({} \"{}\")", kind, selector));
             }
         }
         Ok(())
@@ -250,6 +272,50 @@ impl<'a> Program {
         self.tags = Tags::new();
     }
 
+    /// The runtime tag of this program, in seconds.
+    pub fn runtime(&self) -> Option<usize> {
+        self.tags.runtime
+    }
+
+    /// How long this program's media is, in seconds: its runtime tag, or when
+    /// it has none, the length of its local file as the file's headers give it.
+    pub fn length(&self) -> Option<usize> {
+        self.tags.runtime.or_else(|| metadata::local_path(&self.location).and_then(|x| metadata::duration(&x)))
+    }
+
+    /// Computes how long this program plays for, in seconds, including any
+    /// subprograms. A play instruction with no duration plays until the end of
+    /// the media, which needs the length of the media to be known.
+    pub fn running_time(&self) -> usize {
+        let runtime = self.length();
+        self.instructions.iter().fold(0, |total, instr| total + instr.running_time(runtime))
+    }
+
+    /// The track of `kind` playing at instruction `idx`: what the last `audio`
+    /// or `subtitle` instruction before it chose, or else the track marked as
+    /// the default. Without one, the first audio track plays and subtitles
+    /// are off, apart from forced ones in the language of the audio.
+    pub fn selected_track(&'a self, kind: TrackKind, idx: usize) -> Option<&'a Track> {
+        let tracks = self.tags.tracks(kind);
+        let language = match kind {
+            TrackKind::Audio => None,
+            TrackKind::Subtitle => self.selected_track(TrackKind::Audio, idx).and_then(|x| x.language.as_ref())
+        };
+        let language = language.map(|x| &x[..]);
+        let chosen = self.instructions.iter().take(idx).filter_map(|instr| match *instr {
+            Instruction::Select(k, ref selector) if k == kind => Some(selector),
+            _ => None
+        }).last();
+
+        match chosen {
+            Some(selector) => selector.select(tracks, language),
+            None => tracks.iter().find(|x| x.default).or_else(|| match kind {
+                TrackKind::Audio => tracks.first(),
+                TrackKind::Subtitle => TrackSelector::Off.select(tracks, language)
+            })
+        }
+    }
+
     pub fn get_instrs(&'a self) -> &'a Vec<Instruction> {
         &self.instructions
     }
@@ -283,9 +349,9 @@ impl<'a> Program {
         self.next_instr();
     }
 
-    pub fn insert_instruction(&mut self, idx: usize, elt: &Instruction) -> Result<(), String> {
+    pub fn insert_instruction(&mut self, idx: usize, elt: &Instruction) -> error::Result<()> {
         if idx > self.instructions.len() {
-            Err("Out of bounds".to_string())
+            Err(Error::OutOfBounds(idx, self.instructions.len()))
         } else {
             self.instructions.insert(idx, elt.clone());
             Ok(())
@@ -303,28 +369,46 @@ impl<'a> Program {
         prog
     }
 
-    pub fn remove_instruction(&mut self, idx: usize) -> Result<Instruction, String> {
-        if idx > self.instructions.len() {
-            Err("Out of bounds".to_string())
-        } else {
-            if idx == self.instructions.len() {
-                self.prev_instr();
-            } else if idx > self.current_instr.unwrap() {
-                self.current_instr = Some(idx - 1);
-            }
-            Ok(self.instructions.remove(idx))
+    pub fn remove_instruction(&mut self, idx: usize) -> error::Result<Instruction> {
+        try!(error::check_index(idx, self.instructions.len()));
+        let instr = self.instructions.remove(idx);
+        let len = self.instructions.len();
+        match self.current_instr {
+            _ if len == 0 => self.current_instr = None,
+            Some(current) if current >= len => self.current_instr = Some(len - 1),
+            _ => { }
         }
+        Ok(instr)
     }
 
-    pub fn set_current_instr_idx(&mut self, idx: usize) -> Result<(), String> {
-        if idx > self.instructions.len() {
-            Err("Out of bounds".to_string())
-        } else {
-            self.current_instr = Some(idx);
-            Ok(())
+    /// Replaces the instruction at `idx`, returning the old one.
+    pub fn set_instruction(&mut self, idx: usize, elt: &Instruction) -> error::Result<Instruction> {
+        let len = self.instructions.len();
+        match self.instructions.get_mut(idx) {
+            Some(instr) => Ok(::std::mem::replace(instr, elt.clone())),
+            None => Err(Error::OutOfBounds(idx, len))
         }
     }
 
+    /// Moves the instruction at `from` so that it ends up at `to`.
+    pub fn move_instruction(&mut self, from: usize, to: usize) -> error::Result<()> {
+        try!(error::check_index(from, self.instructions.len()));
+        try!(error::check_index(to, self.instructions.len()));
+        let instr = self.instructions.remove(from);
+        self.instructions.insert(to, instr);
+        Ok(())
+    }
+
+    pub fn get_current_instr_idx(&self) -> Option<usize> {
+        self.current_instr
+    }
+
+    pub fn set_current_instr_idx(&mut self, idx: usize) -> error::Result<()> {
+        try!(error::check_index(idx, self.instructions.len()));
+        self.current_instr = Some(idx);
+        Ok(())
+    }
+
     pub fn next_instr(&mut self) {
         let current_maybe = self.current_instr;
         match (self.instructions.len() > 0, current_maybe) {
@@ -0,0 +1,419 @@
+//! A small language for finding programs by their tags, such as
+//!
+//! ```text
+//! media_type = anime and studio = "Studio Deen" and airdate > 2014 and runtime < 25m
+//! ```
+//!
+//! A comparison is a tag name, an operator and a value, which is quoted when
+//! it holds spaces. `=` and `!=` compare text without regard to case; for tags
+//! holding several values, such as genres, `=` asks whether any of them is
+//! the value. `<`, `<=`, `>` and `>=` work on `year`, `season`, `episode`,
+//! `track_no`, `runtime` and `airdate`. An airdate can be given as just a year
+//! or a year and month, so `airdate > 2014` means after the end of 2014. An
+//! airdate only known to the month stands for the whole month: it is before
+//! 2014-06-15 only if all of it is. `~` matches a substring and `=~` a regular
+//! expression. `has tag` asks whether a tag is set at all. Comparisons combine
+//! with `and`, `or`, `not` and parentheses.
+
+extern crate chrono;
+extern crate regex;
+
+use std::fmt;
+use self::chrono::NaiveDate;
+use self::regex::Regex;
+use super::schedule::Schedule;
+use super::program::Program;
+use super::path::ProgramPath;
+use super::tags::{PartialDate, Tags, TagType};
+use super::parse::ParseError;
+use super::parse::ParseError::BadToken;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Text(String),
+    Number(usize),
+    /// The first and last days of a year, month or single day.
+    Dates(NaiveDate, NaiveDate)
+}
+
+#[derive(Clone, Debug)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Has(TagType),
+    Compare(TagType, Op, Value),
+    /// The tag holds the text, ignoring case.
+    Contains(TagType, String),
+    Matches(TagType, Regex)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Lexeme {
+    LParen,
+    RParen,
+    Word(String),
+    Quoted(String),
+    Op(String)
+}
+
+impl fmt::Display for Lexeme {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Lexeme::LParen => write!(fmt, "("),
+            Lexeme::RParen => write!(fmt, ")"),
+            Lexeme::Word(ref x) | Lexeme::Op(ref x) => write!(fmt, "{}", x),
+            Lexeme::Quoted(ref x) => write!(fmt, "\"{}\"", x)
+        }
+    }
+}
+
+fn lex(s: &str) -> Result<Vec<Lexeme>, ParseError> {
+    let mut lexemes = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => lexemes.push(Lexeme::LParen),
+            ')' => lexemes.push(Lexeme::RParen),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(x) => text.push(x),
+                        None => return Err(BadToken("Unterminated quote in query".to_string()))
+                    }
+                }
+                lexemes.push(Lexeme::Quoted(text));
+            },
+            '=' | '!' | '<' | '>' | '~' => {
+                let mut op = c.to_string();
+                if let Some(&next) = chars.peek() {
+                    if next == '=' || (c == '=' && next == '~') {
+                        op.push(next);
+                        chars.next();
+                    }
+                }
+                if op == "!" {
+                    return Err(BadToken("Expected != in query".to_string()))
+                }
+                lexemes.push(Lexeme::Op(op));
+            },
+            x if x.is_whitespace() => { },
+            x => {
+                let mut word = x.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "()\"=!<>~".contains(next) {
+                        break
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                lexemes.push(Lexeme::Word(word));
+            }
+        }
+    }
+    Ok(lexemes)
+}
+
+fn is_keyword(lexeme: Option<&Lexeme>, keyword: &str) -> bool {
+    match lexeme {
+        Some(&Lexeme::Word(ref x)) => x.to_lowercase() == keyword,
+        _ => false
+    }
+}
+
+fn is_numeric(tag: &TagType) -> bool {
+    match *tag {
+        TagType::Year | TagType::Season | TagType::Episode | TagType::TrackNo | TagType::Runtime => true,
+        _ => false
+    }
+}
+
+/// Reads `yyyy`, `yyyy-mm` or `yyyy-mm-dd` as the days it covers.
+fn parse_dates(s: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let mut parts = Vec::new();
+    for part in s.split('-') {
+        match part.parse::<u32>() {
+            Ok(x) => parts.push(x),
+            Err(_) => return None
+        }
+    }
+    match parts.len() {
+        1 => NaiveDate::from_ymd_opt(parts[0] as i32, 1, 1).and_then(|x| {
+            NaiveDate::from_ymd_opt(parts[0] as i32, 12, 31).map(|y| (x, y))
+        }),
+        2 => PartialDate::month(parts[0] as i32, parts[1]).map(|x| (x.first_day(), x.last_day())),
+        3 => NaiveDate::from_ymd_opt(parts[0] as i32, parts[1], parts[2]).map(|x| (x, x)),
+        _ => None
+    }
+}
+
+fn parse_value(tag: &TagType, op: Op, text: &str) -> Result<Value, ParseError> {
+    match *tag {
+        TagType::Runtime => {
+            let mut tags = Tags::new();
+            try!(tags.modify_tag(tag, text));
+            Ok(Value::Number(tags.runtime.unwrap()))
+        },
+        TagType::Airdate => match parse_dates(text) {
+            Some((first, last)) => Ok(Value::Dates(first, last)),
+            None => Err(BadToken(format!("Expected a date like 2014, 2014-05 or 2014-05-03, found {}", text)))
+        },
+        ref x if is_numeric(x) => match text.parse::<usize>() {
+            Ok(n) => Ok(Value::Number(n)),
+            Err(_) => Err(BadToken(format!("Expected a number for {}, found {}", tag, text)))
+        },
+        _ if op == Op::Eq || op == Op::Ne => Ok(Value::Text(text.to_string())),
+        _ => Err(BadToken(format!("{} cannot be compared with < or >", tag)))
+    }
+}
+
+struct Parser {
+    lexemes: Vec<Lexeme>,
+    pos: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Lexeme, ParseError> {
+        match self.lexemes.get(self.pos).cloned() {
+            Some(x) => {
+                self.pos += 1;
+                Ok(x)
+            },
+            None => Err(BadToken("The query ends too soon".to_string()))
+        }
+    }
+
+    fn or(&mut self) -> Result<Query, ParseError> {
+        let mut query = try!(self.and());
+        while is_keyword(self.peek(), "or") {
+            self.pos += 1;
+            query = Query::Or(Box::new(query), Box::new(try!(self.and())));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, ParseError> {
+        let mut query = try!(self.not());
+        while is_keyword(self.peek(), "and") {
+            self.pos += 1;
+            query = Query::And(Box::new(query), Box::new(try!(self.not())));
+        }
+        Ok(query)
+    }
+
+    fn not(&mut self) -> Result<Query, ParseError> {
+        if is_keyword(self.peek(), "not") {
+            self.pos += 1;
+            Ok(Query::Not(Box::new(try!(self.not()))))
+        } else {
+            self.atom()
+        }
+    }
+
+    fn tag(&mut self) -> Result<TagType, ParseError> {
+        match try!(self.next()) {
+            Lexeme::Word(ref x) => x.parse::<TagType>().map_err(|_| BadToken(format!("{} is not a tag", x))),
+            x => Err(BadToken(format!("Expected a tag, found {}", x)))
+        }
+    }
+
+    fn atom(&mut self) -> Result<Query, ParseError> {
+        if self.peek() == Some(&Lexeme::LParen) {
+            self.pos += 1;
+            let query = try!(self.or());
+            return match try!(self.next()) {
+                Lexeme::RParen => Ok(query),
+                x => Err(BadToken(format!("Expected ), found {}", x)))
+            }
+        }
+        if is_keyword(self.peek(), "has") {
+            self.pos += 1;
+            return Ok(Query::Has(try!(self.tag())))
+        }
+
+        let tag = try!(self.tag());
+        let op = match try!(self.next()) {
+            Lexeme::Op(x) => x,
+            x => return Err(BadToken(format!("Expected an operator after {}, found {}", tag, x)))
+        };
+        let text = match try!(self.next()) {
+            Lexeme::Word(x) | Lexeme::Quoted(x) => x,
+            x => return Err(BadToken(format!("Expected a value, found {}", x)))
+        };
+
+        let op = match &op[..] {
+            "~" => return Ok(Query::Contains(tag, text.to_lowercase())),
+            "=~" => return match Regex::new(&text) {
+                Ok(re) => Ok(Query::Matches(tag, re)),
+                Err(f) => Err(BadToken(format!("Bad regular expression {}: {}", text, f)))
+            },
+            "=" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            x => return Err(BadToken(format!("Unknown operator {}", x)))
+        };
+        let value = try!(parse_value(&tag, op, &text));
+        Ok(Query::Compare(tag, op, value))
+    }
+}
+
+/// The values of a tag as text, one for each value of a list.
+fn texts(tags: &Tags, tag: &TagType) -> Vec<String> {
+    match tags.values(tag) {
+        Some(x) => x,
+        None => tags.get_tag(tag).into_iter().collect()
+    }
+}
+
+fn number(tags: &Tags, tag: &TagType) -> Option<usize> {
+    match *tag {
+        TagType::Year => tags.year.map(|x| x as usize),
+        TagType::Season => tags.season.map(|x| x as usize),
+        TagType::Episode => tags.episode.map(|x| x as usize),
+        TagType::TrackNo => tags.track_no.map(|x| x as usize),
+        TagType::Runtime => tags.runtime,
+        _ => None
+    }
+}
+
+fn compare(tags: &Tags, tag: &TagType, op: Op, value: &Value) -> bool {
+    match *value {
+        Value::Text(ref text) => {
+            let text = text.to_lowercase();
+            let found = texts(tags, tag).iter().any(|x| x.trim().to_lowercase() == text);
+            if op == Op::Ne { !found } else { found }
+        },
+        Value::Number(n) => match number(tags, tag) {
+            Some(x) => match op {
+                Op::Eq => x == n,
+                Op::Ne => x != n,
+                Op::Lt => x < n,
+                Op::Le => x <= n,
+                Op::Gt => x > n,
+                Op::Ge => x >= n
+            },
+            None => op == Op::Ne
+        },
+        Value::Dates(first, last) => match tags.airdate {
+            Some(date) => {
+                let (start, end) = (date.first_day(), date.last_day());
+                match op {
+                    Op::Eq => first <= start && end <= last,
+                    Op::Ne => start < first || last < end,
+                    Op::Lt => end < first,
+                    Op::Le => end <= last,
+                    Op::Gt => last < start,
+                    Op::Ge => first <= start
+                }
+            },
+            None => op == Op::Ne
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(s: &str) -> Result<Query, ParseError> {
+        let mut parser = Parser { lexemes: try!(lex(s)), pos: 0 };
+        let query = try!(parser.or());
+        match parser.peek() {
+            None => Ok(query),
+            Some(x) => Err(BadToken(format!("Unexpected {} in query", x)))
+        }
+    }
+
+    pub fn matches(&self, tags: &Tags) -> bool {
+        match *self {
+            Query::And(ref x, ref y) => x.matches(tags) && y.matches(tags),
+            Query::Or(ref x, ref y) => x.matches(tags) || y.matches(tags),
+            Query::Not(ref x) => !x.matches(tags),
+            Query::Has(ref tag) => tags.get_tag(tag).is_some(),
+            Query::Compare(ref tag, op, ref value) => compare(tags, tag, op, value),
+            Query::Contains(ref tag, ref text) => texts(tags, tag).iter().any(|x| x.to_lowercase().contains(text)),
+            Query::Matches(ref tag, ref re) => texts(tags, tag).iter().any(|x| re.is_match(x))
+        }
+    }
+
+    /// The programs of a catalog whose own tags match.
+    pub fn filter<'a>(&self, catalog: &'a [Program]) -> Vec<&'a Program> {
+        catalog.iter().filter(|x| self.matches(x.get_tags())).collect()
+    }
+
+    /// Every program in the schedule, subprograms included, whose effective
+    /// tags match, in the order they play.
+    pub fn search(&self, sched: &Schedule) -> Vec<ProgramPath> {
+        let mut found = Vec::new();
+        for idx in 0 .. sched.programs_len() {
+            if let Some(prog) = sched.get_program_at(idx) {
+                self.search_program(prog, &ProgramPath::new(idx), sched.defaults(), &mut found);
+            }
+        }
+        found
+    }
+
+    fn search_program(&self, prog: &Program, path: &ProgramPath, parent: &Tags, found: &mut Vec<ProgramPath>) {
+        let tags = prog.get_tags().inherit(parent);
+        if self.matches(&tags) {
+            found.push(path.clone());
+        }
+        for (idx, instr) in prog.get_instrs().iter().enumerate() {
+            if let Some(sub) = instr.subprogram() {
+                self.search_program(sub, &path.child(idx), &tags, found);
+            }
+        }
+    }
+}
+
+#[test]
+fn studio_deen_anime() {
+    use super::parse;
+
+    let catalog = parse::parse("(schedule \"catalog\"
+ (program (local \"fsn\") (tags :media_type \"anime\" :studio \"Studio Deen\" :airdate \"2006-01-06\"
+  :runtime \"24m\" :genre (list \"Fantasy\" \"Action\")) (instr (play)))
+ (program (local \"higurashi\") (tags :media_type \"anime\" :studio \"Studio Deen\" :airdate \"2015-01-01\"
+  :runtime \"24m\") (instr (play)))
+ (program (local \"ubw\") (tags :media_type \"anime\" :studio \"ufotable\" :airdate \"2014-10-04\"
+  :runtime \"30m\" :genre \"Fantasy\") (instr (play)))
+ (program (local \"fma\") (tags :media_type \"cartoon\" :studio \"Bones\" :airdate \"2014-12-31\"
+  :runtime \"24m\") (instr (play))))").unwrap().programs();
+
+    let query = Query::parse("media_type = anime and studio = \"Studio Deen\" and airdate > 2014 \
+                              and runtime < 25m").unwrap();
+    let found = query.filter(&catalog).iter().map(|x| x.get_path().unwrap()).collect::<Vec<&str>>();
+    assert_eq!(found, vec!("higurashi"));
+
+    let found = |q: &str| Query::parse(q).unwrap().filter(&catalog).len();
+    assert_eq!(found("genre = fantasy"), 2);
+    assert_eq!(found("not (genre = fantasy or has genre)"), 2);
+    assert_eq!(found("airdate = 2014"), 2);
+    assert_eq!(found("airdate >= 2014-12 and airdate <= 2014-12"), 1);
+    assert_eq!(found("airdate < 2015-01-01"), 3);
+    assert_eq!(found("studio ~ deen"), 2);
+    assert_eq!(found("studio =~ \"^[a-z]+$\""), 1);
+    assert!(Query::parse("title < 5").is_err());
+    assert!(Query::parse("dierekteur = x").is_err());
+    assert!(Query::parse("(year = 1").is_err());
+
+    let sched = parse::parse("(schedule \"night\" (defaults :media_type \"anime\")
+ (program (local \"ad\") (tags :title \"Ad\") (instr (play))))").unwrap();
+    assert_eq!(Query::parse("media_type = anime").unwrap().search(&sched), vec!(ProgramPath::new(0)));
+}
@@ -0,0 +1,391 @@
+//! Content ratings and the watershed rules that keep mature programs late at
+//! night.
+
+extern crate chrono;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use self::chrono::{Duration, NaiveDateTime, NaiveTime};
+use super::parse::ParseError;
+use super::parse::ParseError::*;
+use super::program::Program;
+use super::schedule::Schedule;
+use super::channel::DATETIME_FORMAT;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum MpaaRating {
+    G,
+    PG,
+    PG13,
+    R,
+    NC17
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum TvRating {
+    TVY,
+    TVY7,
+    TVG,
+    TVPG,
+    TV14,
+    TVMA
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum BbfcRating {
+    U,
+    PG,
+    Twelve,
+    TwelveA,
+    Fifteen,
+    Eighteen,
+    R18
+}
+
+/// How suitable a program is for younger audiences. Ratings from different
+/// systems are compared through `level`, from 0 (all ages) to 5 (adults only).
+/// Custom ratings carry their own level, if they are given one; ratings this
+/// crate does not know, such as `NR` or `TV-14 LV`, are kept without a level
+/// and are not held to watershed rules.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Rating {
+    Mpaa(MpaaRating),
+    Tv(TvRating),
+    Bbfc(BbfcRating),
+    Custom(String, Option<u8>)
+}
+
+pub static MAX_LEVEL: u8 = 5;
+
+impl Rating {
+    pub fn level(&self) -> Option<u8> {
+        Some(match *self {
+            Rating::Mpaa(x) => match x {
+                MpaaRating::G => 0,
+                MpaaRating::PG => 1,
+                MpaaRating::PG13 => 2,
+                MpaaRating::R => 4,
+                MpaaRating::NC17 => 5
+            },
+            Rating::Tv(x) => match x {
+                TvRating::TVY | TvRating::TVG => 0,
+                TvRating::TVY7 | TvRating::TVPG => 1,
+                TvRating::TV14 => 3,
+                TvRating::TVMA => 4
+            },
+            Rating::Bbfc(x) => match x {
+                BbfcRating::U => 0,
+                BbfcRating::PG => 1,
+                BbfcRating::Twelve | BbfcRating::TwelveA => 2,
+                BbfcRating::Fifteen => 3,
+                BbfcRating::Eighteen => 4,
+                BbfcRating::R18 => 5
+            },
+            Rating::Custom(_, level) => return level
+        })
+    }
+
+    fn system(&self) -> u8 {
+        match *self {
+            Rating::Mpaa(_) => 0,
+            Rating::Tv(_) => 1,
+            Rating::Bbfc(_) => 2,
+            Rating::Custom(_, _) => 3
+        }
+    }
+}
+
+impl PartialOrd for Rating {
+    fn partial_cmp(&self, other: &Rating) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Ratings are ordered by level first, so a TV-MA program sorts above a PG-13
+/// one. Ratings with the same level are then ordered by system. Ratings without
+/// a level sort below all others.
+impl Ord for Rating {
+    fn cmp(&self, other: &Rating) -> Ordering {
+        match self.level().cmp(&other.level()) {
+            Ordering::Equal => { },
+            x => return x
+        }
+        match self.system().cmp(&other.system()) {
+            Ordering::Equal => { },
+            x => return x
+        }
+        match (self, other) {
+            (&Rating::Mpaa(ref x), &Rating::Mpaa(ref y)) => x.cmp(y),
+            (&Rating::Tv(ref x), &Rating::Tv(ref y)) => x.cmp(y),
+            (&Rating::Bbfc(ref x), &Rating::Bbfc(ref y)) => x.cmp(y),
+            (&Rating::Custom(ref x, _), &Rating::Custom(ref y, _)) => x.cmp(y),
+            _ => Ordering::Equal
+        }
+    }
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rating::Mpaa(x) => try!(write!(fmt, "{}", match x {
+                MpaaRating::G => "G",
+                MpaaRating::PG => "PG",
+                MpaaRating::PG13 => "PG-13",
+                MpaaRating::R => "R",
+                MpaaRating::NC17 => "NC-17"
+            })),
+            Rating::Tv(x) => try!(write!(fmt, "{}", match x {
+                TvRating::TVY => "TV-Y",
+                TvRating::TVY7 => "TV-Y7",
+                TvRating::TVG => "TV-G",
+                TvRating::TVPG => "TV-PG",
+                TvRating::TV14 => "TV-14",
+                TvRating::TVMA => "TV-MA"
+            })),
+            Rating::Bbfc(x) => try!(write!(fmt, "BBFC {}", match x {
+                BbfcRating::U => "U",
+                BbfcRating::PG => "PG",
+                BbfcRating::Twelve => "12",
+                BbfcRating::TwelveA => "12A",
+                BbfcRating::Fifteen => "15",
+                BbfcRating::Eighteen => "18",
+                BbfcRating::R18 => "R18"
+            })),
+            Rating::Custom(ref name, Some(level)) => try!(write!(fmt, "{}/{}", name, level)),
+            Rating::Custom(ref name, None) => try!(write!(fmt, "{}", name))
+        }
+        Ok(())
+    }
+}
+
+/// Parses a rating. `PG` on its own is the MPAA rating; the BBFC one is
+/// written `BBFC PG`. Custom ratings are written as `NAME/LEVEL`, and any other
+/// text is kept as a rating without a level.
+impl FromStr for Rating {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Rating, ParseError> {
+        let s = s.trim();
+        if s.len() == 0 {
+            return Err(BadToken("Expected a rating".to_string()))
+        }
+        let bbfc = |x: &str| match x {
+            "U" => Some(BbfcRating::U),
+            "PG" => Some(BbfcRating::PG),
+            "12" => Some(BbfcRating::Twelve),
+            "12A" => Some(BbfcRating::TwelveA),
+            "15" => Some(BbfcRating::Fifteen),
+            "18" => Some(BbfcRating::Eighteen),
+            "R18" => Some(BbfcRating::R18),
+            _ => None
+        };
+
+        let res = match s {
+            "G" => Rating::Mpaa(MpaaRating::G),
+            "PG" => Rating::Mpaa(MpaaRating::PG),
+            "PG-13" | "PG13" => Rating::Mpaa(MpaaRating::PG13),
+            "R" => Rating::Mpaa(MpaaRating::R),
+            "NC-17" | "NC17" => Rating::Mpaa(MpaaRating::NC17),
+
+            "TV-Y" | "TVY" => Rating::Tv(TvRating::TVY),
+            "TV-Y7" | "TVY7" => Rating::Tv(TvRating::TVY7),
+            "TV-G" | "TVG" => Rating::Tv(TvRating::TVG),
+            "TV-PG" | "TVPG" => Rating::Tv(TvRating::TVPG),
+            "TV-14" | "TV14" => Rating::Tv(TvRating::TV14),
+            "TV-MA" | "TVMA" => Rating::Tv(TvRating::TVMA),
+
+            x if x.starts_with("BBFC ") => match bbfc(x[5..].trim()) {
+                Some(rating) => Rating::Bbfc(rating),
+                None => return Err(BadToken(format!("Unknown BBFC rating {}", x)))
+            },
+
+            x => match (bbfc(x), x.rfind('/')) {
+                (Some(rating), _) => Rating::Bbfc(rating),
+                (None, Some(idx)) => match x[idx + 1..].parse::<u8>() {
+                    Ok(level) if level <= MAX_LEVEL && idx > 0 => Rating::Custom(x[..idx].to_string(), Some(level)),
+                    Ok(_) => return Err(BadToken(format!("Custom ratings need a level from 0 to {}, found {}",
+                                                         MAX_LEVEL, x))),
+                    Err(_) => Rating::Custom(x.to_string(), None)
+                },
+                (None, None) => Rating::Custom(x.to_string(), None)
+            }
+        };
+        Ok(res)
+    }
+}
+
+/// Programs rated at the level of `rating` or above may only air between `from` and `until`
+/// in the time of day. The window may wrap past midnight.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WatershedRule {
+    pub rating: Rating,
+    pub from: NaiveTime,
+    pub until: NaiveTime
+}
+
+impl fmt::Display for WatershedRule {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "(watershed \"{}\" \"{}\" \"{}\")", self.rating,
+                    self.from.format("%H:%M"), self.until.format("%H:%M")));
+        Ok(())
+    }
+}
+
+impl WatershedRule {
+    pub fn new(rating: Rating, from: NaiveTime, until: NaiveTime) -> WatershedRule {
+        WatershedRule {
+            rating: rating,
+            from: from,
+            until: until
+        }
+    }
+
+    fn in_window(&self, time: NaiveTime) -> bool {
+        if self.from <= self.until {
+            self.from <= time && time < self.until
+        } else {
+            self.from <= time || time < self.until
+        }
+    }
+
+    /// Whether a program rated `rating` may play from `start` to `end`.
+    pub fn allows(&self, rating: &Rating, start: NaiveDateTime, end: NaiveDateTime) -> bool {
+        match (rating.level(), self.rating.level()) {
+            (Some(x), Some(y)) if x >= y => { },
+            _ => return true
+        }
+        let last = if end > start { end - Duration::seconds(1) } else { start };
+        (last - start).num_days() == 0 && self.in_window(start.time()) && self.in_window(last.time())
+    }
+}
+
+/// A program that airs outside the watershed for its rating.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WatershedViolation {
+    /// The index of the program in the schedule, followed by the instruction
+    /// indices of any subprograms leading to the offending one.
+    pub path: Vec<usize>,
+    pub program: Program,
+    pub start: NaiveDateTime,
+    pub rule: WatershedRule
+}
+
+impl fmt::Display for WatershedViolation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.program.get_tags().title {
+            Some(ref title) => title.clone(),
+            None => self.program.get_location().to_string()
+        };
+        try!(write!(fmt, "{} ({}) is rated {} and airs at {}, but {} and above may only air from {} to {}",
+                    name, self.path.iter().map(|x| x.to_string()).collect::<Vec<String>>().join("/"),
+                    self.program.get_tags().rating.as_ref().unwrap(), self.start.format(DATETIME_FORMAT),
+                    self.rule.rating, self.rule.from.format("%H:%M"), self.rule.until.format("%H:%M")));
+        Ok(())
+    }
+}
+
+fn check_program(rules: &[WatershedRule], prog: &Program, path: &mut Vec<usize>, start: NaiveDateTime,
+                 violations: &mut Vec<WatershedViolation>) {
+    let end = start + Duration::seconds(prog.running_time() as i64);
+    if let Some(ref rating) = prog.get_tags().rating {
+        for rule in rules.iter().filter(|rule| !rule.allows(rating, start, end)) {
+            violations.push(WatershedViolation {
+                path: path.clone(),
+                program: prog.clone(),
+                start: start,
+                rule: rule.clone()
+            });
+        }
+    }
+
+    let mut time = start;
+    for (idx, instr) in prog.get_instrs().iter().enumerate() {
+        if let Some(sub) = instr.subprogram() {
+            path.push(idx);
+            check_program(rules, sub, path, time, violations);
+            path.pop();
+        }
+        time = time + Duration::seconds(instr.running_time(prog.length()) as i64);
+    }
+}
+
+/// Checks every program in `sched`, including subprograms, against `rules` if
+/// the schedule starts airing at `start`.
+pub fn check_watershed(rules: &[WatershedRule], sched: &Schedule, start: NaiveDateTime)
+                       -> Result<(), Vec<WatershedViolation>> {
+    let mut violations = Vec::new();
+    let mut time = start;
+    for (idx, prog) in sched.programs().iter().enumerate() {
+        let mut path = vec!(idx);
+        check_program(rules, prog, &mut path, time, &mut violations);
+        time = time + Duration::seconds(prog.running_time() as i64);
+    }
+
+    if violations.len() == 0 {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+#[test]
+fn rating_order() {
+    let parse = |x: &str| x.parse::<Rating>().unwrap();
+    assert!(parse("TV-MA") > parse("PG-13"));
+    assert!(parse("G") < parse("BBFC 12A"));
+    assert_eq!(parse("R").level(), parse("TV-MA").level());
+    assert_eq!(parse("15"), Rating::Bbfc(BbfcRating::Fifteen));
+    assert_eq!(parse("XBTV Late/4").level(), Some(4));
+    for text in ["PG", "BBFC PG", "TV-Y7", "NC-17", "XBTV Late/4", "TV-14 LV", "NR"].iter() {
+        assert_eq!(parse(text).to_string(), *text);
+    }
+    assert_eq!(parse("Unrated"), Rating::Custom("Unrated".to_string(), None));
+    assert!(parse("Unrated") < parse("G"));
+    assert!("Late/9".parse::<Rating>().is_err());
+    assert!("".parse::<Rating>().is_err());
+}
+
+#[test]
+fn watershed_violation() {
+    use self::chrono::NaiveDate;
+    use super::parse;
+
+    let sched = parse::parse("(schedule \"afternoon\"
+ (program (local \"sitcom\") (tags :rating \"TV-PG\" :runtime \"30m\") (instr (play 00:00:00 00:00:00)))
+ (program (local \"cartoon\") (tags :rating \"TV-Y\" :runtime \"30m\")
+  (instr (play 00:00:00 00:00:00)
+         (program (local \"trailer\") (tags :rating \"TV-MA\" :runtime \"30m\") (instr (play 00:00:00 00:00:00)))))
+ (program (local \"drama\") (tags :rating \"TV-MA\" :runtime \"30m\") (instr (play 00:00:00 00:00:00))))").unwrap();
+
+    let rules = vec!(WatershedRule::new("TV-MA".parse::<Rating>().unwrap(),
+                                        NaiveTime::from_hms(21, 0, 0), NaiveTime::from_hms(5, 30, 0)));
+    let day = NaiveDate::from_ymd(2015, 6, 1);
+
+    let violations = check_watershed(&rules, &sched, day.and_hms(14, 0, 0)).unwrap_err();
+    assert_eq!(violations.len(), 2);
+    assert_eq!(violations[0].path, vec!(1, 1));
+    assert_eq!(violations[0].start, day.and_hms(15, 0, 0));
+    assert_eq!(violations[1].path, vec!(2));
+    assert_eq!(violations[1].start, day.and_hms(15, 30, 0));
+
+    assert!(check_watershed(&rules, &sched, day.and_hms(19, 0, 0)).is_err());
+    assert!(check_watershed(&rules, &sched, day.and_hms(22, 0, 0)).is_ok());
+}
+
+#[test]
+fn unleveled_ratings() {
+    use self::chrono::NaiveDate;
+    use super::parse;
+
+    let sched = parse::parse("(schedule \"late\"
+ (program (local \"pilot\") (tags :rating \"NR\" :runtime \"30m\") (instr (play 00:00:00 00:00:00)))
+ (program (local \"finale\") (tags :rating \"TV-14 LV\" :runtime \"30m\") (instr (play 00:00:00 00:00:00))))").unwrap();
+    assert_eq!(sched.get_program_at(1).unwrap().get_tags().rating,
+               Some(Rating::Custom("TV-14 LV".to_string(), None)));
+    assert_eq!(parse::parse(&sched.to_string()), Ok(sched.clone()));
+
+    let rules = vec!(WatershedRule::new("TV-14".parse::<Rating>().unwrap(),
+                                        NaiveTime::from_hms(21, 0, 0), NaiveTime::from_hms(5, 30, 0)));
+    assert!(check_watershed(&rules, &sched, NaiveDate::from_ymd(2015, 6, 1).and_hms(14, 0, 0)).is_ok());
+}
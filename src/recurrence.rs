@@ -0,0 +1,432 @@
+//! Recurrence rules for airings, written in the style of iCalendar's RRULE.
+
+extern crate chrono;
+
+use std::fmt;
+use std::str::FromStr;
+use self::chrono::{Datelike, DateTime, Duration, Local, LocalResult, NaiveDate, NaiveDateTime,
+                   TimeZone, Weekday};
+use super::parse::ParseError;
+use super::parse::ParseError::*;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly
+}
+
+impl fmt::Display for Frequency {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "{}", match *self {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY"
+        }));
+        Ok(())
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Frequency, ParseError> {
+        match s {
+            "DAILY" => Ok(Frequency::Daily),
+            "WEEKLY" => Ok(Frequency::Weekly),
+            "MONTHLY" => Ok(Frequency::Monthly),
+            x => Err(BadToken(format!("Expected DAILY, WEEKLY or MONTHLY, found {}", x)))
+        }
+    }
+}
+
+fn weekday_str(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU"
+    }
+}
+
+fn parse_weekday(s: &str) -> Result<Weekday, ParseError> {
+    match s {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        x => Err(BadToken(format!("Expected a day such as MO or TU, found {}", x)))
+    }
+}
+
+/// Parses an iCalendar date, ignoring any time part: `20150601` or
+/// `20150601T180000Z`.
+fn parse_ical_date(s: &str) -> Result<NaiveDate, ParseError> {
+    let date = if s.len() > 8 { &s[..8] } else { s };
+    match NaiveDate::parse_from_str(date, "%Y%m%d") {
+        Ok(x) => Ok(x),
+        Err(_) => Err(BadToken(format!("Expected a date as yyyymmdd, found {}", s)))
+    }
+}
+
+fn parse_number<T: FromStr>(key: &str, s: &str) -> Result<T, ParseError> {
+    match s.parse::<T>() {
+        Ok(x) => Ok(x),
+        Err(_) => Err(BadToken(format!("Expected a number for {}, found {}", key, s)))
+    }
+}
+
+/// When and how often an airing repeats. The rule is anchored to the start of
+/// the airing it belongs to, which is always the first occurrence.
+///
+/// The textual form is an RRULE such as
+/// `FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR;UNTIL=20150601`, with exception dates
+/// given as an extra `EXDATE=20150525,20150526` part.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Recurrence {
+    freq: Frequency,
+    interval: u32,
+    by_day: Vec<Weekday>,
+    by_month_day: Vec<u32>,
+    until: Option<NaiveDate>,
+    count: Option<usize>,
+    exdates: Vec<NaiveDate>
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "FREQ={}", self.freq));
+        if self.interval != 1 {
+            try!(write!(fmt, ";INTERVAL={}", self.interval));
+        }
+        if self.by_day.len() > 0 {
+            try!(write!(fmt, ";BYDAY={}", self.by_day.iter().map(|&day| weekday_str(day))
+                        .collect::<Vec<&str>>().join(",")));
+        }
+        if self.by_month_day.len() > 0 {
+            try!(write!(fmt, ";BYMONTHDAY={}", self.by_month_day.iter().map(|x| x.to_string())
+                        .collect::<Vec<String>>().join(",")));
+        }
+        if let Some(until) = self.until {
+            try!(write!(fmt, ";UNTIL={}", until.format("%Y%m%d")));
+        }
+        if let Some(count) = self.count {
+            try!(write!(fmt, ";COUNT={}", count));
+        }
+        if self.exdates.len() > 0 {
+            try!(write!(fmt, ";EXDATE={}", self.exdates.iter().map(|x| x.format("%Y%m%d").to_string())
+                        .collect::<Vec<String>>().join(",")));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Recurrence, ParseError> {
+        let mut freq = None;
+        let mut rule = Recurrence::new(Frequency::Daily);
+
+        for part in s.trim().split(';').filter(|x| x.len() > 0) {
+            let (key, value) = match part.find('=') {
+                Some(idx) => (&part[..idx], &part[idx + 1..]),
+                None => return Err(BadToken(format!("Expected KEY=VALUE in recurrence rule, found {}", part)))
+            };
+
+            match key {
+                "FREQ" => freq = Some(try!(value.parse::<Frequency>())),
+                "INTERVAL" => rule.interval = try!(parse_number(key, value)),
+                "BYDAY" => for day in value.split(',') {
+                    rule.by_day.push(try!(parse_weekday(day)));
+                },
+                "BYMONTHDAY" => for day in value.split(',') {
+                    let day: u32 = try!(parse_number(key, day));
+                    if day < 1 || day > 31 {
+                        return Err(BadToken(format!("{} is not a day of the month", day)))
+                    }
+                    rule.by_month_day.push(day);
+                },
+                "UNTIL" => rule.until = Some(try!(parse_ical_date(value))),
+                "COUNT" => rule.count = Some(try!(parse_number(key, value))),
+                "EXDATE" => for date in value.split(',') {
+                    rule.exdates.push(try!(parse_ical_date(date)));
+                },
+                x => return Err(BadToken(format!("Unsupported recurrence rule part {}", x)))
+            }
+        }
+
+        rule.freq = match freq {
+            Some(x) => x,
+            None => return Err(BadToken("A recurrence rule needs a FREQ".to_string()))
+        };
+        try!(rule.validate());
+        Ok(rule)
+    }
+}
+
+impl Recurrence {
+    pub fn new(freq: Frequency) -> Recurrence {
+        Recurrence {
+            freq: freq,
+            interval: 1,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            until: None,
+            count: None,
+            exdates: Vec::new()
+        }
+    }
+
+    pub fn daily() -> Recurrence {
+        Recurrence::new(Frequency::Daily)
+    }
+
+    pub fn weekly(days: Vec<Weekday>) -> Recurrence {
+        let mut rule = Recurrence::new(Frequency::Weekly);
+        rule.by_day = days;
+        rule
+    }
+
+    pub fn monthly(days: Vec<u32>) -> Recurrence {
+        let mut rule = Recurrence::new(Frequency::Monthly);
+        rule.by_month_day = days;
+        rule
+    }
+
+    pub fn interval(mut self, interval: u32) -> Recurrence {
+        self.interval = interval;
+        self
+    }
+
+    pub fn until(mut self, until: NaiveDate) -> Recurrence {
+        self.until = Some(until);
+        self
+    }
+
+    pub fn count(mut self, count: usize) -> Recurrence {
+        self.count = Some(count);
+        self
+    }
+
+    pub fn except(mut self, date: NaiveDate) -> Recurrence {
+        self.exdates.push(date);
+        self
+    }
+
+    pub fn freq(&self) -> Frequency {
+        self.freq
+    }
+
+    pub fn exdates(&self) -> &Vec<NaiveDate> {
+        &self.exdates
+    }
+
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.interval == 0 {
+            Err(BadToken("INTERVAL must be at least 1".to_string()))
+        } else if self.until.is_some() && self.count.is_some() {
+            Err(BadToken("A recurrence rule may not have both UNTIL and COUNT".to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn matches(&self, first: NaiveDate, date: NaiveDate) -> bool {
+        let interval = self.interval as i64;
+        match self.freq {
+            Frequency::Daily => (date - first).num_days() % interval == 0,
+            Frequency::Weekly => {
+                let week_start = |d: NaiveDate| d - Duration::days(d.weekday().num_days_from_monday() as i64);
+                let weeks = (week_start(date) - week_start(first)).num_days() / 7;
+                let day_ok = if self.by_day.len() == 0 {
+                    date.weekday() == first.weekday()
+                } else {
+                    self.by_day.contains(&date.weekday())
+                };
+                day_ok && weeks % interval == 0
+            },
+            Frequency::Monthly => {
+                let months = (date.year() - first.year()) as i64 * 12
+                    + date.month() as i64 - first.month() as i64;
+                let day_ok = if self.by_month_day.len() == 0 {
+                    date.day() == first.day()
+                } else {
+                    self.by_month_day.contains(&date.day())
+                };
+                day_ok && months % interval == 0
+            }
+        }
+    }
+
+    /// The wall clock start times of every occurrence between `from` and `to`
+    /// inclusive, for a rule anchored at `dtstart`. Exception dates still count
+    /// towards `COUNT`, as in iCalendar.
+    pub fn occurrences(&self, dtstart: NaiveDateTime, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDateTime> {
+        let first = dtstart.date();
+        let last = match self.until {
+            Some(until) if until < to => until,
+            _ => to
+        };
+
+        let mut res = Vec::new();
+        let mut seen = 0;
+        let mut date = first;
+        while date <= last {
+            if date == first || self.matches(first, date) {
+                seen += 1;
+                if let Some(count) = self.count {
+                    if seen > count {
+                        break
+                    }
+                }
+                if date >= from && !self.exdates.contains(&date) {
+                    res.push(date.and_time(dtstart.time()));
+                }
+            }
+            date = date.succ();
+        }
+        res
+    }
+
+    /// Expands the rule into concrete airings in `tz`. Wall clock times skipped
+    /// by a daylight saving transition are moved forward by an hour, and
+    /// repeated ones resolve to their first instance.
+    pub fn expand_in<Tz: TimeZone>(&self, tz: &Tz, dtstart: NaiveDateTime,
+                                   from: NaiveDate, to: NaiveDate) -> Vec<DateTime<Tz>> {
+        self.occurrences(dtstart, from, to).iter().filter_map(|local| resolve_local(tz, local)).collect()
+    }
+
+    /// Expands the rule into concrete airings in the local timezone.
+    pub fn expand(&self, dtstart: NaiveDateTime, from: NaiveDate, to: NaiveDate) -> Vec<DateTime<Local>> {
+        self.expand_in(&Local, dtstart, from, to)
+    }
+}
+
+pub fn resolve_local<Tz: TimeZone>(tz: &Tz, local: &NaiveDateTime) -> Option<DateTime<Tz>> {
+    match tz.from_local_datetime(local) {
+        LocalResult::Single(x) => Some(x),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest),
+        LocalResult::None => match tz.from_local_datetime(&(*local + Duration::hours(1))) {
+            LocalResult::Single(x) => Some(x),
+            LocalResult::Ambiguous(earliest, _) => Some(earliest),
+            LocalResult::None => None
+        }
+    }
+}
+
+#[test]
+fn rrule_round_trip() {
+    let text = "FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR;UNTIL=20150601;EXDATE=20150525";
+    let rule = text.parse::<Recurrence>().unwrap();
+    assert_eq!(rule.to_string(), text);
+    assert!("FREQ=DAILY;UNTIL=20150601;COUNT=3".parse::<Recurrence>().is_err());
+    assert!("BYDAY=MO".parse::<Recurrence>().is_err());
+    assert!("FREQ=WEEKLY;BYDAY=XX".parse::<Recurrence>().is_err());
+}
+
+#[test]
+fn weekday_expansion() {
+    let start = NaiveDate::from_ymd(2015, 5, 18).and_hms(18, 0, 0);
+    let rule = Recurrence::weekly(vec!(Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri))
+        .until(NaiveDate::from_ymd(2015, 6, 1))
+        .except(NaiveDate::from_ymd(2015, 5, 25));
+    let airings = rule.occurrences(start, NaiveDate::from_ymd(2015, 5, 1), NaiveDate::from_ymd(2015, 12, 31));
+
+    // Two weeks of weekdays plus June 1st, without Memorial Day.
+    assert_eq!(airings.len(), 10);
+    assert_eq!(airings[0], start);
+    assert!(airings.iter().all(|x| x.weekday() != Weekday::Sat && x.weekday() != Weekday::Sun));
+    assert!(!airings.iter().any(|x| x.date() == NaiveDate::from_ymd(2015, 5, 25)));
+    assert_eq!(airings[9].date(), NaiveDate::from_ymd(2015, 6, 1));
+}
+
+#[test]
+fn count_and_monthly_expansion() {
+    let start = NaiveDate::from_ymd(2015, 1, 31).and_hms(20, 0, 0);
+    let rule = Recurrence::monthly(Vec::new()).count(4);
+    let airings = rule.occurrences(start, NaiveDate::from_ymd(2015, 1, 1), NaiveDate::from_ymd(2016, 1, 1));
+
+    // Months without a 31st are skipped rather than moved.
+    let dates = airings.iter().map(|x| x.date()).collect::<Vec<NaiveDate>>();
+    assert_eq!(dates, vec!(NaiveDate::from_ymd(2015, 1, 31), NaiveDate::from_ymd(2015, 3, 31),
+                           NaiveDate::from_ymd(2015, 5, 31), NaiveDate::from_ymd(2015, 7, 31)));
+}
+
+#[test]
+fn dst_transitions() {
+    use self::chrono::{FixedOffset, Timelike, UTC};
+
+    /// New York time in 2015: daylight saving time starts at 02:00 on
+    /// 2015-03-08 and ends at 02:00 on 2015-11-01.
+    #[derive(Clone)]
+    struct Eastern;
+
+    impl TimeZone for Eastern {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Eastern {
+            Eastern
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms(12, 0, 0))
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let (est, edt) = (FixedOffset::west(5 * 3600), FixedOffset::west(4 * 3600));
+            let spring = NaiveDate::from_ymd(2015, 3, 8).and_hms(2, 0, 0);
+            let fall = NaiveDate::from_ymd(2015, 11, 1).and_hms(1, 0, 0);
+            if *local >= spring && *local < spring + Duration::hours(1) {
+                LocalResult::None
+            } else if *local >= fall && *local < fall + Duration::hours(1) {
+                LocalResult::Ambiguous(edt, est)
+            } else if *local > spring && *local < fall {
+                LocalResult::Single(edt)
+            } else {
+                LocalResult::Single(est)
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms(12, 0, 0))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            if *utc >= NaiveDate::from_ymd(2015, 3, 8).and_hms(7, 0, 0)
+                && *utc < NaiveDate::from_ymd(2015, 11, 1).and_hms(6, 0, 0) {
+                FixedOffset::west(4 * 3600)
+            } else {
+                FixedOffset::west(5 * 3600)
+            }
+        }
+    }
+
+    let evening = NaiveDate::from_ymd(2015, 3, 6).and_hms(18, 0, 0);
+    let airings = Recurrence::daily().count(4)
+        .expand_in(&Eastern, evening, NaiveDate::from_ymd(2015, 3, 1), NaiveDate::from_ymd(2015, 3, 31));
+    assert_eq!(airings.len(), 4);
+    assert!(airings.iter().all(|x| x.hour() == 18));
+    assert_eq!(airings[0].with_timezone(&UTC).hour(), 23);
+    assert_eq!(airings[3].with_timezone(&UTC).hour(), 22);
+
+    // 02:30 does not exist on the day the clocks go forward.
+    let night = NaiveDate::from_ymd(2015, 3, 7).and_hms(2, 30, 0);
+    let airings = Recurrence::daily().count(3)
+        .expand_in(&Eastern, night, NaiveDate::from_ymd(2015, 3, 1), NaiveDate::from_ymd(2015, 3, 31));
+    assert_eq!(airings.len(), 3);
+    assert_eq!((airings[1].hour(), airings[1].minute()), (3, 30));
+    assert_eq!((airings[2].hour(), airings[2].minute()), (2, 30));
+
+    // 01:30 happens twice on the day the clocks go back; the first one is used.
+    let early = NaiveDate::from_ymd(2015, 11, 1).and_hms(1, 30, 0);
+    let airings = Recurrence::daily().count(1)
+        .expand_in(&Eastern, early, NaiveDate::from_ymd(2015, 11, 1), NaiveDate::from_ymd(2015, 11, 1));
+    assert_eq!(airings[0].with_timezone(&UTC).hour(), 5);
+}
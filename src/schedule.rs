@@ -1,16 +1,24 @@
 use std::fmt;
 use super::program::Program;
+use super::tags::Tags;
+use super::path::ProgramPath;
+use super::error::{self, Error};
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Schedule {
     programs: Vec<Program>,
     name: String,
+    /// Tags every program inherits unless it sets its own.
+    defaults: Tags,
     current_program: Option<usize>
 }
 
 impl fmt::Display for Schedule {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         try!(write!(fmt, "(schedule \"{}\" ", self.name));
+        if self.defaults != Tags::new() {
+            try!(write!(fmt, "(defaults {})", self.defaults));
+        }
         for program in self.programs.iter() {
             try!(write!(fmt, "{}", program));
         }
@@ -28,6 +36,7 @@ impl<'a> Schedule {
         Schedule { 
             name: nom.to_string(), 
             programs: progs,
+            defaults: Tags::new(),
             current_program: current
         }
     }
@@ -36,6 +45,7 @@ impl<'a> Schedule {
         Schedule {
             name: "Example".to_string(),
             programs: vec!(Program::example()),
+            defaults: Tags::new(),
             current_program: Some(0)
         }
     }
@@ -51,13 +61,35 @@ impl<'a> Schedule {
     pub fn name_ref(&'a self) -> &'a str {
         &self.name
     }
+
+    pub fn defaults(&'a self) -> &'a Tags {
+        &self.defaults
+    }
+
+    pub fn set_defaults(&mut self, tags: &Tags) {
+        self.defaults = tags.clone();
+    }
+
+    /// The tags that apply to the program at `path`: its own, then those it
+    /// inherits from the programs it is inside, then the schedule's defaults.
+    pub fn effective_tags(&self, path: &ProgramPath) -> Option<Tags> {
+        let mut tags = self.defaults.clone();
+        for depth in 1 .. path.depth() + 1 {
+            let prog = match ProgramPath::from_indices(&path.indices()[.. depth]).and_then(|x| x.get(self)) {
+                Some(x) => x,
+                None => return None
+            };
+            tags = prog.get_tags().inherit(&tags);
+        }
+        Some(tags)
+    }
     
     pub fn programs(&self) -> Vec<Program> {
         self.programs.clone()
     }
 
     pub fn last_program(&'a self) -> Option<&'a Program> {
-        self.programs.get(self.programs.len() - 1)
+        self.programs.last()
     }
 
     pub fn add_program(&mut self, prog: &Program) {
@@ -68,24 +100,32 @@ impl<'a> Schedule {
         self.programs.pop()
     }
 
-    pub fn delete_program(&mut self, idx: usize) -> Result<(), String> {
-        if idx > self.programs.len() - 1 {
-            Err("Index is out of bounds.".to_string())
-        } else {
-            self.programs.remove(idx);
-            Ok(())
-        }
+    pub fn delete_program(&mut self, idx: usize) -> error::Result<()> {
+        try!(error::check_index(idx, self.programs.len()));
+        self.programs.remove(idx);
+        Ok(())
     }
 
-    pub fn insert_program(&mut self, idx: usize, prog: &Program) -> Result<(), String> {
-        if idx > self.programs.len() - 1 {
-            Err("Index is out of bounds.".to_string())
+    /// Inserts `prog` before the program at `idx`, or at the end when `idx`
+    /// is the number of programs.
+    pub fn insert_program(&mut self, idx: usize, prog: &Program) -> error::Result<()> {
+        if idx > self.programs.len() {
+            Err(Error::OutOfBounds(idx, self.programs.len()))
         } else {
             self.programs.insert(idx, prog.clone());
             Ok(())
         }
     }
 
+    /// Moves the program at `from` so that it ends up at `to`.
+    pub fn move_program(&mut self, from: usize, to: usize) -> error::Result<()> {
+        try!(error::check_index(from, self.programs.len()));
+        try!(error::check_index(to, self.programs.len()));
+        let prog = self.programs.remove(from);
+        self.programs.insert(to, prog);
+        Ok(())
+    }
+
     pub fn get_program_mut_at(&'a mut self, idx: usize) -> Option<&'a mut Program> {
         self.programs.get_mut(idx)
     }
@@ -110,6 +150,25 @@ impl<'a> Schedule {
         }
     }
 
+    /// The total running time of the schedule, in seconds.
+    pub fn running_time(&self) -> usize {
+        self.programs.iter().fold(0, |total, prog| total + prog.running_time())
+    }
+
+    /// Finds the program playing `offset` seconds after the schedule starts,
+    /// along with its index.
+    pub fn program_at_offset(&'a self, offset: usize) -> Option<(usize, &'a Program)> {
+        let mut start = 0;
+        for (idx, prog) in self.programs.iter().enumerate() {
+            let end = start + prog.running_time();
+            if offset < end {
+                return Some((idx, prog))
+            }
+            start = end;
+        }
+        None
+    }
+
     pub fn programs_len(&self) -> usize {
         self.programs.len()
     }
@@ -118,13 +177,10 @@ impl<'a> Schedule {
         self.current_program
     }
 
-    pub fn set_current_program_idx(&mut self, idx: usize) -> Result<(), String> {
-        if idx > self.programs.len() {
-            Err("Out of bounds".to_string())
-        } else {
-            self.current_program = Some(idx);
-            Ok(())
-        }
+    pub fn set_current_program_idx(&mut self, idx: usize) -> error::Result<()> {
+        try!(error::check_index(idx, self.programs.len()));
+        self.current_program = Some(idx);
+        Ok(())
     }
 
     pub fn next_prog(&mut self) {
@@ -160,3 +216,29 @@ impl<'a> Schedule {
         }
     }
 }   
+
+#[test]
+fn inherited_tags() {
+    use super::rating::Rating;
+    use super::parse;
+
+    let sched = parse::parse("(schedule \"night\" (defaults :rating \"TV-PG\" :copyright \"XBTV\")
+ (program (local \"ep1\") (tags :title \"Episode 1\" :series \"Fate/stay night\" :rating \"TV-14\")
+  (instr (program (local \"trailer\") (instr (play)))
+         (program (local \"ad\") (tags :noinherit (list \"Series\" \"rating\" \"©\")) (instr (play))))))")
+        .unwrap();
+
+    let trailer = sched.effective_tags(&ProgramPath::new(0).child(0)).unwrap();
+    assert_eq!(trailer.series, Some("Fate/stay night".to_string()));
+    assert_eq!(trailer.rating, Some("TV-14".parse::<Rating>().unwrap()));
+    assert_eq!(trailer.copyright, Some("XBTV".to_string()));
+    assert_eq!(trailer.title, None);
+
+    let ad = sched.effective_tags(&ProgramPath::new(0).child(1)).unwrap();
+    assert_eq!(ad.series, None);
+    assert_eq!(ad.rating, None);
+    assert_eq!(ad.copyright, None);
+    assert!(sched.effective_tags(&ProgramPath::new(1)).is_none());
+
+    assert_eq!(parse::parse(&sched.to_string()), Ok(sched.clone()));
+}
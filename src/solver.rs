@@ -0,0 +1,266 @@
+//! Builds schedules automatically from a catalog of programs and a set of
+//! constraints.
+
+extern crate chrono;
+
+use std::fmt;
+use self::chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use super::schedule::Schedule;
+use super::program::Program;
+use super::channel::DATETIME_FORMAT;
+use super::rating::Rating;
+
+/// The most programs the solver will try placing before it gives up.
+pub static SEARCH_LIMIT: usize = 100000;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Constraint {
+    /// Programs at the level of this rating or above may not start before
+    /// this time of day.
+    RatingNotBefore(Rating, NaiveTime),
+    /// Two programs of the same series must start at least this many seconds
+    /// apart.
+    SeriesSpacing(usize),
+    /// Only programs of this genre may start between these times of day.
+    GenreWindow(String, NaiveTime, NaiveTime),
+    /// Every program must run between these many seconds.
+    SlotLength(usize, usize)
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Constraint::RatingNotBefore(ref rating, time) => {
+                try!(write!(fmt, "no rating {} before {}", rating, time.format("%H:%M")))
+            },
+            Constraint::SeriesSpacing(secs) => {
+                try!(write!(fmt, "no series twice within {} minutes", secs / 60))
+            },
+            Constraint::GenreWindow(ref genre, start, end) => {
+                try!(write!(fmt, "only {} from {} to {}", genre, start.format("%H:%M"), end.format("%H:%M")))
+            },
+            Constraint::SlotLength(min, max) => {
+                try!(write!(fmt, "each slot between {} and {} minutes", min / 60, max / 60))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn in_window(time: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        start <= time || time < end
+    }
+}
+
+impl Constraint {
+    /// Whether `prog` may start at `time`, given the programs already placed
+    /// and the times they start.
+    pub fn allows(&self, prog: &Program, time: NaiveDateTime, placed: &[(NaiveDateTime, &Program)]) -> bool {
+        let tags = prog.get_tags();
+        match *self {
+            Constraint::RatingNotBefore(ref rating, not_before) => {
+                match (tags.rating.as_ref().and_then(|x| x.level()), rating.level()) {
+                    (Some(x), Some(y)) if x >= y => time.time() >= not_before,
+                    _ => true
+                }
+            },
+            Constraint::SeriesSpacing(secs) => match tags.series {
+                Some(ref series) => !placed.iter().any(|&(start, other)| {
+                    other.get_tags().series.as_ref() == Some(series)
+                        && (time - start).num_seconds() < secs as i64
+                }),
+                None => true
+            },
+            Constraint::GenreWindow(ref genre, start, end) => {
+                !in_window(time.time(), start, end) || tags.genres().contains(genre)
+            },
+            Constraint::SlotLength(min, max) => {
+                let length = prog.running_time();
+                min <= length && length <= max
+            }
+        }
+    }
+}
+
+/// Why the solver could not build a schedule.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Unsatisfiable {
+    /// The furthest point the solver reached.
+    pub at: NaiveDateTime,
+    /// The constraint that ruled out the most programs at that point, if the
+    /// catalog was not simply empty.
+    pub constraint: Option<Constraint>
+}
+
+impl fmt::Display for Unsatisfiable {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.constraint {
+            Some(ref x) => try!(write!(fmt, "Could not satisfy \"{}\" at {}", x, self.at.format(DATETIME_FORMAT))),
+            None => try!(write!(fmt, "No programs available at {}", self.at.format(DATETIME_FORMAT)))
+        }
+        Ok(())
+    }
+}
+
+pub struct Solver {
+    catalog: Vec<Program>,
+    constraints: Vec<Constraint>
+}
+
+struct Search<'a> {
+    solver: &'a Solver,
+    end: NaiveDateTime,
+    steps: usize,
+    furthest: NaiveDateTime,
+    rejections: Vec<usize>
+}
+
+impl<'a> Search<'a> {
+    fn place(&mut self, time: NaiveDateTime, placed: &mut Vec<(NaiveDateTime, &'a Program)>) -> bool {
+        if time >= self.end {
+            return true
+        }
+
+        let mut rejections = vec!(0; self.solver.constraints.len());
+        let mut candidates = Vec::new();
+        for prog in self.solver.catalog.iter().filter(|x| x.running_time() > 0) {
+            match self.solver.constraints.iter().position(|c| !c.allows(prog, time, placed)) {
+                Some(idx) => rejections[idx] += 1,
+                None => candidates.push(prog)
+            }
+        }
+
+        if time >= self.furthest {
+            self.furthest = time;
+            self.rejections = rejections;
+        }
+
+        // Try the programs that have gone longest without airing first.
+        candidates.sort_by(|x, y| {
+            let last = |prog: &Program| placed.iter().rposition(|&(_, other)| other == prog);
+            last(*x).cmp(&last(*y))
+        });
+
+        for prog in candidates.into_iter() {
+            if self.steps == SEARCH_LIMIT {
+                return false
+            }
+            self.steps += 1;
+
+            placed.push((time, prog));
+            if self.place(time + Duration::seconds(prog.running_time() as i64), placed) {
+                return true
+            }
+            placed.pop();
+        }
+        false
+    }
+}
+
+impl<'a> Solver {
+    pub fn new(catalog: Vec<Program>, constraints: Vec<Constraint>) -> Solver {
+        Solver {
+            catalog: catalog,
+            constraints: constraints
+        }
+    }
+
+    pub fn catalog(&'a self) -> &'a Vec<Program> {
+        &self.catalog
+    }
+
+    pub fn constraints(&'a self) -> &'a Vec<Constraint> {
+        &self.constraints
+    }
+
+    pub fn add_constraint(&mut self, constraint: Constraint) {
+        self.constraints.push(constraint);
+    }
+
+    /// Builds a schedule that plays from `start` until at least `end`. The
+    /// last program may run past `end`.
+    pub fn solve(&self, name: &str, start: NaiveDateTime, end: NaiveDateTime) -> Result<Schedule, Unsatisfiable> {
+        let mut search = Search {
+            solver: self,
+            end: end,
+            steps: 0,
+            furthest: start,
+            rejections: Vec::new()
+        };
+        let mut placed = Vec::new();
+
+        if search.place(start, &mut placed) {
+            Ok(Schedule::new(name, placed.into_iter().map(|(_, prog)| prog.clone()).collect()))
+        } else {
+            let worst = search.rejections.iter().enumerate()
+                .fold(None, |worst: Option<(usize, usize)>, (idx, &count)| match worst {
+                    Some((_, most)) if most >= count => worst,
+                    _ if count > 0 => Some((idx, count)),
+                    _ => worst
+                }).map(|(idx, _)| self.constraints[idx].clone());
+            Err(Unsatisfiable { at: search.furthest, constraint: worst })
+        }
+    }
+
+    /// Builds one schedule per day for `days` days, each playing from `from`
+    /// to `to` in the time of day.
+    pub fn solve_days(&self, name: &str, first: NaiveDate, days: usize, from: NaiveTime, to: NaiveTime)
+                      -> Result<Vec<Schedule>, Unsatisfiable> {
+        let mut scheds = Vec::new();
+        for day in 0 .. days {
+            let date = first + Duration::days(day as i64);
+            let start = date.and_time(from);
+            let end = if to > from { date.and_time(to) } else { date.succ().and_time(to) };
+            let sched_name = format!("{}-{}", name, date.format("%Y-%m-%d"));
+            scheds.push(try!(self.solve(&sched_name, start, end)));
+        }
+        Ok(scheds)
+    }
+}
+
+#[test]
+fn solve_evening() {
+    use super::parse;
+
+    let catalog = parse::parse("(schedule \"catalog\"
+ (program (local \"fsn-01\") (tags :series \"Fate/stay night\" :genre \"anime\" :rating \"TV-14\" :runtime \"30m\")
+  (instr (play 00:00:00 00:00:00)))
+ (program (local \"fsn-02\") (tags :series \"Fate/stay night\" :genre \"anime\" :rating \"TV-14\" :runtime \"30m\")
+  (instr (play 00:00:00 00:00:00)))
+ (program (local \"movie\") (tags :genre \"drama\" :rating \"R\" :runtime \"100m\")
+  (instr (play 00:00:00 00:00:00)))
+ (program (local \"ghibli\") (tags :series \"Ghibli Shorts\" :genre \"kids\" :rating \"G\" :runtime \"30m\")
+  (instr (play 00:00:00 00:00:00)))
+ (program (local \"horror\") (tags :genre \"horror\" :rating \"R\" :runtime \"30m\")
+  (instr (play 00:00:00 00:00:00)))
+ (program (local \"sitcom\") (tags :series \"Sitcom\" :genre \"comedy\" :rating \"TV-PG\" :runtime \"29m\")
+  (instr (play 00:00:00 00:00:00)))
+ (program (local \"cartoon\") (tags :series \"Cartoon\" :genre \"kids\" :rating \"TV-Y\" :runtime \"28m\")
+  (instr (play 00:00:00 00:00:00))))").unwrap().programs();
+
+    let constraints = vec!(
+        Constraint::RatingNotBefore("R".parse::<Rating>().unwrap(), NaiveTime::from_hms(21, 0, 0)),
+        Constraint::SeriesSpacing(4 * 3600),
+        Constraint::GenreWindow("kids".to_string(), NaiveTime::from_hms(7, 0, 0), NaiveTime::from_hms(10, 0, 0)),
+        Constraint::SlotLength(28 * 60, 31 * 60));
+    let solver = Solver::new(catalog, constraints);
+
+    let day = NaiveDate::from_ymd(2015, 6, 1);
+    let sched = solver.solve("evening", day.and_hms(20, 0, 0), day.and_hms(22, 0, 0)).unwrap();
+    let mut time = day.and_hms(20, 0, 0);
+    let mut placed = Vec::new();
+    for prog in sched.programs().iter() {
+        assert!(solver.constraints().iter().all(|c| c.allows(prog, time, &placed)));
+        placed.push((time, prog));
+        time = time + Duration::seconds(prog.running_time() as i64);
+    }
+    assert!(time >= day.and_hms(22, 0, 0));
+
+    // Only two kids' programs and no kids' series may repeat within 4 hours.
+    let err = solver.solve("morning", day.and_hms(7, 0, 0), day.and_hms(10, 0, 0)).unwrap_err();
+    assert_eq!(err.constraint, Some(Constraint::GenreWindow("kids".to_string(), NaiveTime::from_hms(7, 0, 0),
+                                                            NaiveTime::from_hms(10, 0, 0))));
+}
@@ -0,0 +1,160 @@
+//! Writing schedules to disk safely: atomic replacement, rotating backups and
+//! noticing when someone else has changed a file.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hasher, SipHasher};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use super::schedule::Schedule;
+use super::parse;
+use super::error;
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// The `n`th backup of `path`, 1 being the newest.
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".bak.{}", n))
+}
+
+/// Reads and parses the schedule saved at `path`.
+pub fn read_schedule(path: &Path) -> error::Result<Schedule> {
+    let mut s = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut s));
+    Ok(try!(parse::parse(&s)))
+}
+
+/// Writes `contents` to a temporary file beside `path` and renames it over
+/// `path`, so the file holds either the old contents or the new, never a mix.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let tmp = with_suffix(path, ".tmp");
+    {
+        let mut file = try!(File::create(&tmp));
+        try!(file.write_all(contents));
+        try!(file.sync_all());
+    }
+    if let Err(f) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(f)
+    }
+    Ok(())
+}
+
+/// Copies `path` to its first backup, moving older backups down and dropping
+/// the one past `count`. Does nothing if `path` does not exist yet.
+pub fn rotate_backups(path: &Path, count: usize) -> Result<(), Error> {
+    if count == 0 || !path.exists() {
+        return Ok(())
+    }
+    if let Err(f) = fs::remove_file(backup_path(path, count)) {
+        if f.kind() != ErrorKind::NotFound {
+            return Err(f)
+        }
+    }
+    for n in (1 .. count).rev() {
+        let older = backup_path(path, n);
+        if older.exists() {
+            try!(fs::rename(&older, backup_path(path, n + 1)));
+        }
+    }
+    try!(fs::copy(path, backup_path(path, 1)));
+    Ok(())
+}
+
+/// What a file looked like when it was last read or written.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileStamp {
+    hash: u64
+}
+
+impl FileStamp {
+    /// Stamps the file at `path`, or gives `None` if there is no such file.
+    pub fn read(path: &Path) -> Result<Option<FileStamp>, Error> {
+        let mut file = match File::open(path) {
+            Ok(x) => x,
+            Err(ref f) if f.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(f) => return Err(f)
+        };
+        let mut contents = Vec::new();
+        try!(file.read_to_end(&mut contents));
+
+        let mut hasher = SipHasher::new();
+        hasher.write(&contents);
+        Ok(Some(FileStamp { hash: hasher.finish() }))
+    }
+
+    /// Whether the file is unchanged since this stamp. Only the contents
+    /// count: a file that was only touched is unchanged, and one rewritten
+    /// within the same tick of the clock, or copied keeping its time as
+    /// `cp -p` and `rsync -t` do, is not.
+    pub fn matches(&self, now: &FileStamp) -> bool {
+        self.hash == now.hash
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(Error),
+    /// The file was changed by something else since the buffer last read or
+    /// wrote it.
+    ChangedOnDisk(PathBuf)
+}
+
+impl From<Error> for SaveError {
+    fn from(err: Error) -> SaveError {
+        SaveError::Io(err)
+    }
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Io(ref f) => try!(write!(fmt, "{}", f)),
+            SaveError::ChangedOnDisk(ref path) => {
+                try!(write!(fmt, "{} was changed by another program since it was opened", path.display()))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn backups_and_stamps() {
+    use std::env;
+
+    let path = env::temp_dir().join("xbtved-storage-test.xbtv");
+    for n in 1 .. 4 {
+        let _ = fs::remove_file(backup_path(&path, n));
+    }
+
+    write_atomic(&path, b"first").unwrap();
+    let stamp = FileStamp::read(&path).unwrap().unwrap();
+    for contents in ["second", "third", "fourth"].iter() {
+        rotate_backups(&path, 2).unwrap();
+        write_atomic(&path, contents.as_bytes()).unwrap();
+    }
+
+    let read = |p: &Path| {
+        let mut s = String::new();
+        File::open(p).unwrap().read_to_string(&mut s).unwrap();
+        s
+    };
+    assert_eq!(read(&path), "fourth");
+    assert_eq!(read(&backup_path(&path, 1)), "third");
+    assert_eq!(read(&backup_path(&path, 2)), "second");
+    assert!(!backup_path(&path, 3).exists());
+    assert!(!with_suffix(&path, ".tmp").exists());
+
+    let now = FileStamp::read(&path).unwrap().unwrap();
+    assert!(!stamp.matches(&now));
+    write_atomic(&path, b"fifth").unwrap();
+    assert!(!now.matches(&FileStamp::read(&path).unwrap().unwrap()));
+    write_atomic(&path, b"fourth").unwrap();
+    assert!(now.matches(&FileStamp::read(&path).unwrap().unwrap()));
+    fs::remove_file(&path).unwrap();
+    assert_eq!(FileStamp::read(&path).unwrap(), None);
+}
@@ -1,11 +1,21 @@
 extern crate chrono;
 
 use std::usize;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::fmt;
 use super::parse::ParseError;
 use super::parse::ParseError::*;
-pub use self::chrono::{/*DateTime, Local, UTC,*/ NaiveDate};
+use super::rating::Rating;
+use super::track::{Track, TrackKind};
+use super::recurrence::resolve_local;
+pub use self::chrono::{DateTime, Local, UTC, NaiveDate};
+use self::chrono::{NaiveDateTime, TimeZone};
+
+/// How date and time tags are written: UTC ends in `Z`, local time gives its
+/// offset, as in `2015-04-03T20:00:00+0900`.
+static DATETIME_UTC_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%SZ";
+static DATETIME_LOCAL_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S%z";
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum VideoType {
@@ -27,7 +37,7 @@ impl fmt::Display for VideoType {
             VideoType::Anime => "Anime",
             VideoType::MusicVideo => "Music Video",
             VideoType::Short => "Short",
-            VideoType::Advert => "Advertisemet"
+            VideoType::Advert => "Advertisement"
         }));
         Ok(())
     }
@@ -86,7 +96,7 @@ impl FromStr for AudioType {
             "Radio" | "radio" => Ok(AudioType::Radio),
             "podcast" | "Podcast" => Ok(AudioType::Podcast),
             "clip" | "Clip" => Ok(AudioType::Clip),
-            "misc" | "Misc" | "miscellaneous" | "Miscellaneous" => Ok(AudioType::Misc),
+            "misc" | "Misc" | "Misc Audio" | "miscellaneous" | "Miscellaneous" => Ok(AudioType::Misc),
             _ =>  Err(())
         }
     }
@@ -120,7 +130,7 @@ impl FromStr for MediaType {
 
 impl fmt::Display for MediaType {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        try!(write!(fmt, "{} ", match *self {
+        try!(write!(fmt, "{}", match *self {
             MediaType::Video(ref x) => x.to_string(),
             MediaType::Audio(ref x) => x.to_string(),
             MediaType::Image => "Image".to_string()
@@ -129,7 +139,7 @@ impl fmt::Display for MediaType {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum TagType {
     Title,
     MediaType,
@@ -150,6 +160,8 @@ pub enum TagType {
 
     Year,
     Airdate,
+    DateTimeUTC,
+    DateTimeLocal,
 
     Comment,
     Summary,
@@ -163,11 +175,25 @@ pub enum TagType {
     Runtime,
     AspectRatio,
     AudioTracks,
-    Subtitles
+    Subtitles,
+
+    /// The tags this program does not inherit from its parent, or `all`.
+    NoInherit,
+
+    /// A tag of our own, written `:x-name`. Holds the name without the `x-`.
+    Custom(String)
+}
+
+/// Whether `name` may follow `x-` in a custom tag.
+fn valid_custom(name: &str) -> bool {
+    name.len() > 0 && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
 }
 
 impl fmt::Display for TagType {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let TagType::Custom(ref name) = *self {
+            return write!(fmt, "x-{}", name)
+        }
         try!(write!(fmt, "{}", match *self {
             TagType::Title => "title",
             TagType::MediaType => "media_type",
@@ -188,6 +214,8 @@ impl fmt::Display for TagType {
 
             TagType::Year => "year",
             TagType::Airdate => "airdate",
+            TagType::DateTimeUTC => "datetimeutc",
+            TagType::DateTimeLocal => "datetimelocal",
 
             TagType::Comment => "comment",
             TagType::Summary => "summary",
@@ -201,12 +229,110 @@ impl fmt::Display for TagType {
             TagType::Runtime => "runtime",
             TagType::AspectRatio => "aspect_ratio",
             TagType::AudioTracks => "audio_tracks",
-            TagType::Subtitles => "subtitles"
+            TagType::Subtitles => "subtitles",
+            TagType::NoInherit => "noinherit",
+            TagType::Custom(_) => unreachable!()
         }));
         Ok(())
     }
 }
 
+impl TagType {
+    /// Every tag, in the order they are written out.
+    pub fn all() -> Vec<TagType> {
+        vec!(
+            TagType::Title,
+            TagType::MediaType,
+            TagType::Genre,
+            TagType::Series,
+            TagType::Season,
+            TagType::Episode,
+            TagType::Album,
+            TagType::TrackNo,
+            TagType::Studio,
+            TagType::Director,
+            TagType::Artist,
+            TagType::Composer,
+            TagType::Cast,
+            TagType::Photographer,
+            TagType::Year,
+            TagType::Airdate,
+            TagType::DateTimeUTC,
+            TagType::DateTimeLocal,
+            TagType::Comment,
+            TagType::Summary,
+            TagType::Country,
+            TagType::Rating,
+            TagType::License,
+            TagType::Copyright,
+            TagType::URL,
+            TagType::Picture,
+            TagType::Runtime,
+            TagType::AspectRatio,
+            TagType::AudioTracks,
+            TagType::Subtitles,
+            TagType::NoInherit)
+    }
+
+    /// Every built in tag, then every custom tag set in any of `tags`.
+    pub fn all_in(tags: &[&Tags]) -> Vec<TagType> {
+        let mut names = tags.iter().flat_map(|x| x.custom.keys()).cloned().collect::<Vec<String>>();
+        names.sort();
+        names.dedup();
+        let mut all = TagType::all();
+        all.extend(names.into_iter().map(TagType::Custom));
+        all
+    }
+
+    /// Whether the tag holds a list of values rather than one.
+    pub fn is_multi(&self) -> bool {
+        match *self {
+            TagType::Genre |
+            TagType::Studio |
+            TagType::Director |
+            TagType::Artist |
+            TagType::Composer |
+            TagType::Cast |
+            TagType::Country |
+            TagType::AudioTracks |
+            TagType::Subtitles |
+            TagType::NoInherit => true,
+            _ => false
+        }
+    }
+
+    /// Whether a program without this tag takes it from the program it is in,
+    /// or from the schedule's defaults. Tags describing one particular piece
+    /// of media, like its title or runtime, are never inherited.
+    pub fn inherits(&self) -> bool {
+        match *self {
+            TagType::MediaType |
+            TagType::Genre |
+            TagType::Series |
+            TagType::Season |
+            TagType::Album |
+            TagType::Studio |
+            TagType::Director |
+            TagType::Artist |
+            TagType::Composer |
+            TagType::Year |
+            TagType::Country |
+            TagType::Rating |
+            TagType::License |
+            TagType::Copyright |
+            TagType::Custom(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_custom(&self) -> bool {
+        match *self {
+            TagType::Custom(_) => true,
+            _ => false
+        }
+    }
+}
+
 impl FromStr for TagType {
     type Err = ();
 
@@ -231,8 +357,8 @@ impl FromStr for TagType {
 
             "year" | "Year" => Ok(TagType::Year),
             "airdate" | "Airdate" => Ok(TagType::Airdate),
-//            "datetimeutc" | "DateTimeUTC" | "datetimeUTC" => Ok(TagType::DTimeUTC),
-//            "datetimelocal" | "DateTimeLocal" => Ok(TagType::DTLocal),
+            "datetimeutc" | "DateTimeUTC" | "datetimeUTC" => Ok(TagType::DateTimeUTC),
+            "datetimelocal" | "DateTimeLocal" => Ok(TagType::DateTimeLocal),
 
             "comment" | "Comment" => Ok(TagType::Comment),
             "summary" | "Summary" => Ok(TagType::Summary),
@@ -247,17 +373,128 @@ impl FromStr for TagType {
             "aspect_ratio" | "AspectRatio" | "Aspect_Ratio" => Ok(TagType::AspectRatio),
             "audio_tracks" | "AudioTracks" | "Audio_Tracks" => Ok(TagType::AudioTracks),
             "subtitles" | "Subtitles" => Ok(TagType::Subtitles),
+            "noinherit" | "NoInherit" => Ok(TagType::NoInherit),
 
+            x if x.starts_with("x-") && valid_custom(&x[2 ..]) => Ok(TagType::Custom(x[2 ..].to_string())),
             _ => Err(())
         }
     }
 }
 
+fn as_slice(values: &Option<Vec<String>>) -> &[String] {
+    match *values {
+        Some(ref x) => x,
+        None => &[]
+    }
+}
+
+/// Fails unless every name is a tag, or `all`.
+fn check_noinherit(names: &Vec<String>) -> Result<(), ParseError> {
+    match names.iter().find(|x| &x[..] != "all" && x.parse::<TagType>().is_err()) {
+        Some(x) => Err(BadToken(format!("noinherit expects tag names, found {}", x))),
+        None => Ok(())
+    }
+}
+
+/// Splits a comma separated list of values, as typed into the editor.
+fn split_list(s: &str) -> Vec<String> {
+    s.split(",").map(|x| x.trim().to_string()).filter(|x| x.len() > 0).collect()
+}
+
+/// An airdate, which may only be known to the month.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PartialDate {
+    Month(i32, u32),
+    Day(NaiveDate)
+}
+
+impl PartialDate {
+    /// A month, if it is one.
+    pub fn month(year: i32, month: u32) -> Option<PartialDate> {
+        NaiveDate::from_ymd_opt(year, month, 1).map(|_| PartialDate::Month(year, month))
+    }
+
+    pub fn first_day(&self) -> NaiveDate {
+        match *self {
+            PartialDate::Month(year, month) => NaiveDate::from_ymd(year, month, 1),
+            PartialDate::Day(date) => date
+        }
+    }
+
+    pub fn last_day(&self) -> NaiveDate {
+        match *self {
+            PartialDate::Month(year, 12) => NaiveDate::from_ymd(year, 12, 31),
+            PartialDate::Month(year, month) => NaiveDate::from_ymd(year, month + 1, 1).pred(),
+            PartialDate::Day(date) => date
+        }
+    }
+}
+
+impl fmt::Display for PartialDate {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PartialDate::Month(year, month) => write!(fmt, "{:04}-{:02}", year, month),
+            PartialDate::Day(date) => write!(fmt, "{}", date)
+        }
+    }
+}
+
+impl FromStr for PartialDate {
+    type Err = ParseError;
+
+    /// Reads `yyyy-mm-dd` or `yyyy-mm`, refusing days that do not exist.
+    fn from_str(s: &str) -> Result<PartialDate, ParseError> {
+        let parts: Vec<&str> = s.split('-').collect();
+        let widths_ok = (parts.len() == 2 || parts.len() == 3) && parts[0].len() == 4
+            && parts.iter().skip(1).all(|x| x.len() == 2)
+            && parts.iter().all(|x| x.chars().all(|c| c.is_digit(10)));
+        if !widths_ok {
+            return Err(BadToken(format!("Expected a date as yyyy-mm-dd or yyyy-mm, found {}", s)))
+        }
+
+        let year = parts[0].parse::<i32>().unwrap();
+        let month = parts[1].parse::<u32>().unwrap();
+        let date = if parts.len() == 2 {
+            PartialDate::month(year, month)
+        } else {
+            NaiveDate::from_ymd_opt(year, month, parts[2].parse::<u32>().unwrap()).map(PartialDate::Day)
+        };
+        date.ok_or(BadToken(format!("There is no such date as {}", s)))
+    }
+}
+
+/// Reads a date and time as written for `datetimeutc` and `datetimelocal`:
+/// ending in `Z` for UTC, in an offset like `+0900`, or with neither, taken
+/// to be in `tz`.
+fn parse_datetime<Tz: TimeZone>(tz: &Tz, s: &str) -> Result<DateTime<Tz>, ParseError> {
+    let naive_format = &DATETIME_UTC_FORMAT[.. DATETIME_UTC_FORMAT.len() - 1];
+    let parsed = if s.ends_with('Z') {
+        NaiveDateTime::parse_from_str(&s[.. s.len() - 1], naive_format).ok().map(|x| tz.from_utc_datetime(&x))
+    } else if let Ok(x) = DateTime::parse_from_str(s, DATETIME_LOCAL_FORMAT) {
+        Some(x.with_timezone(tz))
+    } else {
+        NaiveDateTime::parse_from_str(s, naive_format).ok().and_then(|x| resolve_local(tz, &x))
+    };
+    parsed.ok_or(BadToken(format!("Expected a date and time like 2015-04-03T20:00:00Z, found {}", s)))
+}
+
+fn parse_tracks(values: &[String]) -> Result<Vec<Track>, ParseError> {
+    let mut tracks = Vec::new();
+    for value in values.iter() {
+        tracks.push(try!(value.parse::<Track>()));
+    }
+    Ok(tracks)
+}
+
+fn track_strings(tracks: &[Track]) -> Vec<String> {
+    tracks.iter().map(|x| x.to_string()).collect()
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct Tags {
     pub title: Option<String>,
     pub media_type: Option<MediaType>,
-    pub genre: Option<String>,
+    pub genre: Option<Vec<String>>,
 
     pub series: Option<String>,
     pub season: Option<u8>,
@@ -265,22 +502,22 @@ pub struct Tags {
     pub album: Option<String>,
     pub track_no: Option<u16>,
 
-    pub studio: Option<String>,
-    pub director: Option<String>,
-    pub artist: Option<String>,
-    pub composer: Option<String>,
+    pub studio: Option<Vec<String>>,
+    pub director: Option<Vec<String>>,
+    pub artist: Option<Vec<String>>,
+    pub composer: Option<Vec<String>>,
     pub cast: Option<Vec<String>>,
     pub photographer: Option<String>,
 
     pub year: Option<u16>,
-    pub airdate: Option<NaiveDate>,
-//    pub datetimeutc: Option<DateTime<UTC>>,
-//    pub datetimelocal: Option<DateTime<Local>>,
+    pub airdate: Option<PartialDate>,
+    pub datetimeutc: Option<DateTime<UTC>>,
+    pub datetimelocal: Option<DateTime<Local>>,
 
     pub comment: Option<String>,
     pub summary: Option<String>,
-    pub country: Option<String>,
-    pub rating: Option<String>,
+    pub country: Option<Vec<String>>,
+    pub rating: Option<Rating>,
     pub license: Option<String>,
     pub copyright: Option<String>,
     pub url: Option<String>,
@@ -288,8 +525,13 @@ pub struct Tags {
     pub picture: Option<String>,
     pub runtime: Option<usize>,
     pub aspect_ratio: Option<String>,
-    pub audio_tracks: Option<Vec<String>>, /* Perhaps language instead? */
-    pub subtitles: Option<Vec<String>>,
+    pub audio_tracks: Option<Vec<Track>>,
+    pub subtitles: Option<Vec<Track>>,
+
+    pub noinherit: Option<Vec<String>>,
+
+    /// Custom tags by name, without the `x-`.
+    pub custom: BTreeMap<String, String>
 }
 
 impl Tags {
@@ -315,8 +557,8 @@ impl Tags {
 
             year: None,
             airdate: None,
-//            datetimeutc: None,
-//            datetimelocal: None,
+            datetimeutc: None,
+            datetimelocal: None,
 
             comment: None,
             summary: None,
@@ -330,7 +572,11 @@ impl Tags {
             runtime: None,
             aspect_ratio: None,
             audio_tracks: None,
-            subtitles: None
+            subtitles: None,
+
+            noinherit: None,
+
+            custom: BTreeMap::new()
         }
     }
 
@@ -343,7 +589,7 @@ impl Tags {
                 self.media_type = Some(try!(tagdata.parse::<MediaType>()));
             },
             TagType::Genre => {
-                self.genre = Some(tagdata.to_string());
+                self.genre = Some(split_list(tagdata));
             },
             
             TagType::Series => {
@@ -378,20 +624,19 @@ impl Tags {
             },
 
             TagType::Studio => {
-                self.studio = Some(tagdata.to_string());
+                self.studio = Some(split_list(tagdata));
             },
             TagType::Director => {
-                self.director = Some(tagdata.to_string());
+                self.director = Some(split_list(tagdata));
             },
             TagType::Artist => {
-                self.artist = Some(tagdata.to_string());
+                self.artist = Some(split_list(tagdata));
             },
             TagType::Composer => {
-                self.composer = Some(tagdata.to_string());
+                self.composer = Some(split_list(tagdata));
             },
             TagType::Cast => {
-                let cast: Vec<String> = tagdata.split(",").map(|x: &str| x.to_string()).collect();
-                self.cast = Some(cast);
+                self.cast = Some(split_list(tagdata));
             },
             TagType::Photographer => {
                 self.photographer = Some(tagdata.to_string());
@@ -406,28 +651,13 @@ impl Tags {
                 self.year = Some(year);
             },
             TagType::Airdate => {
-                let ymd_string: Vec<String> = tagdata.split("-").map(|x: &str| x.to_string()).collect();
-                if ymd_string.len() != 3 || ymd_string[0].len() != 4 || ymd_string[1].len() != 2 
-                    || ymd_string[2].len() != 2
-                {
-                    return Err(BadToken("Input dates as yyyy-mm-dd".to_string()))
-                }
-
-                let year = match ymd_string[0].parse::<i32>() {
-                    Ok(x) => x,
-                    Err(_) => return Err(BadToken(format!("{}{}","Expected year, found ",ymd_string[0])))
-                };
-                let month = match ymd_string[1].parse::<u32>() {
-                    Ok(x) => x,
-                    Err(_) => return Err(BadToken(format!("{}{}","Expected month, found ",ymd_string[1])))
-                };
-                let day = match ymd_string[2].parse::<u32>() {
-                    Ok(x) => x,
-                    Err(_) => return Err(BadToken(format!("{}{}","Expected month, found ",ymd_string[1])))
-                };
-
-                let ymd = NaiveDate::from_ymd(year, month, day);
-                self.airdate = Some(ymd);
+                self.airdate = Some(try!(tagdata.parse::<PartialDate>()));
+            },
+            TagType::DateTimeUTC => {
+                self.datetimeutc = Some(try!(parse_datetime(&UTC, tagdata)));
+            },
+            TagType::DateTimeLocal => {
+                self.datetimelocal = Some(try!(parse_datetime(&Local, tagdata)));
             },
 
 
@@ -438,10 +668,10 @@ impl Tags {
                 self.summary = Some(tagdata.to_string());
             },
             TagType::Country => {
-                self.country = Some(tagdata.to_string());
+                self.country = Some(split_list(tagdata));
             },
             TagType::Rating => {
-                self.rating = Some(tagdata.to_string());
+                self.rating = Some(try!(tagdata.parse::<Rating>()));
             },
             TagType::License => {
                 self.license = Some(tagdata.to_string());
@@ -484,42 +714,320 @@ impl Tags {
                 self.aspect_ratio = Some(tagdata.to_string());
             },
             TagType::AudioTracks => {
-                let tracks: Vec<String> = tagdata.split(",").map(|x: &str| x.to_string()).collect();
-                self.audio_tracks = Some(tracks);
+                self.audio_tracks = Some(try!(parse_tracks(&split_list(tagdata))));
             },
             TagType::Subtitles => {
-                let subtitles: Vec<String> = tagdata.split(",").map(|x: &str| x.to_string()).collect();
-                self.subtitles = Some(subtitles);
+                self.subtitles = Some(try!(parse_tracks(&split_list(tagdata))));
+            },
+
+            TagType::NoInherit => {
+                let names = split_list(tagdata);
+                try!(check_noinherit(&names));
+                self.noinherit = Some(names);
+            },
+
+            TagType::Custom(ref name) => {
+                self.custom.insert(name.clone(), tagdata.to_string());
             }
         }
         Ok(())
     }
 
+    /// The value of a tag as text, with lists separated by commas.
+    pub fn get_tag(&self, tagtype: &TagType) -> Option<String> {
+        match *tagtype {
+            TagType::Title => self.title.as_ref().map(|x| x.to_string()),
+            TagType::MediaType => self.media_type.as_ref().map(|x| x.to_string()),
+            TagType::Genre => self.genre.as_ref().map(|x| x.join(", ")),
+            TagType::Series => self.series.as_ref().map(|x| x.to_string()),
+            TagType::Season => self.season.as_ref().map(|x| x.to_string()),
+            TagType::Episode => self.episode.as_ref().map(|x| x.to_string()),
+            TagType::Album => self.album.as_ref().map(|x| x.to_string()),
+            TagType::TrackNo => self.track_no.as_ref().map(|x| x.to_string()),
+            TagType::Studio => self.studio.as_ref().map(|x| x.join(", ")),
+            TagType::Director => self.director.as_ref().map(|x| x.join(", ")),
+            TagType::Artist => self.artist.as_ref().map(|x| x.join(", ")),
+            TagType::Composer => self.composer.as_ref().map(|x| x.join(", ")),
+            TagType::Cast => self.cast.as_ref().map(|x| x.join(", ")),
+            TagType::Photographer => self.photographer.as_ref().map(|x| x.to_string()),
+            TagType::Year => self.year.as_ref().map(|x| x.to_string()),
+            TagType::Airdate => self.airdate.as_ref().map(|x| x.to_string()),
+            TagType::DateTimeUTC => self.datetimeutc.as_ref().map(|x| x.format(DATETIME_UTC_FORMAT).to_string()),
+            TagType::DateTimeLocal => self.datetimelocal.as_ref().map(|x| x.format(DATETIME_LOCAL_FORMAT).to_string()),
+            TagType::Comment => self.comment.as_ref().map(|x| x.to_string()),
+            TagType::Summary => self.summary.as_ref().map(|x| x.to_string()),
+            TagType::Country => self.country.as_ref().map(|x| x.join(", ")),
+            TagType::Rating => self.rating.as_ref().map(|x| x.to_string()),
+            TagType::License => self.license.as_ref().map(|x| x.to_string()),
+            TagType::Copyright => self.copyright.as_ref().map(|x| x.to_string()),
+            TagType::URL => self.url.as_ref().map(|x| x.to_string()),
+            TagType::Picture => self.picture.as_ref().map(|x| x.to_string()),
+            TagType::Runtime => self.runtime.map(|x| format!("{}s", x)),
+            TagType::AspectRatio => self.aspect_ratio.as_ref().map(|x| x.to_string()),
+            TagType::AudioTracks => self.audio_tracks.as_ref().map(|x| track_strings(x).join(", ")),
+            TagType::Subtitles => self.subtitles.as_ref().map(|x| track_strings(x).join(", ")),
+            TagType::NoInherit => self.noinherit.as_ref().map(|x| x.join(", ")),
+            TagType::Custom(ref name) => self.custom.get(name).cloned()
+        }
+    }
+
+    /// Sets one tag to its value in `other`, including unsetting it.
+    pub fn copy_tag(&mut self, other: &Tags, tagtype: &TagType) {
+        match *tagtype {
+            TagType::Title => self.title = other.title.clone(),
+            TagType::MediaType => self.media_type = other.media_type.clone(),
+            TagType::Genre => self.genre = other.genre.clone(),
+            TagType::Series => self.series = other.series.clone(),
+            TagType::Season => self.season = other.season.clone(),
+            TagType::Episode => self.episode = other.episode.clone(),
+            TagType::Album => self.album = other.album.clone(),
+            TagType::TrackNo => self.track_no = other.track_no.clone(),
+            TagType::Studio => self.studio = other.studio.clone(),
+            TagType::Director => self.director = other.director.clone(),
+            TagType::Artist => self.artist = other.artist.clone(),
+            TagType::Composer => self.composer = other.composer.clone(),
+            TagType::Cast => self.cast = other.cast.clone(),
+            TagType::Photographer => self.photographer = other.photographer.clone(),
+            TagType::Year => self.year = other.year.clone(),
+            TagType::Airdate => self.airdate = other.airdate.clone(),
+            TagType::DateTimeUTC => self.datetimeutc = other.datetimeutc.clone(),
+            TagType::DateTimeLocal => self.datetimelocal = other.datetimelocal.clone(),
+            TagType::Comment => self.comment = other.comment.clone(),
+            TagType::Summary => self.summary = other.summary.clone(),
+            TagType::Country => self.country = other.country.clone(),
+            TagType::Rating => self.rating = other.rating.clone(),
+            TagType::License => self.license = other.license.clone(),
+            TagType::Copyright => self.copyright = other.copyright.clone(),
+            TagType::URL => self.url = other.url.clone(),
+            TagType::Picture => self.picture = other.picture.clone(),
+            TagType::Runtime => self.runtime = other.runtime.clone(),
+            TagType::AspectRatio => self.aspect_ratio = other.aspect_ratio.clone(),
+            TagType::AudioTracks => self.audio_tracks = other.audio_tracks.clone(),
+            TagType::Subtitles => self.subtitles = other.subtitles.clone(),
+            TagType::NoInherit => self.noinherit = other.noinherit.clone(),
+            TagType::Custom(ref name) => match other.custom.get(name) {
+                Some(x) => { self.custom.insert(name.clone(), x.clone()); },
+                None => { self.custom.remove(name); }
+            }
+        }
+    }
+
+    /// Unsets a tag.
+    pub fn remove_tag(&mut self, tagtype: &TagType) {
+        self.copy_tag(&Tags::new(), tagtype);
+    }
+
     pub fn modify_multi(&mut self, tagdata: &Vec<String>, tagtype: TagType) -> Result<(), ParseError> {
-        match tagtype {
-            TagType::Cast => self.cast = Some(tagdata.clone()),
-            TagType::AudioTracks => self.audio_tracks = Some(tagdata.clone()),
-            TagType::Subtitles => self.subtitles = Some(tagdata.clone()),
-            _ => return Err(BadToken("Attempting to modify tags which do not take a vector of strings".to_string()))
+        if tagtype == TagType::NoInherit {
+            try!(check_noinherit(tagdata));
+        }
+        if let Some(field) = self.tracks_mut(&tagtype) {
+            *field = Some(try!(parse_tracks(tagdata)));
+            return Ok(())
+        }
+        match self.values_mut(&tagtype) {
+            Some(field) => *field = Some(tagdata.clone()),
+            None => return Err(BadToken("Attempting to modify tags which do not take a vector of strings".to_string()))
         }
         Ok(())
     }
+
+    /// The values of a tag that holds several as text, none when it is
+    /// unset, or `None` for other tags.
+    pub fn values(&self, tagtype: &TagType) -> Option<Vec<String>> {
+        let values = match *tagtype {
+            TagType::Genre => &self.genre,
+            TagType::Studio => &self.studio,
+            TagType::Director => &self.director,
+            TagType::Artist => &self.artist,
+            TagType::Composer => &self.composer,
+            TagType::Cast => &self.cast,
+            TagType::Country => &self.country,
+            TagType::AudioTracks => return Some(track_strings(self.tracks(TrackKind::Audio))),
+            TagType::Subtitles => return Some(track_strings(self.tracks(TrackKind::Subtitle))),
+            TagType::NoInherit => &self.noinherit,
+            _ => return None
+        };
+        Some(as_slice(values).to_vec())
+    }
+
+    /// The audio tracks or subtitles described for this program's media.
+    pub fn tracks(&self, kind: TrackKind) -> &[Track] {
+        let tracks = match kind {
+            TrackKind::Audio => &self.audio_tracks,
+            TrackKind::Subtitle => &self.subtitles
+        };
+        match *tracks {
+            Some(ref x) => x,
+            None => &[]
+        }
+    }
+
+    fn tracks_mut(&mut self, tagtype: &TagType) -> Option<&mut Option<Vec<Track>>> {
+        match *tagtype {
+            TagType::AudioTracks => Some(&mut self.audio_tracks),
+            TagType::Subtitles => Some(&mut self.subtitles),
+            _ => None
+        }
+    }
+
+    fn values_mut(&mut self, tagtype: &TagType) -> Option<&mut Option<Vec<String>>> {
+        match *tagtype {
+            TagType::Genre => Some(&mut self.genre),
+            TagType::Studio => Some(&mut self.studio),
+            TagType::Director => Some(&mut self.director),
+            TagType::Artist => Some(&mut self.artist),
+            TagType::Composer => Some(&mut self.composer),
+            TagType::Cast => Some(&mut self.cast),
+            TagType::Country => Some(&mut self.country),
+            TagType::NoInherit => Some(&mut self.noinherit),
+            _ => None
+        }
+    }
+
+    /// Whether a tag holding several values includes `value`.
+    pub fn has_value(&self, tagtype: &TagType, value: &str) -> bool {
+        match self.values(tagtype) {
+            Some(x) => x.iter().any(|y| y == value),
+            None => false
+        }
+    }
+
+    /// Adds `value` to a tag holding several values, unless it is there
+    /// already.
+    pub fn add_value(&mut self, tagtype: &TagType, value: &str) -> Result<(), ParseError> {
+        if *tagtype == TagType::NoInherit {
+            try!(check_noinherit(&vec!(value.to_string())));
+        }
+        if let Some(field) = self.tracks_mut(tagtype) {
+            let track = try!(value.parse::<Track>());
+            let mut tracks = field.take().unwrap_or(Vec::new());
+            if !tracks.contains(&track) {
+                tracks.push(track);
+            }
+            *field = Some(tracks);
+            return Ok(())
+        }
+        match self.values_mut(tagtype) {
+            Some(field) => {
+                if field.is_none() {
+                    *field = Some(Vec::new());
+                }
+                if let Some(ref mut values) = *field {
+                    if !values.iter().any(|x| x == value) {
+                        values.push(value.to_string());
+                    }
+                }
+                Ok(())
+            },
+            None => Err(BadToken(format!("The {} tag holds a single value", tagtype)))
+        }
+    }
+
+    /// Removes `value` from a tag holding several values, unsetting the tag
+    /// when it was the last one.
+    pub fn remove_value(&mut self, tagtype: &TagType, value: &str) -> Result<(), ParseError> {
+        if let Some(field) = self.tracks_mut(tagtype) {
+            let track = try!(value.parse::<Track>());
+            let tracks = field.take().unwrap_or(Vec::new()).into_iter().filter(|x| *x != track).collect::<Vec<Track>>();
+            if tracks.len() > 0 {
+                *field = Some(tracks);
+            }
+            return Ok(())
+        }
+        match self.values_mut(tagtype) {
+            Some(field) => {
+                let empty = match *field {
+                    Some(ref mut values) => {
+                        values.retain(|x| x != value);
+                        values.len() == 0
+                    },
+                    None => false
+                };
+                if empty {
+                    *field = None;
+                }
+                Ok(())
+            },
+            None => Err(BadToken(format!("The {} tag holds a single value", tagtype)))
+        }
+    }
+
+    /// Whether this program refuses to inherit `tagtype`. Names are compared
+    /// as tags, so any of a tag's spellings blocks it.
+    pub fn blocks(&self, tagtype: &TagType) -> bool {
+        match self.noinherit {
+            Some(ref names) => names.iter().any(|x| &x[..] == "all" || x.parse::<TagType>().ok().as_ref() == Some(tagtype)),
+            None => false
+        }
+    }
+
+    /// These tags as they apply to a program inside one with the tags
+    /// `parent`, which should already be effective themselves. A tag set here
+    /// overrides the parent's; a tag left unset takes the parent's value,
+    /// unless it is not one that inherits or `noinherit` names it.
+    pub fn inherit(&self, parent: &Tags) -> Tags {
+        let mut tags = self.clone();
+        for tag in TagType::all_in(&[parent]).into_iter() {
+            if tag.inherits() && self.get_tag(&tag).is_none() && !self.blocks(&tag) {
+                tags.copy_tag(parent, &tag);
+            }
+        }
+        tags
+    }
+
+    /// Copies each tag set in `other` but not here, leaving the tags already
+    /// set alone. Gives the tags it filled in.
+    pub fn fill_from(&mut self, other: &Tags) -> Vec<TagType> {
+        let mut filled = Vec::new();
+        for tag in TagType::all_in(&[other]).into_iter() {
+            if self.get_tag(&tag).is_none() && other.get_tag(&tag).is_some() {
+                self.copy_tag(other, &tag);
+                filled.push(tag);
+            }
+        }
+        filled
+    }
+
+    pub fn genres(&self) -> &[String] {
+        as_slice(&self.genre)
+    }
+
+    pub fn studios(&self) -> &[String] {
+        as_slice(&self.studio)
+    }
+
+    pub fn directors(&self) -> &[String] {
+        as_slice(&self.director)
+    }
+
+    pub fn artists(&self) -> &[String] {
+        as_slice(&self.artist)
+    }
+
+    pub fn composers(&self) -> &[String] {
+        as_slice(&self.composer)
+    }
+
+    pub fn countries(&self) -> &[String] {
+        as_slice(&self.country)
+    }
 }
 
 macro_rules! opt_display {
     ($fmt: ident, $self_: ident, $field:ident, $tag:expr) => (match $self_.$field {
-        Some(ref val) => { try!(write!($fmt, "{}", format!("{}=\"{}\"", $tag, val.clone()))); }
+        Some(ref val) => { try!(write!($fmt, ":{} \"{}\" ", $tag, val)); }
         None => { }
     })
 }
 
 macro_rules! opt_display_vec {
     ($fmt: ident, $self_: ident, $field:ident, $tag:expr) => (match $self_.$field {
-        Some(ref val) => { try!(write!($fmt, "{}", format!("{}= \" ", $tag)));
+        Some(ref val) => { try!(write!($fmt, ":{} (list", $tag));
                            for member in val.iter() {
-                               try!(write!($fmt, "{}", member.clone()));
+                               try!(write!($fmt, " \"{}\"", member));
                            }
-                           try!(write!($fmt,"\")"));
+                           try!(write!($fmt, ") "));
         },
         None => { }
     })
@@ -527,42 +1035,80 @@ macro_rules! opt_display_vec {
 
 impl fmt::Display for Tags {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        opt_display!(fmt, self, title, "title");
-        opt_display!(fmt, self, media_type, "mediatype");
-        opt_display!(fmt, self, genre, "genre");
-
-        opt_display!(fmt, self, series, "series");
-        opt_display!(fmt, self, season, "season");
-        opt_display!(fmt, self, episode, "episode");
-        opt_display!(fmt, self, album, "album");
-        opt_display!(fmt, self, track_no, "trackno");
-
-        opt_display!(fmt, self, studio, "studio");
-        opt_display!(fmt, self, director,"director");
-        opt_display!(fmt, self, artist, "artist");
-        opt_display!(fmt, self, composer, "composer");
-        opt_display_vec!(fmt, self, cast, "cast");
-        opt_display!(fmt, self, photographer, "photographer");
-
-        opt_display!(fmt, self, year, "year");
-        opt_display!(fmt, self, airdate, "airdate");
-//        opt_display!(fmt, self, datetimeutc, "datetimeutc");
-//        opt_display!(fmt, self, datetimelocal, "datetimelocal");
-
-        opt_display!(fmt, self, comment, "comment");
-        opt_display!(fmt, self, summary, "summary");
-        opt_display!(fmt, self, country, "country");
-        opt_display!(fmt, self, rating, "rating");
-        opt_display!(fmt, self, license, "license");
-        opt_display!(fmt, self, copyright, "copyright");
-        opt_display!(fmt, self, url, "url");
-
-        opt_display!(fmt, self, picture, "picture");
-        opt_display!(fmt, self, runtime, "runtime");
-        opt_display!(fmt, self, aspect_ratio, "aspectratio");
-        opt_display_vec!(fmt, self, audio_tracks, "audiotracks");
-        opt_display_vec!(fmt, self, subtitles, "subtitles");
+        opt_display!(fmt, self, title, TagType::Title);
+        opt_display!(fmt, self, media_type, TagType::MediaType);
+        opt_display_vec!(fmt, self, genre, TagType::Genre);
+
+        opt_display!(fmt, self, series, TagType::Series);
+        opt_display!(fmt, self, season, TagType::Season);
+        opt_display!(fmt, self, episode, TagType::Episode);
+        opt_display!(fmt, self, album, TagType::Album);
+        opt_display!(fmt, self, track_no, TagType::TrackNo);
+
+        opt_display_vec!(fmt, self, studio, TagType::Studio);
+        opt_display_vec!(fmt, self, director, TagType::Director);
+        opt_display_vec!(fmt, self, artist, TagType::Artist);
+        opt_display_vec!(fmt, self, composer, TagType::Composer);
+        opt_display_vec!(fmt, self, cast, TagType::Cast);
+        opt_display!(fmt, self, photographer, TagType::Photographer);
+
+        opt_display!(fmt, self, year, TagType::Year);
+        opt_display!(fmt, self, airdate, TagType::Airdate);
+        if let Some(ref when) = self.datetimeutc {
+            try!(write!(fmt, ":{} \"{}\" ", TagType::DateTimeUTC, when.format(DATETIME_UTC_FORMAT)));
+        }
+        if let Some(ref when) = self.datetimelocal {
+            try!(write!(fmt, ":{} \"{}\" ", TagType::DateTimeLocal, when.format(DATETIME_LOCAL_FORMAT)));
+        }
+
+        opt_display!(fmt, self, comment, TagType::Comment);
+        opt_display!(fmt, self, summary, TagType::Summary);
+        opt_display_vec!(fmt, self, country, TagType::Country);
+        opt_display!(fmt, self, rating, TagType::Rating);
+        opt_display!(fmt, self, license, TagType::License);
+        opt_display!(fmt, self, copyright, TagType::Copyright);
+        opt_display!(fmt, self, url, TagType::URL);
+
+        opt_display!(fmt, self, picture, TagType::Picture);
+        if let Some(runtime) = self.runtime {
+            try!(write!(fmt, ":{} \"{}s\" ", TagType::Runtime, runtime));
+        }
+        opt_display!(fmt, self, aspect_ratio, TagType::AspectRatio);
+        opt_display_vec!(fmt, self, audio_tracks, TagType::AudioTracks);
+        opt_display_vec!(fmt, self, subtitles, TagType::Subtitles);
+        opt_display_vec!(fmt, self, noinherit, TagType::NoInherit);
+
+        for (name, val) in self.custom.iter() {
+            try!(write!(fmt, ":{} \"{}\" ", TagType::Custom(name.clone()), val));
+        }
 
         Ok(())
     }
 }
+
+#[test]
+fn dates_and_times() {
+    let mut tags = Tags::new();
+    assert!(tags.modify_tag(&TagType::Airdate, "2014-02-30").is_err());
+    assert!(tags.modify_tag(&TagType::Airdate, "2014-2-03").is_err());
+    tags.modify_tag(&TagType::Airdate, "2014-02").unwrap();
+    assert_eq!(tags.airdate.unwrap().last_day(), NaiveDate::from_ymd(2014, 2, 28));
+
+    tags.modify_tag(&TagType::DateTimeUTC, "2015-04-03T11:00:00Z").unwrap();
+    tags.modify_tag(&TagType::DateTimeLocal, "2015-04-03T20:00:00+0900").unwrap();
+    assert_eq!(tags.datetimelocal.as_ref().map(|x| x.with_timezone(&UTC)), tags.datetimeutc);
+
+    let text = format!("(schedule \"dates\" (program (local \"a.webm\") (tags {}) (instr (play))))", tags);
+    let sched = super::parse::parse(&text).unwrap();
+    let read = sched.get_program_at(0).unwrap().get_tags();
+    assert_eq!(read.get_tag(&TagType::Airdate), Some("2014-02".to_string()));
+    assert_eq!(read.get_tag(&TagType::DateTimeUTC), Some("2015-04-03T11:00:00Z".to_string()));
+    assert_eq!(read.datetimelocal, tags.datetimelocal);
+}
+
+#[test]
+fn list_tags() {
+    let mut tags = Tags::new();
+    tags.modify_tag(&TagType::Cast, "Noriaki Sugiyama, Kana Ueda,, ").unwrap();
+    assert_eq!(tags.cast, Some(vec!("Noriaki Sugiyama".to_string(), "Kana Ueda".to_string())));
+}
@@ -41,7 +41,7 @@ pub fn test() {
 
     assert_eq!(test2,test4);
 
-    test4.modify_program(0).unwrap().tags.director=Some("John Wayne".to_string());
+    test4.modify_program(0).unwrap().tags.director=Some(vec!("John Wayne".to_string()));
 
     println!("{}", test4);
 
@@ -0,0 +1,354 @@
+//! The audio tracks and subtitles a program's media holds, and the choice of
+//! which of them plays.
+//!
+//! A track is written in the `audio_tracks` and `subtitles` tags as its
+//! fields separated by semicolons, the language first:
+//!
+//! ```text
+//! :subtitles (list "en; codec=ass; label=Signs; forced" "ja; file=~/htpc/Videos/fsn.ja.srt")
+//! ```
+//!
+//! Languages are BCP 47 tags such as `en`, `pt-BR` or `zh-Hant-TW`. Text written
+//! before these tags described tracks, such as `CC` or `Commentary; director`,
+//! is kept whole as the label: without a `codec=`, `label=` or other such
+//! field, a value is only read as fields when it starts with an ISO 639 code.
+
+use std::fmt;
+use std::str::FromStr;
+use super::parse::ParseError;
+use super::parse::ParseError::BadToken;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrackKind {
+    Audio,
+    Subtitle
+}
+
+impl fmt::Display for TrackKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", match *self {
+            TrackKind::Audio => "audio",
+            TrackKind::Subtitle => "subtitle"
+        })
+    }
+}
+
+/// The ISO 639-1 codes, and the ISO 639-2 codes of the same languages along
+/// with those for undetermined, multiple and no language.
+const ISO_639: &'static str = "\
+    aa ab ae af ak am an ar as av ay az ba be bg bh bi bm bn bo br bs ca ce ch co cr cs cu cv cy da de dv dz \
+    ee el en eo es et eu fa ff fi fj fo fr fy ga gd gl gn gu gv ha he hi ho hr ht hu hy hz ia id ie ig ii ik \
+    io is it iu ja jv ka kg ki kj kk kl km kn ko kr ks ku kv kw ky la lb lg li ln lo lt lu lv mg mh mi mk ml \
+    mn mr ms mt my na nb nd ne ng nl nn no nr nv ny oc oj om or os pa pi pl ps pt qu rm rn ro ru rw sa sc sd \
+    se sg si sk sl sm sn so sq sr ss st su sv sw ta te tg th ti tk tl tn to tr ts tt tw ty ug uk ur uz ve vi \
+    vo wa wo xh yi yo za zh zu \
+    aar abk ave afr aka amh arg ara asm ava aym aze bak bel bul bih bis bam ben bod tib bre bos cat che cha \
+    cos cre ces cze chu chv cym wel dan deu ger div dzo ewe ell gre eng epo spa est eus baq fas per ful fin \
+    fij fao fra fre fry gle gla glg grn guj glv hau heb hin hmo hrv hat hun hye arm her ina ind ile ibo iii \
+    ipk ido isl ice ita iku jpn jav kat geo kon kik kua kaz kal khm kan kor kau kas kur kom cor kir lat ltz \
+    lug lim lin lao lit lub lav mlg mah mri mao mkd mac mal mon mar msa may mlt mya bur nau nob nde nep ndo \
+    nld dut nno nor nbl nav nya oci oji orm ori oss pan pli pol pus por que roh run ron rum rus kin san srd \
+    snd sme sag sin slk slo slv smo sna som sqi alb srp ssw sot sun swe swa tam tel tgk tha tir tuk tgl tsn \
+    ton tur tso tat twi tah uig ukr urd uzb ven vie vol wln wol xho yid yor zha zho chi zul fil und mul zxx";
+
+/// The fields a track is written with as `key=value`.
+const KEYS: [&'static str; 5] = ["language", "lang", "codec", "label", "file"];
+
+fn letters(s: &str) -> bool {
+    s.chars().all(|c| c >= 'a' && c <= 'z')
+}
+
+fn digits(s: &str) -> bool {
+    s.chars().all(|c| c >= '0' && c <= '9')
+}
+
+/// Checks `s` against the syntax of a BCP 47 language tag, giving it with the
+/// usual case: `zh-hant-tw` becomes `zh-Hant-TW`. Grandfathered tags and
+/// primary languages of more than three letters are not accepted.
+pub fn language_tag(s: &str) -> Option<String> {
+    let subtags = s.split('-').map(|x| x.to_lowercase()).collect::<Vec<String>>();
+    if subtags.iter().any(|x| x.len() == 0 || x.len() > 8 || !x.chars().all(|c| (c >= 'a' && c <= 'z') || (c >= '0' && c <= '9'))) {
+        return None
+    }
+
+    let n = subtags.len();
+    let mut tag = Vec::new();
+    let mut i = 0;
+    if subtags[0] != "x" {
+        if !(letters(&subtags[0]) && subtags[0].len() >= 2 && subtags[0].len() <= 3) {
+            return None
+        }
+        tag.push(subtags[0].clone());
+        i = 1;
+
+        let mut extlangs = 0;
+        while i < n && extlangs < 3 && subtags[i].len() == 3 && letters(&subtags[i]) {
+            tag.push(subtags[i].clone());
+            extlangs += 1;
+            i += 1;
+        }
+        if i < n && subtags[i].len() == 4 && letters(&subtags[i]) {
+            tag.push(format!("{}{}", subtags[i][.. 1].to_uppercase(), &subtags[i][1 ..]));
+            i += 1;
+        }
+        if i < n && ((subtags[i].len() == 2 && letters(&subtags[i])) || (subtags[i].len() == 3 && digits(&subtags[i]))) {
+            tag.push(subtags[i].to_uppercase());
+            i += 1;
+        }
+        while i < n && (subtags[i].len() >= 5 || (subtags[i].len() == 4 && digits(&subtags[i][.. 1]))) {
+            tag.push(subtags[i].clone());
+            i += 1;
+        }
+        // Extensions: a singleton other than `x`, then subtags of two or more.
+        while i < n && subtags[i].len() == 1 && subtags[i] != "x" {
+            tag.push(subtags[i].clone());
+            i += 1;
+            let start = i;
+            while i < n && subtags[i].len() >= 2 {
+                tag.push(subtags[i].clone());
+                i += 1;
+            }
+            if i == start {
+                return None
+            }
+        }
+    }
+    if i < n && subtags[i] == "x" {
+        if i + 1 == n {
+            return None
+        }
+        tag.extend(subtags[i ..].iter().cloned());
+        i = n;
+    }
+
+    if i < n { None } else { Some(tag.join("-")) }
+}
+
+/// Whether `s` is a language tag whose language has an ISO 639 code, as `en`,
+/// `pt-BR` and `jpn` do but `cc` and `sdh` do not.
+fn known_language(s: &str) -> bool {
+    match language_tag(s) {
+        Some(tag) => {
+            let primary = tag.split('-').next().unwrap_or("").to_string();
+            ISO_639.split_whitespace().any(|x| x == primary)
+        },
+        None => false
+    }
+}
+
+fn is_keyed(field: &str) -> bool {
+    let mut parts = field.splitn(2, '=');
+    let key = parts.next().unwrap().trim();
+    parts.next().is_some() && KEYS.contains(&key)
+}
+
+/// One audio track or subtitle of a program's media, or for subtitles, a
+/// file beside it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Track {
+    /// A BCP 47 language tag, as `language_tag` gives it.
+    pub language: Option<String>,
+    pub codec: Option<String>,
+    pub label: Option<String>,
+    /// Whether the track plays when none is chosen.
+    pub default: bool,
+    /// Whether the subtitle shows even when subtitles are off, as for signs
+    /// or foreign dialogue.
+    pub forced: bool,
+    /// Where an external subtitle file is.
+    pub file: Option<String>
+}
+
+impl Track {
+    pub fn new() -> Track {
+        Track {
+            language: None,
+            codec: None,
+            label: None,
+            default: false,
+            forced: false,
+            file: None
+        }
+    }
+
+    /// Whether the track is in `language` or a more specific form of it, as
+    /// `en-GB` is of `en`.
+    pub fn is_in(&self, language: &str) -> bool {
+        match self.language {
+            Some(ref x) => x.to_lowercase() == language.to_lowercase()
+                || x.to_lowercase().starts_with(&format!("{}-", language.to_lowercase())),
+            None => false
+        }
+    }
+}
+
+impl FromStr for Track {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Track, ParseError> {
+        let mut track = Track::new();
+        let fields = s.split(';').map(|x| x.trim()).filter(|x| x.len() > 0).collect::<Vec<&str>>();
+        if fields.len() == 0 {
+            return Err(BadToken("A track needs a language or a label".to_string()))
+        }
+        let flags = fields[1 ..].iter().all(|x| *x == "default" || *x == "forced");
+        if !fields.iter().any(|x| is_keyed(x)) && !(known_language(fields[0]) && flags) {
+            track.label = Some(s.trim().to_string());
+            return Ok(track)
+        }
+
+        for (i, field) in fields.iter().enumerate() {
+            let mut parts = field.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            match (key, parts.next().map(|x| x.trim().to_string())) {
+                ("language", Some(x)) | ("lang", Some(x)) => match language_tag(&x) {
+                    Some(tag) => track.language = Some(tag),
+                    None => return Err(BadToken(format!("{} is not a BCP 47 language tag", x)))
+                },
+                ("codec", Some(x)) => track.codec = Some(x),
+                ("label", Some(x)) => track.label = Some(x),
+                ("file", Some(x)) => track.file = Some(x),
+                ("default", None) => track.default = true,
+                ("forced", None) => track.forced = true,
+                (x, None) if i == 0 => match language_tag(x) {
+                    Some(tag) => track.language = Some(tag),
+                    None => track.label = Some(x.to_string())
+                },
+                _ => return Err(BadToken(format!("Expected language, codec, label, file, default or forced \
+                                                  in a track, found {}", field)))
+            }
+        }
+        Ok(track)
+    }
+}
+
+/// A track with only a label that reads back as itself, as text from before
+/// tracks were described does, is written as the bare label.
+impl fmt::Display for Track {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref x) = self.label {
+            if x.parse::<Track>().ok().as_ref() == Some(self) {
+                return write!(fmt, "{}", x)
+            }
+        }
+        let mut fields = Vec::new();
+        if let Some(ref x) = self.language {
+            fields.push(x.clone());
+        }
+        if let Some(ref x) = self.codec {
+            fields.push(format!("codec={}", x));
+        }
+        if let Some(ref x) = self.label {
+            fields.push(format!("label={}", x));
+        }
+        if let Some(ref x) = self.file {
+            fields.push(format!("file={}", x));
+        }
+        if self.default {
+            fields.push("default".to_string());
+        }
+        if self.forced {
+            fields.push("forced".to_string());
+        }
+        write!(fmt, "{}", fields.join("; "))
+    }
+}
+
+/// Which track an `audio` or `subtitle` instruction picks out of those the
+/// program's tags describe.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TrackSelector {
+    /// The nth track, counting from 1 as players do.
+    Index(usize),
+    /// The first track in a language.
+    Language(String),
+    /// No subtitles, other than forced ones.
+    Off
+}
+
+impl TrackSelector {
+    pub fn parse(kind: TrackKind, s: &str) -> Result<TrackSelector, ParseError> {
+        let s = s.trim();
+        if s == "off" {
+            return match kind {
+                TrackKind::Subtitle => Ok(TrackSelector::Off),
+                TrackKind::Audio => Err(BadToken("Audio cannot be turned off".to_string()))
+            }
+        }
+        if s.len() > 0 && digits(s) {
+            return match s.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(TrackSelector::Index(n)),
+                _ => Err(BadToken(format!("Tracks are counted from 1, found {}", s)))
+            }
+        }
+        match language_tag(s) {
+            Some(tag) => Ok(TrackSelector::Language(tag)),
+            None => Err(BadToken(format!("Expected a track number, a language or off, found {}", s)))
+        }
+    }
+
+    /// The track chosen from `tracks`. When subtitles are off, that is a
+    /// forced subtitle in `language`, the language of the audio, if any.
+    pub fn select<'a>(&self, tracks: &'a [Track], language: Option<&str>) -> Option<&'a Track> {
+        match *self {
+            TrackSelector::Index(n) => n.checked_sub(1).and_then(|i| tracks.get(i)),
+            TrackSelector::Language(ref x) => tracks.iter().find(|t| t.is_in(x)),
+            TrackSelector::Off => tracks.iter().find(|t| t.forced && language.map_or(true, |x| t.is_in(x)))
+        }
+    }
+}
+
+impl fmt::Display for TrackSelector {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrackSelector::Index(n) => write!(fmt, "{}", n),
+            TrackSelector::Language(ref x) => write!(fmt, "{}", x),
+            TrackSelector::Off => write!(fmt, "off")
+        }
+    }
+}
+
+#[test]
+fn track_descriptors() {
+    use super::parse;
+    use super::tags::TagType;
+
+    assert_eq!(language_tag("zh-hant-tw"), Some("zh-Hant-TW".to_string()));
+    assert_eq!(language_tag("es-419"), Some("es-419".to_string()));
+    assert_eq!(language_tag("en-x-pirate"), Some("en-x-pirate".to_string()));
+    assert_eq!(language_tag("English"), None);
+    assert_eq!(language_tag("en-"), None);
+
+    let track = "ja-jp; codec=opus; label=Commentary; default".parse::<Track>().unwrap();
+    assert_eq!(track.language, Some("ja-JP".to_string()));
+    assert_eq!(track.to_string(), "ja-JP; codec=opus; label=Commentary; default");
+    assert_eq!("English".parse::<Track>().unwrap().label, Some("English".to_string()));
+    assert_eq!("eng; forced".parse::<Track>().unwrap().language, Some("eng".to_string()));
+    assert!("en; codec=opus; bitrate=128".parse::<Track>().is_err());
+
+    // Values from before tracks were described.
+    for text in ["CC", "SDH", "Dub", "Sub", "en; bitrate=128", "Commentary; director", "Cut = extended"].iter() {
+        let track = text.parse::<Track>().unwrap();
+        assert_eq!(track.language, None);
+        assert_eq!(track.label, Some(text.to_string()));
+        assert_eq!(track.to_string(), *text);
+    }
+    assert_eq!("Commentary; codec=opus".parse::<Track>().unwrap().label, Some("Commentary".to_string()));
+
+    let text = "(schedule \"subs\" (program (local \"fsn.mkv\")
+ (tags :audio_tracks (list \"ja; default\" \"en\")
+       :subtitles (list \"en; forced; label=Signs\" \"en; file=fsn.en.srt\"))
+ (instr (play 00:00:00 00:10:00) (audio \"en\") (subtitle \"off\") (play 00:10:00 00:00:00))))";
+    let sched = parse::parse(text).unwrap();
+    assert_eq!(parse::parse(&sched.to_string()), Ok(sched.clone()));
+
+    let prog = sched.get_program_at(0).unwrap();
+    assert_eq!(prog.get_tags().tracks(TrackKind::Subtitle)[1].file, Some("fsn.en.srt".to_string()));
+    assert!(prog.get_tags().get_tag(&TagType::AudioTracks).unwrap().starts_with("ja; default"));
+    assert!(prog.selected_track(TrackKind::Audio, 0).unwrap().is_in("ja"));
+    assert!(prog.selected_track(TrackKind::Subtitle, 0).is_none());
+    assert!(prog.selected_track(TrackKind::Audio, 3).unwrap().is_in("en"));
+    assert_eq!(prog.selected_track(TrackKind::Subtitle, 3).unwrap().label, Some("Signs".to_string()));
+    assert!(TrackSelector::Index(0).select(prog.get_tags().tracks(TrackKind::Audio), None).is_none());
+    assert!(parse::parse(&text.replace("(audio \"en\")", "(audio \"off\")")).is_err());
+}